@@ -0,0 +1,73 @@
+use rand::rngs::SmallRng;
+use rand::RngCore;
+use rand::SeedableRng;
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+
+const NUM_PIECES: usize = 12; // Empty is not counted
+const NUM_SQUARES: usize = 64;
+/// The seed used for the Zobrist keys unless ARIMAA_ZOBRIST_SEED is set
+const DEFAULT_SEED: u64 = 0xDEADBEEF;
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=ARIMAA_ZOBRIST_SEED");
+    let seed = match env::var("ARIMAA_ZOBRIST_SEED") {
+        Ok(s) => parse_seed(&s).unwrap_or_else(|| panic!("Invalid ARIMAA_ZOBRIST_SEED {:?}", s)),
+        Err(_) => DEFAULT_SEED,
+    };
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let mut f = File::create(Path::new(&out_dir).join("table_zobrist.rs")).unwrap();
+    write_zobrist(&mut f, seed);
+}
+
+/// Accepts the seed in decimal or as 0x prefixed hexadecimal
+fn parse_seed(s: &str) -> Option<u64> {
+    let s = s.trim();
+    if s.starts_with("0x") || s.starts_with("0X") {
+        u64::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+fn write_zobrist(f: &mut File, seed: u64) {
+    let mut rng = SmallRng::seed_from_u64(seed);
+
+    writeln!(f, "/// Autogenerated by build.rs").unwrap();
+
+    write!(f, "pub const ZOBRIST_SEED: u64 = {};\n\n", seed).unwrap();
+
+    write!(
+        f,
+        "const SIDE_TO_MOVE: [u64; 2] = [{}, {}];\n\n",
+        rng.next_u64(),
+        rng.next_u64()
+    )
+    .unwrap();
+
+    write!(
+        f,
+        "const STEPS_LEFT: [u64; 4] = [{}, {}, {}, {}];\n\n",
+        rng.next_u64(),
+        rng.next_u64(),
+        rng.next_u64(),
+        rng.next_u64()
+    )
+    .unwrap();
+
+    for name in &["ZOBRIST_PIECES", "PENDING_STEP"] {
+        writeln!(f, "const {}: [[u64; NUM_SQUARES]; NUM_PIECES] = [[", name).unwrap();
+        for j in 0..NUM_PIECES {
+            for _ in 0..NUM_SQUARES {
+                writeln!(f, "    {},", rng.next_u64()).unwrap();
+            }
+            if j != NUM_PIECES - 1 {
+                writeln!(f, "   ], [").unwrap();
+            }
+        }
+        write!(f, "]];\n\n").unwrap();
+    }
+}
@@ -0,0 +1,313 @@
+use crate::game::{Game, Move};
+use crate::position::{EndState, Position, Side, Step, Symmetry, NUM_STEP_INDICES};
+use failure::{ensure, format_err, Error};
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"ARBK";
+const VERSION: u8 = 3;
+
+/// A turn found in the book, oriented for the probed position
+#[derive(Clone, Debug)]
+pub struct BookMove {
+    pub steps: Move,
+    /// The number of games in which the turn was played
+    pub count: u32,
+    /// The mean result for the side playing the turn, from 0 for a loss to 1
+    /// for a win, with unfinished games counting as draws
+    pub score: f64,
+}
+
+/// A turn as stored, with its steps as dense indices in the orientation of
+/// the book's key
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    steps: Vec<u16>,
+    count: u32,
+    /// Wins count two and unfinished games one
+    half_points: u32,
+    /// The hash of the position the turn reaches, so that turns reaching the
+    /// same position can be merged
+    reached: u64,
+}
+
+/// Turns played after setup in a collection of games. Positions are keyed by
+/// their hash at the start of a turn, taking the smaller of the hashes of the
+/// position and its left-right mirror so that mirrored games share entries.
+///
+/// On disk a book is a four byte magic number and a version byte followed by
+/// the number of positions (u32). Each position is its key (u64) and number
+/// of turns (u16), then each turn as its count and half points (u32 each),
+/// the hash of the position it reaches (u64), its number of steps (u8) and
+/// each step as its dense index (u16). Captures are left out and integers are
+/// little endian.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OpeningBook {
+    entries: HashMap<u64, Vec<Entry>>,
+}
+
+/// The book key of a turn-start position and whether it is taken from the
+/// mirrored position
+fn book_key(pos: &Position) -> (u64, bool) {
+    let mirrored = pos.mirror().current_hash;
+    if mirrored < pos.current_hash {
+        (mirrored, true)
+    } else {
+        (pos.current_hash, false)
+    }
+}
+
+/// Plays steps given as dense indices from `pos`, returning them as steps.
+/// Returns `None` unless every step is legal and only the last step may end
+/// the turn or the game, since a hash collision or a corrupt book can hold
+/// any indices.
+fn resolve(pos: &Position, indices: &[u16]) -> Option<Vec<Step>> {
+    let mut pos = pos.clone();
+    let side = pos.side;
+    let mut steps = Vec::with_capacity(indices.len());
+    for (i, index) in indices.iter().enumerate() {
+        let step = Step::from_index(*index as usize, &pos)?;
+        if !pos.gen_steps().contains(&step) {
+            return None;
+        }
+        let status = pos.do_step(step);
+        steps.push(step);
+        let ended = status != EndState::Neither || pos.side != side;
+        if ended && i + 1 < indices.len() {
+            return None;
+        }
+    }
+    Some(steps)
+}
+
+/// The dense indices of the steps of `mov` played from `pos`, mirrored if
+/// `mirrored` is set, or `None` if a step has no index
+fn turn_indices(pos: &Position, mov: &Move, mirrored: bool) -> Option<Vec<u16>> {
+    let mut indices = Vec::with_capacity(mov.steps.len());
+    let mut replay = pos.clone();
+    for step in mov.steps.iter() {
+        let step = if mirrored {
+            Symmetry::Mirror.step(*step)
+        } else {
+            *step
+        };
+        if let Step::Remove(_, _) = step {
+            continue;
+        }
+        indices.push(step.to_index(&replay)? as u16);
+        replay.do_step(step);
+    }
+    Some(indices)
+}
+
+impl OpeningBook {
+    pub fn new() -> OpeningBook {
+        OpeningBook::default()
+    }
+    /// Builds a book from the first `max_turns` turns after setup of each
+    /// game, skipping turns the book can't hold
+    pub fn from_games(games: &[Game], max_turns: usize) -> OpeningBook {
+        let mut book = OpeningBook::new();
+        for game in games.iter() {
+            book.add_game(game, max_turns);
+        }
+        book
+    }
+    /// The number of positions in the book
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// Records the first `max_turns` turns after setup of `game`, returning
+    /// the number of turns skipped because the book can't hold them
+    pub fn add_game(&mut self, game: &Game, max_turns: usize) -> usize {
+        let positions = game.positions();
+        let mut skipped = 0;
+        for (pos, mov) in positions.iter().zip(game.moves[2..].iter()).take(max_turns) {
+            let half_points = match (&game.result, pos.side) {
+                (EndState::Neither, _) => 1,
+                (EndState::WhiteWin, Side::White) | (EndState::BlackWin, Side::Black) => 2,
+                _ => 0,
+            };
+            let (key, mirrored) = book_key(pos);
+            let oriented = if mirrored { pos.mirror() } else { pos.clone() };
+            let indices = match turn_indices(&oriented, mov, mirrored) {
+                Some(indices) => indices,
+                None => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+            let reached = match resolve(&oriented, &indices) {
+                Some(steps) => result_hash(&oriented, &steps),
+                None => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+            let entries = self.entries.entry(key).or_default();
+            // Turns reaching the same position are merged
+            let existing = entries.iter_mut().find(|e| e.reached == reached);
+            match existing {
+                Some(entry) => {
+                    entry.count += 1;
+                    entry.half_points += half_points;
+                }
+                None => entries.push(Entry {
+                    steps: indices,
+                    count: 1,
+                    half_points,
+                    reached,
+                }),
+            }
+        }
+        skipped
+    }
+    /// The turns played from `pos`, most frequent first
+    pub fn probe(&self, pos: &Position) -> Vec<BookMove> {
+        let (key, mirrored) = book_key(pos);
+        let oriented = if mirrored { pos.mirror() } else { pos.clone() };
+        let mut moves: Vec<_> = self
+            .entries
+            .get(&key)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let mut steps = resolve(&oriented, &entry.steps)?;
+                if mirrored {
+                    steps = steps
+                        .into_iter()
+                        .map(|s| Symmetry::Mirror.step(s))
+                        .collect();
+                }
+                Some(BookMove {
+                    steps: Move::new(steps),
+                    count: entry.count,
+                    score: f64::from(entry.half_points) / f64::from(2 * entry.count),
+                })
+            })
+            .collect();
+        moves.sort_by_key(|m| std::cmp::Reverse(m.count));
+        moves
+    }
+    /// Picks a book turn at random in proportion to how often it was played,
+    /// ignoring turns played fewer than `min_count` times. The choice depends
+    /// only on the position and `seed`.
+    pub fn choose(&self, pos: &Position, min_count: u32, seed: u64) -> Option<Move> {
+        let moves: Vec<_> = self
+            .probe(pos)
+            .into_iter()
+            .filter(|m| m.count >= min_count.max(1))
+            .collect();
+        let total: u32 = moves.iter().map(|m| m.count).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut rng = SmallRng::seed_from_u64(seed ^ pos.current_hash);
+        let mut choice = rng.gen_range(0, total);
+        for m in moves.into_iter() {
+            if choice < m.count {
+                return Some(m.steps);
+            }
+            choice -= m.count;
+        }
+        unreachable!()
+    }
+    pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        out.write_all(&(self.entries.len() as u32).to_le_bytes())?;
+        let mut keys: Vec<_> = self.entries.keys().collect();
+        keys.sort();
+        for key in keys {
+            let entries = &self.entries[key];
+            out.write_all(&key.to_le_bytes())?;
+            out.write_all(&(entries.len() as u16).to_le_bytes())?;
+            for entry in entries.iter() {
+                out.write_all(&entry.count.to_le_bytes())?;
+                out.write_all(&entry.half_points.to_le_bytes())?;
+                out.write_all(&entry.reached.to_le_bytes())?;
+                out.write_all(&[entry.steps.len() as u8])?;
+                for index in entry.steps.iter() {
+                    out.write_all(&index.to_le_bytes())?;
+                }
+            }
+        }
+        out.flush()
+    }
+    pub fn read<R: Read>(mut input: R) -> Result<OpeningBook, Error> {
+        let mut header = [0; 9];
+        input.read_exact(&mut header)?;
+        ensure!(&header[..4] == MAGIC, format_err!("Not an opening book"));
+        ensure!(
+            header[4] == VERSION,
+            format_err!("Unsupported book version {}", header[4])
+        );
+        let num_positions = u32::from_le_bytes([header[5], header[6], header[7], header[8]]);
+        let mut entries = HashMap::with_capacity(num_positions as usize);
+        for _ in 0..num_positions {
+            let mut head = [0; 10];
+            input.read_exact(&mut head)?;
+            let mut key = [0; 8];
+            key.copy_from_slice(&head[..8]);
+            let num_moves = u16::from_le_bytes([head[8], head[9]]);
+            let mut moves = Vec::with_capacity(num_moves as usize);
+            for _ in 0..num_moves {
+                let mut fields = [0; 17];
+                input.read_exact(&mut fields)?;
+                let count = u32::from_le_bytes([fields[0], fields[1], fields[2], fields[3]]);
+                let half_points = u32::from_le_bytes([fields[4], fields[5], fields[6], fields[7]]);
+                ensure!(half_points <= 2 * count, format_err!("Invalid book score"));
+                let mut reached = [0; 8];
+                reached.copy_from_slice(&fields[8..16]);
+                let mut steps = vec![0; fields[16] as usize];
+                for index in steps.iter_mut() {
+                    let mut bytes = [0; 2];
+                    input.read_exact(&mut bytes)?;
+                    *index = u16::from_le_bytes(bytes);
+                    ensure!(
+                        (*index as usize) < NUM_STEP_INDICES,
+                        format_err!("Invalid step index {}", index)
+                    );
+                }
+                moves.push(Entry {
+                    steps,
+                    count,
+                    half_points,
+                    reached: u64::from_le_bytes(reached),
+                });
+            }
+            ensure!(
+                entries.insert(u64::from_le_bytes(key), moves).is_none(),
+                format_err!("Duplicate book position")
+            );
+        }
+        Ok(OpeningBook { entries })
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        self.write(BufWriter::new(File::create(path)?))?;
+        Ok(())
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<OpeningBook, Error> {
+        OpeningBook::read(BufReader::new(File::open(path)?))
+    }
+}
+
+fn result_hash(pos: &Position, steps: &[Step]) -> u64 {
+    let mut pos = pos.clone();
+    let side = pos.side;
+    let mut status = EndState::Neither;
+    for step in steps.iter() {
+        status = pos.do_step(*step);
+    }
+    if status == EndState::Neither && pos.side == side {
+        pos.do_step(Step::Pass);
+    }
+    pos.current_hash
+}
@@ -0,0 +1,246 @@
+use crate::position::Position;
+use crate::search::SearchConfig;
+use failure::{bail, ensure, format_err, Error};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// When a game is halted regardless of the clocks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameLimit {
+    Time(Duration),
+    Turns(u32),
+}
+
+/// A time control in the gameroom `M/R/P/L/G/T` format: time per move,
+/// starting reserve, percent of unused move time added to the reserve,
+/// reserve limit, game limit and the maximum time for a single turn. For
+/// example `2/2/100/10/8` gives two minutes per move, a two minute reserve
+/// with full carry-over capped at ten minutes, and an eight hour game.
+///
+/// Times are minutes unless written as `m:s`, `h:m:s` or with `d`, `h`, `m`
+/// and `s` units such as `1m30s`. The game limit is in hours, written as
+/// `h:m` or with units, or a number of turns as in `80t`. A limit of zero
+/// means there is none.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeControl {
+    pub per_move: Duration,
+    pub reserve: Duration,
+    pub percent_carry: u32,
+    pub reserve_limit: Option<Duration>,
+    pub game_limit: Option<GameLimit>,
+    pub max_turn: Option<Duration>,
+}
+
+impl TimeControl {
+    pub fn start_clock(&self) -> Clock {
+        Clock {
+            reserve: self.reserve,
+            game_elapsed: Duration::from_secs(0),
+            turns: 0,
+        }
+    }
+}
+
+impl FromStr for TimeControl {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<TimeControl, Error> {
+        let fields: Vec<_> = s.trim().split('/').collect();
+        ensure!(
+            fields.len() >= 3 && fields.len() <= 6,
+            format_err!("Expected M/R/P[/L[/G[/T]]] but got {}", s)
+        );
+        let percent_carry = fields[2]
+            .parse()
+            .map_err(|_| format_err!("Invalid carry-over percentage {}", fields[2]))?;
+        ensure!(
+            percent_carry <= 100,
+            format_err!("Carry-over above 100 percent: {}", percent_carry)
+        );
+        let non_zero = |d: Duration| {
+            if d == Duration::from_secs(0) {
+                None
+            } else {
+                Some(d)
+            }
+        };
+        let game_limit = match fields.get(4) {
+            Some(f) if f.ends_with('t') => {
+                let turns: u32 = f[..f.len() - 1]
+                    .parse()
+                    .map_err(|_| format_err!("Invalid turn limit {}", f))?;
+                if turns == 0 {
+                    None
+                } else {
+                    Some(GameLimit::Turns(turns))
+                }
+            }
+            Some(f) => non_zero(parse_time(f, 3600)?).map(GameLimit::Time),
+            None => None,
+        };
+        Ok(TimeControl {
+            per_move: parse_time(fields[0], 60)?,
+            reserve: parse_time(fields[1], 60)?,
+            percent_carry,
+            reserve_limit: match fields.get(3) {
+                Some(f) => non_zero(parse_time(f, 60)?),
+                None => None,
+            },
+            game_limit,
+            max_turn: match fields.get(5) {
+                Some(f) => non_zero(parse_time(f, 60)?),
+                None => None,
+            },
+        })
+    }
+}
+
+/// Parses a time whose bare numbers and leading colon field count `unit`
+/// seconds each
+fn parse_time(text: &str, unit: u64) -> Result<Duration, Error> {
+    let invalid = || format_err!("Invalid time {}", text);
+    if text.is_empty() {
+        bail!(invalid());
+    }
+    if text.chars().any(|c| c.is_ascii_alphabetic()) {
+        let mut seconds = 0;
+        let mut number = String::new();
+        for c in text.chars() {
+            if c.is_ascii_digit() {
+                number.push(c);
+                continue;
+            }
+            let scale = match c {
+                'd' => 86400,
+                'h' => 3600,
+                'm' => 60,
+                's' => 1,
+                _ => bail!(invalid()),
+            };
+            let value: u64 = number.parse().map_err(|_| invalid())?;
+            seconds += value * scale;
+            number.clear();
+        }
+        ensure!(number.is_empty(), invalid());
+        return Ok(Duration::from_secs(seconds));
+    }
+    let parts: Vec<_> = text.split(':').collect();
+    ensure!(parts.len() <= 3, invalid());
+    let mut seconds = 0;
+    let mut scale = unit;
+    for part in parts.iter() {
+        let value: u64 = part.parse().map_err(|_| invalid())?;
+        seconds += value * scale;
+        scale = (scale / 60).max(1);
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// The state of one player's clock during a game
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clock {
+    pub reserve: Duration,
+    /// Game time counted against the game limit. `update` adds this player's
+    /// turns, while the opponent's must be added by the caller.
+    pub game_elapsed: Duration,
+    pub turns: u32,
+}
+
+impl Clock {
+    /// The most time the current turn may take without losing on time
+    pub fn available(&self, tc: &TimeControl) -> Duration {
+        let mut available = tc.per_move + self.reserve;
+        if let Some(max_turn) = tc.max_turn {
+            available = available.min(max_turn);
+        }
+        if let Some(GameLimit::Time(limit)) = tc.game_limit {
+            available = available.min(limit.checked_sub(self.game_elapsed).unwrap_or_default());
+        }
+        available
+    }
+    /// Charges a turn which took `used` to the clock. Returns false if the
+    /// turn overran the time available for it.
+    pub fn update(&mut self, tc: &TimeControl, used: Duration) -> bool {
+        let in_time = used <= self.available(tc);
+        if used <= tc.per_move {
+            let unused = tc.per_move - used;
+            self.reserve += unused * tc.percent_carry / 100;
+        } else {
+            self.reserve = self
+                .reserve
+                .checked_sub(used - tc.per_move)
+                .unwrap_or_default();
+        }
+        if let Some(limit) = tc.reserve_limit {
+            self.reserve = self.reserve.min(limit);
+        }
+        self.game_elapsed += used;
+        self.turns += 1;
+        in_time
+    }
+}
+
+/// Decides how long a search may think on a turn. The move time is spent in
+/// full unless unused time carries over completely, while a fraction of the
+/// reserve is added in proportion to how complex the position looks.
+#[derive(Clone, Copy, Debug)]
+pub struct TimeAllocator {
+    /// The share of the reserve spent on a turn of average complexity
+    pub reserve_fraction: f64,
+    /// Kept back from the time available to cover communication delays
+    pub safety_margin: Duration,
+    /// The game length assumed when spreading out a game time limit
+    pub expected_turns: u32,
+}
+
+impl Default for TimeAllocator {
+    fn default() -> Self {
+        TimeAllocator {
+            reserve_fraction: 0.1,
+            safety_margin: Duration::from_secs(1),
+            expected_turns: 60,
+        }
+    }
+}
+
+impl TimeAllocator {
+    pub fn allocate(&self, tc: &TimeControl, clock: &Clock, pos: &Position) -> Duration {
+        let complexity = complexity(pos);
+        let base = if tc.percent_carry < 100 {
+            tc.per_move
+        } else {
+            tc.per_move.mul_f64(complexity.min(1.0))
+        };
+        let mut target = base + clock.reserve.mul_f64(self.reserve_fraction * complexity);
+        if let Some(GameLimit::Time(limit)) = tc.game_limit {
+            let remaining = limit.checked_sub(clock.game_elapsed).unwrap_or_default();
+            let turns_left = self.expected_turns.saturating_sub(clock.turns).max(10);
+            target = target.min(remaining / turns_left);
+        }
+        let hard_limit = clock
+            .available(tc)
+            .checked_sub(self.safety_margin)
+            .unwrap_or_default();
+        target.min(hard_limit)
+    }
+    /// A copy of `config` limited to the time allocated for this turn
+    pub fn configure(
+        &self,
+        config: &SearchConfig,
+        tc: &TimeControl,
+        clock: &Clock,
+        pos: &Position,
+    ) -> SearchConfig {
+        SearchConfig {
+            playouts: None,
+            time_limit: Some(self.allocate(tc, clock, pos)),
+            ..config.clone()
+        }
+    }
+}
+
+/// The number of legal steps relative to a typical position, between 0.5 and 2
+fn complexity(pos: &Position) -> f64 {
+    let steps = pos.gen_steps().len() as f64;
+    (steps / 24.0).clamp(0.5, 2.0)
+}
@@ -0,0 +1,341 @@
+use crate::game::{Game, Move};
+use crate::position::{piece_char_index, EndState, Piece, Position};
+use crate::zobrist::compute_hash;
+use failure::{bail, ensure, format_err, Error};
+use num_traits::FromPrimitive;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const MAGIC: &[u8; 4] = b"ARDB";
+const VERSION: u8 = 1;
+/// The number of each piece at the start of a game, indexed by piece
+const FULL_COUNTS: [u8; 13] = [0, 8, 2, 2, 2, 1, 1, 8, 2, 2, 2, 1, 1];
+/// Pieces in the order material is written, strongest first
+const MATERIAL_ORDER: [Piece; 12] = [
+    Piece::WElephant,
+    Piece::WCamel,
+    Piece::WHorse,
+    Piece::WDog,
+    Piece::WCat,
+    Piece::WRabbit,
+    Piece::BElephant,
+    Piece::BCamel,
+    Piece::BHorse,
+    Piece::BDog,
+    Piece::BCat,
+    Piece::BRabbit,
+];
+
+/// The number of each piece on the board, written as the letters of every
+/// piece such as `EMHHDDCCRRRRRRRRemhhddccrrrrrrrr`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Material {
+    counts: [u8; 13],
+}
+
+impl Material {
+    pub fn new(pos: &Position) -> Material {
+        let mut counts = [0; 13];
+        for (count, bitboard) in counts.iter_mut().zip(pos.bitboards.iter()).skip(1) {
+            *count = bitboard.count_ones() as u8;
+        }
+        Material { counts }
+    }
+    pub fn count(&self, piece: Piece) -> u8 {
+        self.counts[piece as usize]
+    }
+    /// The number of `piece` missing since the start of the game
+    pub fn captured(&self, piece: Piece) -> u8 {
+        FULL_COUNTS[piece as usize].saturating_sub(self.count(piece))
+    }
+    /// Every piece, in order of piece index
+    pub fn pieces(&self) -> Vec<Piece> {
+        let mut pieces = Vec::new();
+        for (pix, count) in self.counts.iter().enumerate().skip(1) {
+            let piece = Piece::from_usize(pix).unwrap();
+            pieces.extend(std::iter::repeat_n(piece, *count as usize));
+        }
+        pieces
+    }
+    /// The material left after capturing one `piece`, if there is one
+    pub fn without(&self, piece: Piece) -> Option<Material> {
+        let mut counts = self.counts;
+        counts[piece as usize] = counts[piece as usize].checked_sub(1)?;
+        Some(Material { counts })
+    }
+}
+
+impl fmt::Display for Material {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for piece in MATERIAL_ORDER.iter() {
+            for _ in 0..self.count(*piece) {
+                write!(f, "{}", piece)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses the letters of every piece in any order, ignoring spaces and `/`
+impl FromStr for Material {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Material, Error> {
+        let mut counts = [0; 13];
+        for c in input.chars().filter(|c| !c.is_whitespace() && *c != '/') {
+            match piece_char_index(c) {
+                0 => bail!("Invalid piece {} in material {}", c, input),
+                pix => counts[pix as usize] += 1,
+            }
+        }
+        for (count, full) in counts.iter().zip(FULL_COUNTS.iter()) {
+            ensure!(
+                count <= full,
+                format_err!("Too many pieces in material {}", input)
+            );
+        }
+        Ok(Material { counts })
+    }
+}
+
+/// Criteria for `GameDatabase::search`. A game must meet every criterion
+/// given.
+#[derive(Clone, Default)]
+pub struct Query {
+    /// The board and side to move at the start of some turn, ignoring the
+    /// steps left and last step of the given position
+    pub position: Option<Position>,
+    /// Material on the board at the start of some turn
+    pub material: Option<Material>,
+    /// A player named by the `white` or `black` tag, ignoring case
+    pub player: Option<String>,
+    /// Pieces of which at least one must have been captured, such as both
+    /// elephants to find games where either elephant was lost
+    pub captured: Vec<Piece>,
+}
+
+/// Games stored in a file, with indices of the positions and material
+/// reached at the start of each turn after setup.
+///
+/// The file is a four byte magic number and a version byte followed by each
+/// game as its length (u32) and record. A record is its result byte (0 if
+/// undecided, 1 if White won and 2 if Black won), the number of tags (u8)
+/// with each tag's name and value as a length (u16) and UTF-8 bytes, and the
+/// number of turns (u16) followed by each turn packed by `Move::to_bytes`.
+/// Integers are little endian. Games are appended as they are added, and the
+/// indices are rebuilt as the games are replayed on opening.
+#[derive(Default)]
+pub struct GameDatabase {
+    games: Vec<Game>,
+    positions: HashMap<u64, Vec<usize>>,
+    materials: HashMap<Material, Vec<usize>>,
+    file: Option<File>,
+}
+
+/// The index key of a position, which is its hash at the start of the turn
+fn position_key(pos: &Position) -> u64 {
+    compute_hash(&pos.pieces, pos.side, 4, None)
+}
+
+impl GameDatabase {
+    /// A database held only in memory
+    pub fn new() -> GameDatabase {
+        GameDatabase::default()
+    }
+    /// Opens the database stored at `path`, creating it if it doesn't exist.
+    /// The indices aren't stored in the file, so every game is parsed and
+    /// replayed to rebuild them, and opening takes time in proportion to the
+    /// whole archive.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<GameDatabase, Error> {
+        let path = path.as_ref();
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => bail!("Could not read {}: {}", path.display(), e),
+        };
+        let mut database = GameDatabase::new();
+        if !bytes.is_empty() {
+            ensure!(
+                bytes.len() >= 5 && &bytes[..4] == MAGIC,
+                format_err!("{} is not a game database", path.display())
+            );
+            ensure!(
+                bytes[4] == VERSION,
+                format_err!("Unsupported database version {}", bytes[4])
+            );
+            let mut rest = &bytes[5..];
+            while !rest.is_empty() {
+                ensure!(rest.len() >= 4, format_err!("Truncated database"));
+                let len = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+                ensure!(rest.len() >= 4 + len, format_err!("Truncated database"));
+                let id = database.games.len();
+                let game = read_record(&rest[4..4 + len])
+                    .map_err(|e| format_err!("Game {}: {}", id, e))?;
+                database.index(game);
+                rest = &rest[4 + len..];
+            }
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if bytes.is_empty() {
+            file.write_all(MAGIC)?;
+            file.write_all(&[VERSION])?;
+        }
+        database.file = Some(file);
+        Ok(database)
+    }
+    /// The number of games stored
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+    /// The game with the given id, ids counting up from 0 in the order games
+    /// were added
+    pub fn game(&self, id: usize) -> Option<&Game> {
+        self.games.get(id)
+    }
+    /// Stores a game, writing it to the file if there is one, and returns its
+    /// id
+    pub fn add_game(&mut self, game: Game) -> Result<usize, Error> {
+        if let Some(file) = self.file.as_mut() {
+            let record = write_record(&game)?;
+            let mut bytes = (record.len() as u32).to_le_bytes().to_vec();
+            bytes.extend_from_slice(&record);
+            file.write_all(&bytes)?;
+        }
+        Ok(self.index(game))
+    }
+    fn index(&mut self, game: Game) -> usize {
+        let id = self.games.len();
+        for pos in game.positions().iter() {
+            let ids = self.positions.entry(position_key(pos)).or_default();
+            if ids.last() != Some(&id) {
+                ids.push(id);
+            }
+            let ids = self.materials.entry(Material::new(pos)).or_default();
+            if ids.last() != Some(&id) {
+                ids.push(id);
+            }
+        }
+        self.games.push(game);
+        id
+    }
+    /// The ids of every game meeting the query, in the order they were added
+    pub fn search(&self, query: &Query) -> Vec<usize> {
+        let position = query.position.as_ref().map(|pos| {
+            self.positions
+                .get(&position_key(pos))
+                .map_or(&[][..], |ids| &ids[..])
+        });
+        let material = query
+            .material
+            .map(|m| self.materials.get(&m).map_or(&[][..], |ids| &ids[..]));
+        let candidates: Vec<usize> = match (position, material) {
+            (Some(ids), _) | (None, Some(ids)) => ids.to_vec(),
+            (None, None) => (0..self.games.len()).collect(),
+        };
+        let player = query.player.as_ref().map(|p| p.to_lowercase());
+        candidates
+            .into_iter()
+            .filter(|id| {
+                let game = &self.games[*id];
+                material.is_none_or(|ids| ids.binary_search(id).is_ok())
+                    && player.as_ref().is_none_or(|name| {
+                        ["white", "black"]
+                            .iter()
+                            .any(|tag| game.tag(tag).map(str::to_lowercase).as_ref() == Some(name))
+                    })
+                    && (query.captured.is_empty() || {
+                        // Material only ever decreases, so the final position
+                        // shows every capture
+                        let material = Material::new(&game.position);
+                        query.captured.iter().any(|p| material.captured(*p) > 0)
+                    })
+            })
+            .collect()
+    }
+}
+
+fn write_record(game: &Game) -> Result<Vec<u8>, Error> {
+    let mut bytes = vec![match game.result {
+        EndState::Neither => 0,
+        EndState::WhiteWin => 1,
+        EndState::BlackWin => 2,
+    }];
+    ensure!(game.tags.len() < 256, format_err!("Too many tags"));
+    bytes.push(game.tags.len() as u8);
+    for (name, value) in game.tags.iter() {
+        for text in [name, value].iter() {
+            ensure!(text.len() < 1 << 16, format_err!("Tag too long"));
+            bytes.extend_from_slice(&(text.len() as u16).to_le_bytes());
+            bytes.extend_from_slice(text.as_bytes());
+        }
+    }
+    ensure!(game.moves.len() < 1 << 16, format_err!("Too many turns"));
+    bytes.extend_from_slice(&(game.moves.len() as u16).to_le_bytes());
+    let empty = Position::new_empty();
+    let positions = game.positions();
+    let starts = std::iter::repeat_n(&empty, 2).chain(positions.iter());
+    for (mov, pos) in game.moves.iter().zip(starts) {
+        bytes.extend(mov.to_bytes(pos)?);
+    }
+    Ok(bytes)
+}
+
+fn read_record(mut bytes: &[u8]) -> Result<Game, Error> {
+    let mut take = |n: usize| -> Result<&[u8], Error> {
+        ensure!(bytes.len() >= n, format_err!("Truncated game record"));
+        let (taken, rest) = bytes.split_at(n);
+        bytes = rest;
+        Ok(taken)
+    };
+    let result = take(1)?[0];
+    let num_tags = take(1)?[0];
+    let mut tags = Vec::with_capacity(num_tags as usize);
+    for _ in 0..num_tags {
+        let mut text = || -> Result<String, Error> {
+            let len = take(2)?;
+            let len = u16::from_le_bytes([len[0], len[1]]) as usize;
+            Ok(String::from_utf8(take(len)?.to_vec())?)
+        };
+        let name = text()?;
+        tags.push((name, text()?));
+    }
+    let num_moves = take(2)?;
+    let num_moves = u16::from_le_bytes([num_moves[0], num_moves[1]]);
+    ensure!(num_moves >= 2, format_err!("Missing setups"));
+    let mut next_move = |pos: &Position| -> Result<Move, Error> {
+        let head = take(2)?;
+        let (version, num_steps) = (head[0], head[1]);
+        let steps = take(2 * num_steps as usize)?;
+        Move::from_bytes(&[&[version, num_steps][..], steps].concat(), pos)
+    };
+    let empty = Position::new_empty();
+    let white_setup = next_move(&empty)?;
+    let black_setup = next_move(&empty)?;
+    let mut game = Game::new(white_setup, black_setup)?;
+    for _ in 2..num_moves {
+        let mov = next_move(&game.position)?;
+        game.play(&mov)?;
+    }
+    ensure!(
+        bytes.is_empty(),
+        format_err!("Trailing bytes in game record")
+    );
+    let result = match result {
+        0 => EndState::Neither,
+        1 => EndState::WhiteWin,
+        2 => EndState::BlackWin,
+        r => bail!("Invalid result {}", r),
+    };
+    if game.result == EndState::Neither {
+        game.result = result;
+    }
+    game.tags = tags;
+    Ok(game)
+}
@@ -0,0 +1,180 @@
+//! Dense encodings of positions and steps for neural network experiments.
+//!
+//! A position is encoded as `NUM_PLANES` planes of 64 values, plane by plane,
+//! with squares ordered a1, b1, ... h8 as in `Position::pieces`. Every value
+//! is 0 or 1.
+//!
+//! | Planes | Contents                                                   |
+//! |--------|------------------------------------------------------------|
+//! | 0-5    | White rabbits, cats, dogs, horses, camels and elephants    |
+//! | 6-11   | Black pieces in the same order                             |
+//! | 12     | Set everywhere if Black is to move                         |
+//! | 13-16  | Set everywhere on the plane for one to four steps left     |
+//! | 17     | Frozen white pieces                                        |
+//! | 18     | Frozen black pieces                                        |
+//! | 19     | The four trap squares                                      |
+//! | 20     | The square a push must be completed into                   |
+//! | 21     | Source square of the last step this turn, if not a push    |
+//! | 22     | Destination square of the last step this turn              |
+//! | 23-28  | Set everywhere on the plane for the type of the piece that |
+//! |        | took the last step, from rabbit to elephant                |
+//! | 29     | Set everywhere if that piece belongs to the side to move   |
+//!
+//! Steps are encoded for a policy head by their dense index from
+//! `Step::to_index`, which gives every push and pull its own outputs and
+//! leaves no index unused. Index `PASS_INDEX` passes.
+use crate::position::{Piece, Position, Side, Step, NUM_STEP_INDICES, PASS_STEP_INDEX};
+use crate::zobrist::compute_hash;
+use failure::{ensure, format_err, Error};
+use num_traits::FromPrimitive;
+
+pub const NUM_PLANES: usize = 30;
+pub const ENCODED_SIZE: usize = NUM_PLANES * 64;
+pub const PASS_INDEX: usize = PASS_STEP_INDEX;
+pub const POLICY_SIZE: usize = NUM_STEP_INDICES;
+
+const SIDE_PLANE: usize = 12;
+const STEPS_PLANE: usize = 13;
+const FROZEN_PLANE: usize = 17;
+const TRAP_PLANE: usize = 19;
+const PUSH_PLANE: usize = 20;
+const LAST_SOURCE_PLANE: usize = 21;
+const LAST_DEST_PLANE: usize = 22;
+const LAST_PIECE_PLANE: usize = 23;
+const LAST_OWNER_PLANE: usize = 29;
+const TRAPS: u64 = 0x0000_2400_0024_0000;
+
+pub fn encode(pos: &Position) -> Vec<f32> {
+    encode_bytes(pos).into_iter().map(f32::from).collect()
+}
+
+pub fn encode_bytes(pos: &Position) -> Vec<u8> {
+    let mut planes = vec![0; ENCODED_SIZE];
+    let mut set_mask = |plane: usize, mask: u64| {
+        for sq in 0..64 {
+            if mask & (1 << sq) != 0 {
+                planes[plane * 64 + sq] = 1;
+            }
+        }
+    };
+    for pix in 1..13 {
+        set_mask(pix - 1, pos.bitboards[pix]);
+    }
+    if pos.side == Side::Black {
+        set_mask(SIDE_PLANE, !0);
+    }
+    if (1..=4).contains(&pos.steps_left) {
+        set_mask(STEPS_PLANE + pos.steps_left as usize - 1, !0);
+    }
+    let frozen = pos.frozen();
+    set_mask(FROZEN_PLANE, frozen & pos.placement[0]);
+    set_mask(FROZEN_PLANE + 1, frozen & pos.placement[1]);
+    set_mask(TRAP_PLANE, TRAPS);
+    let (piece, dest) = match pos.last_step {
+        Some(Step::Push(piece, source, dest)) => {
+            set_mask(PUSH_PLANE, 1 << source);
+            (piece, dest)
+        }
+        Some(Step::Move(piece, source, dest)) => {
+            set_mask(LAST_SOURCE_PLANE, 1 << source);
+            (piece, dest)
+        }
+        _ => return planes,
+    };
+    set_mask(LAST_DEST_PLANE, 1 << dest);
+    // The piece may since have been captured, so it is stored separately
+    let pix = piece as usize;
+    let (kind, owner) = if pix <= 6 {
+        (pix, Side::White)
+    } else {
+        (pix - 6, Side::Black)
+    };
+    set_mask(LAST_PIECE_PLANE + kind - 1, !0);
+    if owner == pos.side {
+        set_mask(LAST_OWNER_PLANE, !0);
+    }
+    planes
+}
+
+/// Rebuilds the position encoded by `encode`. The move number and the
+/// repetition history are not encoded, so the result is numbered as the first
+/// move after setup and has no history.
+pub fn decode(planes: &[f32]) -> Result<Position, Error> {
+    decode_planes(planes)
+}
+
+pub fn decode_bytes(planes: &[u8]) -> Result<Position, Error> {
+    decode_planes(planes)
+}
+
+fn decode_planes<T: Copy + Into<f32>>(planes: &[T]) -> Result<Position, Error> {
+    ensure!(
+        planes.len() == ENCODED_SIZE,
+        format_err!("Expected {} values but got {}", ENCODED_SIZE, planes.len())
+    );
+    let mask = |plane: usize| {
+        (0..64)
+            .filter(|sq| planes[plane * 64 + sq].into() > 0.5)
+            .fold(0u64, |m, sq| m | 1 << sq)
+    };
+    let mut pieces = [Piece::Empty; 64];
+    for pix in 1..13 {
+        let bb = mask(pix - 1);
+        for (sq, piece) in pieces.iter_mut().enumerate() {
+            if bb & (1 << sq) != 0 {
+                ensure!(
+                    *piece == Piece::Empty,
+                    format_err!("Two pieces on square {}", sq)
+                );
+                *piece = Piece::from_usize(pix).unwrap();
+            }
+        }
+    }
+    let side = if mask(SIDE_PLANE) != 0 {
+        Side::Black
+    } else {
+        Side::White
+    };
+    let steps_left = (0..4)
+        .find(|i| mask(STEPS_PLANE + i) != 0)
+        .map_or(0, |i| i as u8 + 1);
+    let square = |plane: usize| {
+        let m = mask(plane);
+        if m == 0 {
+            None
+        } else {
+            Some(m.trailing_zeros() as u8)
+        }
+    };
+    let last_piece = (0..6).find(|i| mask(LAST_PIECE_PLANE + i) != 0).map(|i| {
+        let white = (mask(LAST_OWNER_PLANE) != 0) == (side == Side::White);
+        let pix = if white { i + 1 } else { i + 7 };
+        Piece::from_usize(pix).unwrap()
+    });
+    let last_step = match (
+        square(PUSH_PLANE),
+        square(LAST_SOURCE_PLANE),
+        square(LAST_DEST_PLANE),
+        last_piece,
+    ) {
+        (Some(source), _, Some(dest), Some(p)) => Some(Step::Push(p, source, dest)),
+        (None, Some(source), Some(dest), Some(p)) => Some(Step::Move(p, source, dest)),
+        _ => None,
+    };
+    let mut pos = Position::from_pieces(side, steps_left, pieces);
+    pos.last_step = last_step;
+    pos.current_hash = compute_hash(&pos.pieces, side, steps_left, last_step);
+    Ok(pos)
+}
+
+/// The policy index of a step played from `pos`, which is its dense index
+/// `Step::to_index`. Placements and trap removals have no index.
+pub fn policy_index(step: Step, pos: &Position) -> Option<usize> {
+    step.to_index(pos)
+}
+
+/// The step with the given policy index in `pos`, taking the moving piece
+/// from the board. Returns `None` for indices starting from an empty square.
+pub fn policy_step(index: usize, pos: &Position) -> Option<Step> {
+    Step::from_index(index, pos)
+}
@@ -0,0 +1,188 @@
+use crate::position::{neighbors_of, Position, Side, Step};
+use crate::rollout::material_balance;
+use failure::{bail, ensure, format_err, Error};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Scores positions for the search. Implementations must be usable from
+/// several search threads at once.
+pub trait Evaluate: Send + Sync {
+    /// The expected result for the side to move, from -1 for a loss to 1 for
+    /// a win
+    fn value(&self, pos: &Position) -> f64;
+    /// Prior probabilities of `steps`, in the same order, for the search to
+    /// favour. `None` treats all steps alike.
+    fn priors(&self, _pos: &Position, _steps: &[Step]) -> Option<Vec<f64>> {
+        None
+    }
+}
+
+/// The score in material units, with a rabbit worth 100, that the
+/// handcrafted evaluation squashes to a value of tanh(1)
+pub const SCORE_SCALE: f64 = 600.0;
+
+/// Squashes a score in material units into a value from -1 to 1, the way
+/// the handcrafted evaluation does
+pub fn score_value(score: f64) -> f64 {
+    (score / SCORE_SCALE).tanh()
+}
+
+pub const NUM_FEATURES: usize = 10;
+/// The names of the features, as used in weights files
+pub const FEATURE_NAMES: [&str; NUM_FEATURES] = [
+    "rabbit",
+    "cat",
+    "dog",
+    "horse",
+    "camel",
+    "elephant",
+    "rabbit_advance",
+    "frozen",
+    "trap_control",
+    "rabbit_seventh",
+];
+
+const TRAPS: [u64; 4] = [1 << 18, 1 << 21, 1 << 42, 1 << 45];
+const RANK_7: [u64; 2] = [0xFF << 48, 0xFF << 8];
+
+/// Features of the position, each as the difference between the side to move
+/// and its opponent: the number of pieces of each type, the ranks rabbits
+/// have advanced, frozen pieces, pieces next to traps and rabbits one step
+/// from goal.
+pub fn features(pos: &Position) -> [f64; NUM_FEATURES] {
+    let mut features = [0.0; NUM_FEATURES];
+    let frozen = pos.frozen();
+    for side in [Side::White, Side::Black].iter() {
+        let s = *side as usize;
+        let sign = if *side == pos.side { 1.0 } else { -1.0 };
+        for (kind, feature) in features[..6].iter_mut().enumerate() {
+            *feature += sign * pos.bitboards[1 + kind + 6 * s].count_ones() as f64;
+        }
+        let rabbits = pos.bitboards[1 + 6 * s];
+        let advance: u32 = (0..64)
+            .filter(|sq| rabbits & (1 << sq) != 0)
+            .map(|sq| if s == 0 { sq / 8 } else { 7 - sq / 8 })
+            .sum();
+        features[6] += sign * advance as f64;
+        features[7] += sign * (frozen & pos.placement[s]).count_ones() as f64;
+        let guards: u32 = TRAPS
+            .iter()
+            .map(|trap| (neighbors_of(*trap) & pos.placement[s]).count_ones())
+            .sum();
+        features[8] += sign * guards as f64;
+        features[9] += sign * (rabbits & RANK_7[s]).count_ones() as f64;
+    }
+    features
+}
+
+/// A handcrafted evaluation: material, with terms for advanced rabbits,
+/// frozen pieces and trap control, squashed into a value
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HandcraftedEval;
+
+impl Evaluate for HandcraftedEval {
+    fn value(&self, pos: &Position) -> f64 {
+        let f = features(pos);
+        let material = match pos.side {
+            Side::White => material_balance(pos),
+            Side::Black => -material_balance(pos),
+        };
+        let score = material as f64 + 5.0 * f[6] - 20.0 * f[7] + 10.0 * f[8] + 40.0 * f[9];
+        score_value(score)
+    }
+}
+
+/// A model linear in `features`, with the value `tanh(w . features)`. Weights
+/// are read from a text file holding one `name weight` pair per line, where
+/// blank lines and lines starting with `#` are ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LinearEval {
+    pub weights: [f64; NUM_FEATURES],
+}
+
+impl LinearEval {
+    pub fn new(weights: [f64; NUM_FEATURES]) -> LinearEval {
+        LinearEval { weights }
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<LinearEval, Error> {
+        fs::read_to_string(path)?.parse()
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+    /// The weighted sum of the features, before squashing
+    pub fn score(&self, pos: &Position) -> f64 {
+        features(pos)
+            .iter()
+            .zip(self.weights.iter())
+            .map(|(f, w)| f * w)
+            .sum()
+    }
+}
+
+/// Roughly the handcrafted evaluation, as a starting point for tuning
+impl Default for LinearEval {
+    fn default() -> Self {
+        let material = [100.0, 150.0, 200.0, 300.0, 500.0, 800.0];
+        let mut weights = [0.0; NUM_FEATURES];
+        weights[..6].copy_from_slice(&material);
+        weights[6..].copy_from_slice(&[5.0, -20.0, 10.0, 40.0]);
+        for w in weights.iter_mut() {
+            *w /= SCORE_SCALE;
+        }
+        LinearEval { weights }
+    }
+}
+
+impl Evaluate for LinearEval {
+    fn value(&self, pos: &Position) -> f64 {
+        self.score(pos).tanh()
+    }
+}
+
+impl FromStr for LinearEval {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<LinearEval, Error> {
+        let mut weights = [None; NUM_FEATURES];
+        for line in s.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let (name, weight) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(name), Some(weight), None) => (name, weight),
+                _ => bail!("Expected a name and a weight: {}", line),
+            };
+            let index = FEATURE_NAMES
+                .iter()
+                .position(|n| *n == name)
+                .ok_or(format_err!("Unknown feature {}", name))?;
+            ensure!(
+                weights[index].is_none(),
+                format_err!("Duplicate feature {}", name)
+            );
+            let weight: f64 = weight
+                .parse()
+                .map_err(|_| format_err!("Invalid weight {}", weight))?;
+            weights[index] = Some(weight);
+        }
+        let mut result = [0.0; NUM_FEATURES];
+        for (i, w) in weights.iter().enumerate() {
+            result[i] = w.ok_or(format_err!("Missing feature {}", FEATURE_NAMES[i]))?;
+        }
+        Ok(LinearEval::new(result))
+    }
+}
+
+impl fmt::Display for LinearEval {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (name, weight) in FEATURE_NAMES.iter().zip(self.weights.iter()) {
+            writeln!(f, "{} {}", name, weight)?;
+        }
+        Ok(())
+    }
+}
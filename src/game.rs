@@ -1,9 +1,28 @@
-use crate::position::{EndState, Position, Side, Step};
-use std::collections::{hash_map::Entry, HashMap, HashSet};
-//use std::fmt;
+use crate::position::{EndState, Piece, Position, Side, Step, SETUP_COUNTS, TRAP_INDICES};
+use failure::{bail, ensure, format_err, Error};
+use num_traits::FromPrimitive;
+use std::collections::{hash_map::Entry, HashMap};
+use std::fmt;
 
+const MOVE_BYTES_VERSION: u8 = 2;
+/// Marks a packed step as a placement rather than a dense step index
+const PLACEMENT_BIT: u16 = 0x8000;
+
+/// A game from the setups onwards, with every turn validated as it is played
+#[derive(Clone)]
 pub struct Game {
+    /// The position after the moves played so far
     pub position: Position,
+    /// The position after both setups
+    pub start: Position,
+    /// Every turn, starting with the setups of White and Black. Captures are
+    /// recorded as `Remove` steps, as in the standard notation.
+    pub moves: Vec<Move>,
+    /// The result, which may be set by hand for games ending by resignation or
+    /// on time
+    pub result: EndState,
+    /// Header fields of a game record, such as the players, in order
+    pub tags: Vec<(String, String)>,
 }
 #[derive(Clone, Debug)]
 pub struct Move {
@@ -15,17 +34,98 @@ impl Move {
         Move { steps }
     }
     pub fn from_line(line: &str) -> Move {
-        let steps = line.split(" ").map(|s| Step::from_notation(s)).collect();
+        let steps = line.split(' ').map(Step::from_notation).collect();
         Move { steps }
     }
+    /// Parses the steps of a turn, without its move number
+    pub fn parse(line: &str) -> Result<Move, Error> {
+        let steps = line
+            .split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Move { steps })
+    }
+    /// Packs the turn played from `position` as a version byte, its number
+    /// of steps and then each step as two little endian bytes: its dense
+    /// index, or for a placement the top bit set above the piece and square
+    /// bytes. Captures are left out since `Game::play` works them out again.
+    pub fn to_bytes(&self, position: &Position) -> Result<Vec<u8>, Error> {
+        let steps: Vec<_> = self
+            .steps
+            .iter()
+            .filter(|s| !matches!(s, Step::Remove(_, _)))
+            .collect();
+        ensure!(steps.len() < 256, format_err!("Too many steps to pack"));
+        let mut bytes = vec![MOVE_BYTES_VERSION, steps.len() as u8];
+        let mut pos = position.clone();
+        for step in steps {
+            let packed = match *step {
+                Step::Place(p, sq) => PLACEMENT_BIT | (p as u16) << 8 | u16::from(sq),
+                step => step
+                    .to_index(&pos)
+                    .ok_or(format_err!("Can't pack step {}", step))? as u16,
+            };
+            bytes.extend_from_slice(&packed.to_le_bytes());
+            pos.do_step(*step);
+        }
+        Ok(bytes)
+    }
+    /// Unpacks a turn written by `to_bytes`, taking the pieces that step from
+    /// `position` and checking that every step is legal in turn
+    pub fn from_bytes(bytes: &[u8], position: &Position) -> Result<Move, Error> {
+        ensure!(bytes.len() >= 2, format_err!("Packed move too short"));
+        ensure!(
+            bytes[0] == MOVE_BYTES_VERSION,
+            format_err!("Unsupported move version {}", bytes[0])
+        );
+        ensure!(
+            bytes.len() == 2 + 2 * bytes[1] as usize,
+            format_err!("Packed move has the wrong length")
+        );
+        let mut pos = position.clone();
+        let side = pos.side;
+        let mut status = EndState::Neither;
+        let mut steps = Vec::with_capacity(bytes[1] as usize);
+        for pair in bytes[2..].chunks(2) {
+            ensure!(
+                status == EndState::Neither && pos.side == side,
+                format_err!("Too many steps in packed move")
+            );
+            let packed = u16::from_le_bytes([pair[0], pair[1]]);
+            let step = if packed & PLACEMENT_BIT != 0 {
+                let (pix, sq) = ((packed & !PLACEMENT_BIT) >> 8, packed & 0xFF);
+                let piece = Piece::from_u16(pix)
+                    .filter(|p| *p != Piece::Empty)
+                    .ok_or(format_err!("Invalid piece {}", pix))?;
+                ensure!(
+                    sq < 64 && pos.pieces[sq as usize] == Piece::Empty,
+                    format_err!("Invalid placement square {}", sq)
+                );
+                Step::Place(piece, sq as u8)
+            } else {
+                let step = Step::from_index(packed as usize, &pos)
+                    .ok_or(format_err!("Invalid step index {}", packed))?;
+                ensure!(
+                    pos.gen_steps().contains(&step),
+                    format_err!("Illegal step {}", step)
+                );
+                step
+            };
+            status = pos.do_step(step);
+            steps.push(step);
+        }
+        Ok(Move { steps })
+    }
+    /// Every position reachable by a legal turn, with the moves reaching it.
+    /// Turns which lose immediately are excluded, while winning turns end the
+    /// search early.
     pub fn all_positions(position: &Position) -> HashMap<Position, Vec<Move>> {
         let init_side = position.side;
         let loss = EndState::from(init_side.opposite());
-        let mut in_progress = vec![(position.clone(), Move::new(vec![]))];
+        let mut in_progress = vec![(position.clone(), Move::new(vec![]), false)];
         let mut finished: HashMap<_, Vec<Move>> = HashMap::new();
-        while in_progress.len() != 0 {
-            let (pos, mov) = in_progress.pop().unwrap();
-            if pos.side != init_side {
+        while let Some((pos, mov, won)) = in_progress.pop() {
+            if won || pos.side != init_side {
                 let entry = finished.entry(pos);
                 match entry {
                     Entry::Occupied(mut entry) => {
@@ -45,9 +145,305 @@ impl Move {
                     continue; // Suppose we don't want to include auto-losses here
                 }
                 next_move.steps.push(s);
-                in_progress.push((next_pos, next_move));
+                in_progress.push((next_pos, next_move, status != EndState::Neither));
             }
         }
         finished
     }
+    /// One move for each distinct position reachable this turn, choosing the
+    /// move with the fewest steps, along with the resulting position. The
+    /// order is stable across runs.
+    pub fn all_turns(position: &Position) -> Vec<(Move, Position)> {
+        let mut turns: Vec<_> = Self::all_positions(position)
+            .into_iter()
+            .map(|(pos, moves)| {
+                let shortest = moves.into_iter().min_by_key(|m| m.steps.len()).unwrap();
+                (shortest, pos)
+            })
+            .collect();
+        turns.sort_by_key(|(_, pos)| pos.current_hash);
+        turns
+    }
+}
+
+/// The number of sequences of `depth` turns, where turns count once per
+/// distinct position they reach. Games won before `depth` turns count as a
+/// single sequence.
+pub fn perft(position: &Position, depth: u32) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+    Move::all_turns(position)
+        .iter()
+        .map(|(_, pos)| {
+            if pos.side == position.side {
+                1 // The turn won the game without passing play over
+            } else {
+                perft(pos, depth - 1)
+            }
+        })
+        .sum()
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let steps: Vec<_> = self.steps.iter().map(|s| s.to_string()).collect();
+        write!(f, "{}", steps.join(" "))
+    }
+}
+
+impl Game {
+    /// Starts a game from the setups of both sides, given as `Place` steps
+    pub fn new(white_setup: Move, black_setup: Move) -> Result<Game, Error> {
+        let mut pieces = [Piece::Empty; 64];
+        for (setup, side) in [(&white_setup, Side::White), (&black_setup, Side::Black)].iter() {
+            let mut counts = [0; 6];
+            for step in setup.steps.iter() {
+                let (piece, sq) = match *step {
+                    Step::Place(piece, sq) => (piece, sq as usize),
+                    _ => bail!("Expected a setup but found {}", step),
+                };
+                let pix = piece as usize;
+                let (kind, home) = match side {
+                    Side::White => (pix.wrapping_sub(1), sq < 16),
+                    Side::Black => (pix.wrapping_sub(7), sq >= 48),
+                };
+                ensure!(kind < 6 && home, format_err!("Invalid setup step {}", step));
+                ensure!(
+                    pieces[sq] == Piece::Empty,
+                    format_err!("Two pieces placed on {}", step)
+                );
+                pieces[sq] = piece;
+                counts[kind] += 1;
+            }
+            ensure!(
+                counts == SETUP_COUNTS,
+                format_err!("{:?} setup doesn't place every piece once", side)
+            );
+        }
+        let start = Position::from_pieces(Side::White, 4, pieces);
+        Ok(Game {
+            position: start.clone(),
+            start,
+            moves: vec![white_setup, black_setup],
+            result: EndState::Neither,
+            tags: Vec::new(),
+        })
+    }
+    /// The value of the first tag called `name`
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+    /// Plays a turn after checking each step is legal. Pushed and pulled
+    /// pieces may be written as ordinary moves, and capture steps are ignored
+    /// since captures are worked out from the board.
+    pub fn play(&mut self, mov: &Move) -> Result<EndState, Error> {
+        ensure!(
+            self.result == EndState::Neither,
+            format_err!("The game is already over")
+        );
+        let side = self.position.side;
+        let mut pos = self.position.clone();
+        let mut recorded = Vec::new();
+        let mut status = EndState::Neither;
+        for step in mov.steps.iter() {
+            if let Step::Remove(_, _) = step {
+                continue;
+            }
+            ensure!(
+                status == EndState::Neither && pos.side == side,
+                format_err!("Too many steps in {}", mov)
+            );
+            let legal = pos.gen_steps();
+            let step = match *step {
+                Step::Move(p, source, dest) if !legal.contains(step) => Step::Push(p, source, dest),
+                other => other,
+            };
+            ensure!(
+                step != Step::Pass && legal.contains(&step),
+                format_err!("Illegal step {} in {}", step, mov)
+            );
+            let before = pos.pieces;
+            status = pos.do_step(step);
+            recorded.push(step);
+            for trap in TRAP_INDICES.iter().cloned() {
+                let emptied = before[trap] != Piece::Empty && pos.pieces[trap] == Piece::Empty;
+                match step {
+                    Step::Move(p, source, dest) | Step::Push(p, source, dest) => {
+                        if dest as usize == trap && pos.pieces[trap] == Piece::Empty {
+                            recorded.push(Step::Remove(p, dest));
+                        } else if emptied && source as usize != trap {
+                            recorded.push(Step::Remove(before[trap], trap as u8));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        ensure!(
+            !recorded.is_empty(),
+            format_err!("A turn needs at least one step")
+        );
+        if status == EndState::Neither && pos.side == side {
+            ensure!(
+                pos.pieces != self.position.pieces,
+                format_err!("A turn must change the position")
+            );
+            status = pos.do_step(Step::Pass);
+        }
+        self.position = pos;
+        self.moves.push(Move::new(recorded));
+        self.result = status.clone();
+        Ok(status)
+    }
+    /// The position at the start of each turn after the setups, ending with
+    /// the current position
+    pub fn positions(&self) -> Vec<Position> {
+        let mut positions = vec![self.start.clone()];
+        let mut pos = self.start.clone();
+        for mov in self.moves.iter().skip(2) {
+            replay_turn(&mut pos, mov);
+            positions.push(pos.clone());
+        }
+        positions
+    }
+    /// Undoes the last turn after the setups, returning it
+    pub fn takeback(&mut self) -> Option<Move> {
+        if self.moves.len() <= 2 {
+            return None;
+        }
+        let mov = self.moves.pop();
+        let mut pos = self.start.clone();
+        for mov in self.moves.iter().skip(2) {
+            replay_turn(&mut pos, mov);
+        }
+        self.position = pos;
+        self.result = EndState::Neither;
+        mov
+    }
+    /// Parses a game in the standard move list notation, such as
+    /// `1w Ra1 Rb1 ... 1b ra8 rb8 ... 2w Ee2n Ee3n`, where each turn begins
+    /// with its move number and side. Gold and silver may be written as `g`
+    /// and `s`. The last turn may be empty.
+    ///
+    /// Lines starting with `#` hold tags written as `# name value`. A
+    /// `result` tag whose value starts with `w` or `b`, or `g` or `s`, gives
+    /// the winner of a game not decided on the board.
+    pub fn from_move_list(text: &str) -> Result<Game, Error> {
+        let mut turns: Vec<(String, Vec<&str>)> = Vec::new();
+        let mut tags = Vec::new();
+        let mut tokens = Vec::new();
+        for line in text.lines().map(str::trim) {
+            match line.strip_prefix('#') {
+                Some(tag) => {
+                    let tag = tag.trim();
+                    let (name, value) = tag.split_once(' ').unwrap_or((tag, ""));
+                    tags.push((name.to_string(), value.trim().to_string()));
+                }
+                None => tokens.extend(line.split_whitespace()),
+            }
+        }
+        for token in tokens {
+            if token.starts_with(|c: char| c.is_ascii_digit()) {
+                turns.push((token.to_string(), Vec::new()));
+            } else {
+                let turn = turns
+                    .last_mut()
+                    .ok_or(format_err!("Expected a move number before {}", token))?;
+                turn.1.push(token);
+            }
+        }
+        for (i, (label, _)) in turns.iter().enumerate() {
+            let expected = turn_label(i);
+            let alternative = expected.replace('w', "g").replace('b', "s");
+            ensure!(
+                *label == expected || *label == alternative,
+                format_err!("Expected move {} but found {}", expected, label)
+            );
+        }
+        ensure!(turns.len() >= 2, format_err!("Missing setups"));
+        let parse = |steps: &[&str]| Move::parse(&steps.join(" "));
+        let mut game = Game::new(parse(&turns[0].1)?, parse(&turns[1].1)?)?;
+        for (i, (label, steps)) in turns.iter().enumerate().skip(2) {
+            if steps.is_empty() && i == turns.len() - 1 {
+                break;
+            }
+            game.play(&parse(steps)?)
+                .map_err(|e| format_err!("Move {}: {}", label, e))?;
+        }
+        game.tags = tags;
+        if game.result == EndState::Neither {
+            game.result = match game.tag("result").and_then(|r| r.chars().next()) {
+                Some('w') | Some('g') => EndState::WhiteWin,
+                Some('b') | Some('s') => EndState::BlackWin,
+                _ => EndState::Neither,
+            };
+        }
+        Ok(game)
+    }
+    /// The tags as comment lines followed by the move list, as read by
+    /// `from_move_list`
+    pub fn to_record(&self) -> String {
+        let mut record = String::new();
+        for (name, value) in self.tags.iter() {
+            record.push_str(&format!("# {} {}\n", name, value));
+        }
+        record + &self.to_move_list()
+    }
+    /// The game in the standard move list notation, one turn per line
+    pub fn to_move_list(&self) -> String {
+        let lines: Vec<_> = self
+            .moves
+            .iter()
+            .enumerate()
+            .map(|(i, mov)| format!("{} {}", turn_label(i), mov))
+            .collect();
+        lines.join("\n")
+    }
+}
+
+/// Reads every game of an archive, where records as written by
+/// `Game::to_record` are separated by blank lines
+pub fn read_games(text: &str) -> Result<Vec<Game>, Error> {
+    let mut games = Vec::new();
+    let mut record = String::new();
+    for line in text.lines().chain(std::iter::once("")) {
+        if !line.trim().is_empty() {
+            record.push_str(line);
+            record.push('\n');
+            continue;
+        }
+        if !record.is_empty() {
+            let game = Game::from_move_list(&record)
+                .map_err(|e| format_err!("Game {}: {}", games.len() + 1, e))?;
+            games.push(game);
+            record.clear();
+        }
+    }
+    Ok(games)
+}
+
+/// Plays a turn recorded by `Game::play`, passing if it was cut short
+fn replay_turn(pos: &mut Position, mov: &Move) -> EndState {
+    let side = pos.side;
+    let mut status = EndState::Neither;
+    for step in mov.steps.iter() {
+        if let Step::Remove(_, _) = step {
+            continue;
+        }
+        status = pos.do_step(*step);
+    }
+    if status == EndState::Neither && pos.side == side {
+        status = pos.do_step(Step::Pass);
+    }
+    status
+}
+
+/// The move number and side of the `index`th turn, counting the setups
+fn turn_label(index: usize) -> String {
+    let side = if index.is_multiple_of(2) { 'w' } else { 'b' };
+    format!("{}{}", index / 2 + 1, side)
 }
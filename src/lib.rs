@@ -1,7 +1,21 @@
+pub mod book;
+pub mod clock;
+pub mod database;
+pub mod encode;
+pub mod eval;
 pub mod game;
 pub mod position;
-
+pub mod rollout;
 pub mod search;
+pub mod selfplay;
+#[cfg(feature = "serde")]
+mod serialize;
+pub mod setup;
+pub mod solver;
+pub mod tablebase;
+pub mod tournament;
+pub mod transposition;
+pub mod tune;
 pub mod zobrist;
 #[cfg(test)]
 mod tests {
@@ -10,9 +24,9 @@ mod tests {
     use crate::position::{neighbors_of, Piece, Position, Side, Step};
     use crate::search;
 
-    static POS1: &'static str = include_str!("test_games/pos1.txt");
-    static POS2: &'static str = include_str!("test_games/pos2.txt");
-    static POS3: &'static str = include_str!("test_games/pos3.txt");
+    static POS1: &str = include_str!("test_games/pos1.txt");
+    static POS2: &str = include_str!("test_games/pos2.txt");
+    static POS3: &str = include_str!("test_games/pos3.txt");
     #[test]
     fn test_goal_in_two() {
         let pos = Position::from_pos_notation(POS3.to_string());
@@ -21,6 +35,1326 @@ mod tests {
         println!("{:?}", res);
     }
     #[test]
+    fn test_transposition_table() {
+        use crate::transposition::{Bound, TranspositionTable};
+        use std::sync::Arc;
+        let table = Arc::new(TranspositionTable::with_buckets(1));
+        assert_eq!(table.capacity(), 4);
+        let pos = Position::from_pos_notation(POS1.to_string()).unwrap();
+        assert!(table.probe(&pos).is_none());
+        let step = Step::from_notation("Ea4e");
+        table.store(&pos, 3, Bound::Lower, -250, Some(step));
+        let entry = table.probe(&pos).unwrap();
+        assert_eq!(entry.depth, 3);
+        assert_eq!(entry.bound, Bound::Lower);
+        assert_eq!(entry.score, -250);
+        assert_eq!(entry.best_step, Some(step));
+        // Fill the single bucket from several threads; the deepest entry survives
+        let mut positions = Vec::new();
+        for s in pos.gen_steps().into_iter().take(8) {
+            let mut next = pos.clone();
+            next.do_step(s);
+            positions.push(next);
+        }
+        let handles: Vec<_> = positions
+            .chunks(2)
+            .map(|chunk| {
+                let table = table.clone();
+                let chunk = chunk.to_vec();
+                std::thread::spawn(move || {
+                    for p in chunk.iter() {
+                        table.store(p, 1, Bound::Exact, 7, Some(Step::Pass));
+                    }
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(table.probe(&pos).unwrap().depth, 3);
+        for p in positions.iter() {
+            if let Some(entry) = table.probe(p) {
+                assert_eq!(entry.score, 7);
+                assert_eq!(entry.best_step, Some(Step::Pass));
+            }
+        }
+        table.new_search();
+        assert_eq!(table.hashfull(), 0);
+        table.store(&positions[0], 0, Bound::Exact, 0, None);
+        assert!(table.probe(&positions[0]).is_some());
+        assert_eq!(table.hashfull(), 250);
+    }
+    #[test]
+    fn test_table_eval() {
+        use crate::transposition::{Bound, TranspositionTable, WIN_SCORE};
+        use std::sync::Arc;
+        let pos = Position::from_pos_notation(POS1.to_string()).unwrap();
+        let steps = pos.gen_steps();
+        // Every step is visited, scoring the table entry of its position
+        let win_rate = |score: i32| {
+            let table = Arc::new(TranspositionTable::new(1));
+            for s in steps.iter() {
+                let mut next = pos.clone();
+                next.do_step(*s);
+                table.store(&next, 1, Bound::Exact, score, None);
+            }
+            let config = search::SearchConfig {
+                threads: 1,
+                playouts: Some(2 * steps.len() as u32),
+                shared_table: Some(table),
+                ..Default::default()
+            };
+            search::search(search::ArimaaGame::new(pos.clone()), &config).win_rate
+        };
+        let small = win_rate(50);
+        assert!(small > 0.5 && small < 0.6, "{}", small);
+        // Proven results are worth a full reward rather than a squashed score
+        assert!(win_rate(WIN_SCORE - 3) > 0.6);
+        assert!(win_rate(3 - WIN_SCORE) < 0.4);
+    }
+    #[test]
+    fn test_search_config() {
+        let pos = Position::from_pos_notation(POS3.to_string()).unwrap();
+        let config = search::SearchConfig {
+            threads: 1,
+            playouts: Some(2000),
+            seed: 7,
+            ..Default::default()
+        };
+        let first = search::search(search::ArimaaGame::new(pos.clone()), &config);
+        let second = search::search(search::ArimaaGame::new(pos.clone()), &config);
+        assert_eq!(first.visits, second.visits);
+        assert_eq!(first.playouts, 2000);
+        assert!(first.best_move.steps.len() <= 4);
+        assert_eq!(
+            &first.pv[..first.best_move.steps.len()],
+            &first.best_move.steps[..]
+        );
+        assert!(0.0 <= first.win_rate && first.win_rate <= 1.0);
+        let timed = search::SearchConfig {
+            threads: 2,
+            playouts: None,
+            time_limit: Some(std::time::Duration::from_millis(100)),
+            ..Default::default()
+        };
+        let result = search::search(search::ArimaaGame::new(pos), &timed);
+        assert!(result.playouts > 0);
+        assert!(result.elapsed >= std::time::Duration::from_millis(100));
+    }
+    #[test]
+    fn test_turn_search() {
+        use crate::position::EndState;
+        let mut pieces = [Piece::Empty; 64];
+        pieces[48] = Piece::WRabbit; // a7
+        pieces[28] = Piece::WElephant; // e4
+        pieces[55] = Piece::BRabbit; // h7
+        pieces[35] = Piece::BElephant; // d5
+        let pos = Position::from_pieces(Side::White, 4, pieces);
+        let config = search::SearchConfig {
+            threads: 1,
+            playouts: Some(200),
+            ..Default::default()
+        };
+        let turns = search::search_turns(search::ArimaaTurnGame::new(pos.clone()), &config);
+        let steps = search::search(search::ArimaaGame::new(pos.clone()), &config);
+        for best in [&turns.best_move, &steps.best_move].iter() {
+            let mut after = pos.clone();
+            let mut status = EndState::Neither;
+            for step in best.steps.iter() {
+                status = after.do_step(*step);
+            }
+            assert!(status == EndState::WhiteWin, "{:?}", best);
+        }
+        assert!(turns.win_rate > 0.9);
+        assert_eq!(turns.pv[0].steps, turns.best_move.steps);
+        assert!(turns.visits.windows(2).all(|w| w[0].1 >= w[1].1));
+    }
+    #[test]
+    fn test_turn_search_no_turns() {
+        use crate::game::Move;
+        // Any White turn keeping the cat and dog in place leaves the black
+        // rabbit frozen and blocked, so that Black has lost
+        let mut pieces = [Piece::Empty; 64];
+        pieces[56] = Piece::BRabbit; // a8
+        pieces[57] = Piece::WCat; // b8
+        pieces[48] = Piece::WDog; // a7
+        pieces[7] = Piece::WRabbit; // h1
+        let pos = Position::from_pieces(Side::White, 4, pieces);
+        let config = search::SearchConfig {
+            threads: 1,
+            playouts: Some(500),
+            ..Default::default()
+        };
+        let result = search::search_turns(search::ArimaaTurnGame::new(pos.clone()), &config);
+        let mut after = pos;
+        for step in result.best_move.steps.iter() {
+            after.do_step(*step);
+        }
+        assert_eq!(after.side, Side::Black);
+        assert!(Move::all_turns(&after).is_empty());
+        assert!(result.win_rate > 0.9, "{}", result.win_rate);
+    }
+    #[test]
+    fn test_search_session() {
+        let pos = Position::from_pos_notation(POS1.to_string()).unwrap();
+        let config = search::SearchConfig {
+            threads: 1,
+            playouts: Some(300),
+            ..Default::default()
+        };
+        let mut session = search::SearchSession::new(search::ArimaaGame::new(pos.clone()), config);
+        let first = session.search();
+        assert_eq!(first.playouts, 300);
+        // Searching again continues the same tree
+        let second = session.search();
+        assert_eq!(second.playouts, 600);
+        let mut expected = pos;
+        for step in second.best_move.steps.iter() {
+            expected.do_step(*step);
+        }
+        session.advance(&second.best_move.steps);
+        assert!(session.root().pos == expected);
+        assert_eq!(session.root().pos.side, Side::Black);
+        session.ponder();
+        assert!(session.is_pondering());
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        session.stop();
+        assert!(!session.is_pondering());
+        let reply = session.search();
+        assert!(reply.playouts > 300);
+        session.ponder();
+        session.advance(&reply.best_move.steps);
+        assert!(!session.is_pondering());
+        assert_eq!(session.root().pos.side, Side::White);
+        assert_eq!(session.search().playouts, 300);
+    }
+    #[test]
+    fn test_time_control() {
+        use crate::clock::{GameLimit, TimeAllocator, TimeControl};
+        use std::time::Duration;
+        let secs = Duration::from_secs;
+        let tc: TimeControl = "2/2/100/10/8".parse().unwrap();
+        assert_eq!(tc.per_move, secs(120));
+        assert_eq!(tc.reserve, secs(120));
+        assert_eq!(tc.percent_carry, 100);
+        assert_eq!(tc.reserve_limit, Some(secs(600)));
+        assert_eq!(tc.game_limit, Some(GameLimit::Time(secs(8 * 3600))));
+        assert_eq!(tc.max_turn, None);
+        let tc: TimeControl = "0:30/1m30s/75/0/90t/2".parse().unwrap();
+        assert_eq!(tc.per_move, secs(30));
+        assert_eq!(tc.reserve, secs(90));
+        assert_eq!(tc.reserve_limit, None);
+        assert_eq!(tc.game_limit, Some(GameLimit::Turns(90)));
+        assert_eq!(tc.max_turn, Some(secs(120)));
+        for bad in [
+            "",
+            "2/2",
+            "2/2/101",
+            "2/x/100",
+            "2/2/100/10/8/1/1",
+            "1q/2/100",
+        ]
+        .iter()
+        {
+            assert!(bad.parse::<TimeControl>().is_err(), "{}", bad);
+        }
+        // 75% of the unused 20s carries over, and overruns come out of reserve
+        let mut clock = tc.start_clock();
+        assert!(clock.update(&tc, secs(10)));
+        assert_eq!(clock.reserve, secs(105));
+        assert!(clock.update(&tc, secs(100)));
+        assert_eq!(clock.reserve, secs(35));
+        assert_eq!(clock.available(&tc), secs(65));
+        assert!(!clock.update(&tc, secs(70)));
+        assert_eq!(clock.reserve, secs(0));
+        assert_eq!(clock.turns, 3);
+        let tc: TimeControl = "2/2/100/3".parse().unwrap();
+        let mut clock = tc.start_clock();
+        clock.update(&tc, secs(0));
+        assert_eq!(clock.reserve, secs(180));
+        // Allocations stay within the clock and grow with the reserve
+        let pos = Position::from_pos_notation(POS1.to_string()).unwrap();
+        let allocator = TimeAllocator::default();
+        let fresh = allocator.allocate(&tc, &tc.start_clock(), &pos);
+        let saved = allocator.allocate(&tc, &clock, &pos);
+        assert!(fresh < saved);
+        assert!(saved <= clock.available(&tc) - allocator.safety_margin);
+        let config = allocator.configure(&search::SearchConfig::default(), &tc, &clock, &pos);
+        assert_eq!(config.time_limit, Some(saved));
+        assert_eq!(config.playouts, None);
+        let tc: TimeControl = "2/2/100/10/0:01".parse().unwrap();
+        let allocated = allocator.allocate(&tc, &tc.start_clock(), &pos);
+        assert!(allocated <= secs(60) / 60);
+    }
+    #[test]
+    fn test_encode() {
+        use crate::encode;
+        use std::collections::HashSet;
+        let mut positions = Vec::new();
+        for notation in [POS1, POS2, POS3].iter() {
+            let pos = Position::from_pos_notation(notation.to_string()).unwrap();
+            for s in pos.gen_steps() {
+                let mut next = pos.clone();
+                next.do_step(s);
+                positions.push(next);
+            }
+            positions.push(pos);
+        }
+        assert!(positions
+            .iter()
+            .any(|p| matches!(p.last_step, Some(Step::Push(_, _, _)))));
+        for pos in positions.iter() {
+            let planes = encode::encode(pos);
+            assert_eq!(planes.len(), encode::ENCODED_SIZE);
+            let decoded = encode::decode(&planes).unwrap();
+            assert!(decoded == *pos);
+            assert_eq!(decoded.current_hash, pos.current_hash);
+            assert_eq!(decoded.last_step, pos.last_step);
+            assert_eq!(encode::encode_bytes(&decoded), encode::encode_bytes(pos));
+            let steps = pos.gen_steps();
+            let indices: HashSet<_> = steps
+                .iter()
+                .map(|s| encode::policy_index(*s, pos).unwrap())
+                .collect();
+            assert_eq!(indices.len(), steps.len());
+            for s in steps.iter() {
+                let index = encode::policy_index(*s, pos).unwrap();
+                assert!(index < encode::POLICY_SIZE);
+                assert_eq!(encode::policy_step(index, pos), Some(*s));
+            }
+        }
+        // Every policy output is a step on a full board
+        let full = Position::from_pieces(Side::White, 4, [Piece::WCat; 64]);
+        for index in 0..encode::POLICY_SIZE {
+            assert!(encode::policy_step(index, &full).is_some());
+        }
+        assert_eq!(encode::policy_step(encode::POLICY_SIZE, &full), None);
+        assert_eq!(
+            encode::policy_step(encode::PASS_INDEX, &full),
+            Some(Step::Pass)
+        );
+        assert!(encode::decode(&[0.0; 10]).is_err());
+    }
+    #[test]
+    fn test_selfplay() {
+        use crate::selfplay::{
+            self, GameReader, GameRecord, GameWriter, PositionRecord, SelfPlayConfig,
+        };
+        let config = SelfPlayConfig {
+            games: 3,
+            threads: 2,
+            seed: 11,
+            search: search::SearchConfig {
+                playouts: Some(30),
+                ..Default::default()
+            },
+            max_turns: 3,
+            ..Default::default()
+        };
+        let mut parallel = Vec::new();
+        selfplay::generate(&config, &mut parallel).unwrap();
+        let mut serial = Vec::new();
+        let single = SelfPlayConfig {
+            threads: 1,
+            ..config.clone()
+        };
+        selfplay::generate(&single, &mut serial).unwrap();
+        assert!(parallel == serial);
+        let games: Vec<_> = GameReader::new(&parallel[..])
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(games.len(), 3);
+        let mut rng = rand::SeedableRng::seed_from_u64(11);
+        let setup = selfplay::random_setup(&mut rng);
+        assert_eq!(setup.placement[0].count_ones(), 16);
+        assert_eq!(setup.placement[1].count_ones(), 16);
+        for game in games.iter() {
+            assert!(!game.positions.is_empty());
+            let mut pos = game.positions[0].position.clone();
+            for (i, record) in game.positions.iter().enumerate() {
+                assert!(record.position == pos);
+                assert_eq!(record.position.current_hash, pos.current_hash);
+                assert_eq!(record.position.plies, pos.plies);
+                assert!(record.visits.iter().map(|(_, v)| v).sum::<u64>() >= 30);
+                if let Some(next) = game.positions.get(i + 1) {
+                    // Replay the step that led to the next position
+                    let step = record
+                        .visits
+                        .iter()
+                        .map(|(s, _)| *s)
+                        .find(|s| {
+                            let mut after = pos.clone();
+                            after.do_step(*s);
+                            after == next.position
+                        })
+                        .unwrap();
+                    pos.do_step(step);
+                }
+            }
+        }
+        assert!(GameReader::new(&b"nope"[..]).is_err());
+        // The written visits are exactly those of the search
+        let pos = games[0].positions[0].position.clone();
+        let searched = search::search(
+            search::ArimaaGame::new(pos.clone()),
+            &search::SearchConfig {
+                playouts: Some(200),
+                ..Default::default()
+            },
+        );
+        let mut record = GameRecord {
+            positions: vec![PositionRecord {
+                position: pos.clone(),
+                visits: searched.visits.clone(),
+            }],
+            result: position::EndState::Neither,
+        };
+        let mut out = Vec::new();
+        GameWriter::new(&mut out).unwrap().write(&record).unwrap();
+        let read = GameReader::new(&out[..]).unwrap().next().unwrap().unwrap();
+        assert_eq!(read.positions[0].visits, searched.visits);
+        // Visits that don't fit or steps without an index are errors
+        let mut out = Vec::new();
+        let mut writer = GameWriter::new(&mut out).unwrap();
+        record.positions[0].visits[0].1 = u64::from(u32::MAX) + 1;
+        assert!(writer.write(&record).is_err());
+        record.positions[0].visits[0] = (Step::Place(Piece::WRabbit, 0), 1);
+        assert!(writer.write(&record).is_err());
+        // Only the header was written
+        assert_eq!(out.len(), 5);
+    }
+    #[test]
+    fn test_evaluators() {
+        use crate::eval::{self, Evaluate, HandcraftedEval, LinearEval};
+        use std::sync::Arc;
+        let pos = Position::from_pos_notation(POS1.to_string()).unwrap();
+        let flipped = pos.flip_colors();
+        for e in [&HandcraftedEval as &dyn Evaluate, &LinearEval::default()].iter() {
+            let value = e.value(&pos);
+            assert!((-1.0..=1.0).contains(&value));
+            // The same position from the other side's point of view
+            assert!((value - e.value(&flipped)).abs() < 1e-9);
+        }
+        let linear = LinearEval::default();
+        let text = linear.to_string();
+        assert_eq!(text.lines().count(), eval::NUM_FEATURES);
+        assert!(text.parse::<LinearEval>().unwrap() == linear);
+        let path = std::env::temp_dir().join("arimaa_linear_eval_test.txt");
+        linear.save(&path).unwrap();
+        assert!(LinearEval::load(&path).unwrap() == linear);
+        std::fs::remove_file(&path).unwrap();
+        let commented = format!("# tuned\n\n{}", text);
+        assert!(commented.parse::<LinearEval>().is_ok());
+        assert!("rabbit 1".parse::<LinearEval>().is_err());
+        assert!(format!("{}unknown 1", text).parse::<LinearEval>().is_err());
+        // A custom evaluator steers the search through its priors
+        struct PreferPass;
+        impl Evaluate for PreferPass {
+            fn value(&self, _: &Position) -> f64 {
+                0.0
+            }
+            fn priors(&self, _: &Position, steps: &[Step]) -> Option<Vec<f64>> {
+                Some(
+                    steps
+                        .iter()
+                        .map(|s| if *s == Step::Pass { 1.0 } else { 0.0 })
+                        .collect(),
+                )
+            }
+        }
+        let mut pieces = [Piece::Empty; 64];
+        pieces[28] = Piece::WElephant; // e4
+        pieces[35] = Piece::BElephant; // d5
+        let mut mid_turn = Position::from_pieces(Side::White, 4, pieces);
+        mid_turn.do_step(Step::from_notation("Ee4e"));
+        let config = search::SearchConfig {
+            threads: 1,
+            playouts: Some(50),
+            evaluator: Some(Arc::new(PreferPass)),
+            ..Default::default()
+        };
+        let result = search::search(search::ArimaaGame::new(mid_turn), &config);
+        assert_eq!(result.visits[0].0, Step::Pass);
+        let config = search::SearchConfig {
+            evaluator: Some(Arc::new(HandcraftedEval)),
+            ..config
+        };
+        let result = search::search(search::ArimaaGame::new(pos), &config);
+        assert!(0.0 < result.win_rate && result.win_rate < 1.0);
+    }
+    static GAME: &str = "1w Ra1 Rb1 Rc1 Rd1 Re1 Rf1 Rg1 Rh1 Ha2 Db2 Cc2 Md2 Ee2 Cf2 Dg2 Hh2
+1b ra8 rb8 rc8 rd8 re8 rf8 rg8 rh8 ha7 db7 cc7 ed7 me7 cf7 dg7 hh7
+2w Cc2n Ee2n Ee3n Ee4n
+2b me7s
+3w me6n Ee5n
+3b";
+    #[test]
+    fn test_game_record() {
+        use crate::game::Game;
+        let game = Game::from_move_list(GAME).unwrap();
+        assert_eq!(game.moves.len(), 5);
+        // The cat stepping into the unguarded trap is recorded as captured
+        assert_eq!(game.moves[2].to_string(), "Cc2n Cc3x Ee2n Ee3n Ee4n");
+        // The camel is pushed, although written as an ordinary step
+        assert_eq!(game.moves[4].steps[0], Step::Push(Piece::BCamel, 44, 52));
+        assert_eq!(game.position.side, Side::Black);
+        assert_eq!(game.position.pieces[44], Piece::WElephant);
+        let text = game.to_move_list();
+        let reparsed = Game::from_move_list(&text).unwrap();
+        assert_eq!(reparsed.to_move_list(), text);
+        assert!(reparsed.position == game.position);
+        let positions = game.positions();
+        assert_eq!(positions.len(), 4);
+        assert!(positions[3] == game.position);
+        // Gold and silver labels are accepted
+        let gold = GAME.replace("1w", "1g").replace("1b", "1s");
+        assert!(Game::from_move_list(&gold).is_ok());
+        let mut next = game.clone();
+        assert!(next.play(&Move::parse("ha7s").unwrap()).is_ok());
+        let mut bad = game.clone();
+        assert!(bad.play(&Move::parse("ra8e").unwrap()).is_err());
+        assert!(bad
+            .play(&Move::parse("ha7s ha6s ha5s ha4s hh7s").unwrap())
+            .is_err());
+        assert!(bad.play(&Move::parse("").unwrap()).is_err());
+        assert!(bad.play(&Move::parse("ha7s ha6n").unwrap()).is_err());
+        assert!(Game::from_move_list(&GAME.replace("Hh2", "Hh3")).is_err());
+        assert!(Game::from_move_list(&GAME.replace("Hh2", "Dh2")).is_err());
+        assert!(Game::from_move_list(&GAME.replace("2b", "3b")).is_err());
+        assert!("Ea8n".parse::<Step>().is_err());
+        assert!("Xa1n".parse::<Step>().is_err());
+    }
+    #[test]
+    fn test_takeback() {
+        use crate::game::Game;
+        let mut game = Game::from_move_list(GAME).unwrap();
+        let before = game.clone();
+        let mov = Move::parse("ha7s ha6s").unwrap();
+        game.play(&mov).unwrap();
+        assert_eq!(game.takeback().unwrap().to_string(), "ha7s ha6s");
+        assert!(game.position == before.position);
+        assert_eq!(game.to_move_list(), before.to_move_list());
+        // Replaying from the start restores the repetition history
+        game.play(&mov).unwrap();
+        assert!(game.play(&Move::parse("Ee6s").unwrap()).is_ok());
+        while game.takeback().is_some() {}
+        assert_eq!(game.moves.len(), 2);
+        assert!(game.position == game.start);
+        // The engine picks a complete legal turn
+        let config = search::SearchConfig {
+            threads: 1,
+            playouts: Some(200),
+            ..Default::default()
+        };
+        let reply = search::choose_turn(&game.position, &config);
+        assert!(!reply.steps.is_empty() && reply.steps.len() <= 4);
+        game.play(&reply).unwrap();
+        assert_eq!(game.position.side, Side::Black);
+        // Highlighting only adds escape codes around the chosen squares
+        let highlighted = game.position.to_highlighted_notation(1 << 12 | 1 << 20);
+        assert_eq!(highlighted.matches("\x1b[7m").count(), 2);
+        let plain = highlighted.replace("\x1b[7m", "").replace("\x1b[0m", "");
+        assert_eq!(plain, game.position.to_pos_notation());
+    }
+    #[test]
+    fn test_match() {
+        use crate::clock::{Clock, TimeControl};
+        use crate::game::Game;
+        use crate::position::EndState;
+        use crate::tournament::{self, MatchConfig, MatchStats, Player};
+        use failure::Error;
+        /// Moves its first piece able to step
+        struct Stepper;
+        impl Player for Stepper {
+            fn name(&self) -> String {
+                "stepper".to_string()
+            }
+            fn play_turn(
+                &mut self,
+                game: &Game,
+                _: &TimeControl,
+                _: &[Clock; 2],
+            ) -> Result<Move, Error> {
+                let pos = &game.position;
+                let own = pos.placement[pos.side as usize];
+                let step = pos.gen_steps().into_iter().find(|s| match s {
+                    Step::Move(_, source, _) => own & (1 << source) != 0,
+                    _ => false,
+                });
+                Ok(Move::new(step.into_iter().collect()))
+            }
+        }
+        struct Resigner;
+        impl Player for Resigner {
+            fn name(&self) -> String {
+                "resigner".to_string()
+            }
+            fn play_turn(
+                &mut self,
+                _: &Game,
+                _: &TimeControl,
+                _: &[Clock; 2],
+            ) -> Result<Move, Error> {
+                Ok(Move::new(vec![]))
+            }
+        }
+        let config = MatchConfig {
+            games: 3,
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        let stats = tournament::run_match(&config, &mut Stepper, &mut Resigner, &mut out).unwrap();
+        assert_eq!(
+            stats,
+            MatchStats {
+                wins: 3,
+                losses: 0,
+                draws: 0
+            }
+        );
+        let text = String::from_utf8(out).unwrap();
+        let games: Vec<_> = text.trim().split("\n\n").collect();
+        assert_eq!(games.len(), 3);
+        // Colors alternate and each pair of games shares its setups
+        let first = Game::from_move_list(games[0]).unwrap();
+        let second = Game::from_move_list(games[1]).unwrap();
+        assert_eq!(first.moves.len(), 3);
+        assert_eq!(second.moves.len(), 2);
+        assert!(first.start == second.start);
+        assert!(games[1].contains("# white resigner"));
+        assert!(games[1].contains("# result b resigner resigned"));
+        // Without any time every turn overruns the clock
+        let config = MatchConfig {
+            games: 2,
+            time_control: "0/0/0".parse().unwrap(),
+            ..Default::default()
+        };
+        let stats =
+            tournament::run_match(&config, &mut Stepper, &mut Stepper, std::io::sink()).unwrap();
+        assert_eq!((stats.wins, stats.losses), (1, 1));
+        let setup = tournament::setup_moves(&Game::from_move_list(GAME).unwrap().start);
+        let config = MatchConfig {
+            time_control: "1/0/0".parse().unwrap(),
+            max_turns: 4,
+            ..config
+        };
+        let played = tournament::play_game(&setup, &mut Stepper, &mut Stepper, &config).unwrap();
+        assert!(played.game.result == EndState::Neither);
+        assert_eq!(played.game.moves.len(), 6);
+        // Elo from the score, with a confidence interval around it
+        let stats = MatchStats {
+            wins: 6,
+            losses: 2,
+            draws: 2,
+        };
+        let (elo, lower, upper) = stats.elo().unwrap();
+        assert!((elo - 147.2).abs() < 0.1);
+        assert!(lower < elo && elo < upper);
+        assert!(MatchStats::default().elo().is_none());
+        // Lopsided scores give infinite bounds rather than NaN
+        let lopsided = MatchStats {
+            wins: 9,
+            losses: 1,
+            draws: 0,
+        };
+        let (elo, lower, upper) = lopsided.elo().unwrap();
+        assert!((elo - 381.7).abs() < 0.1);
+        assert!(lower.is_finite() && lower < elo);
+        assert_eq!(upper, f64::INFINITY);
+        let perfect = MatchStats {
+            wins: 5,
+            losses: 0,
+            draws: 0,
+        };
+        assert_eq!(
+            perfect.elo(),
+            Some((f64::INFINITY, f64::INFINITY, f64::INFINITY))
+        );
+        let (elo, lower, upper) = MatchStats {
+            wins: 0,
+            losses: 3,
+            draws: 0,
+        }
+        .elo()
+        .unwrap();
+        assert!(elo == lower && lower == upper && elo == f64::NEG_INFINITY);
+    }
+    #[test]
+    fn test_opening_book() {
+        use crate::book::OpeningBook;
+        use crate::game::{self, Game};
+        use crate::position::{EndState, Symmetry};
+        use std::sync::Arc;
+        let mirror = |mov: &Move| {
+            Move::new(
+                mov.steps
+                    .iter()
+                    .map(|s| Symmetry::Mirror.step(*s))
+                    .collect(),
+            )
+        };
+        let first = Game::from_move_list(&format!("# result w\n{}", GAME)).unwrap();
+        assert!(first.result == EndState::WhiteWin);
+        assert_eq!(first.tag("result"), Some("w"));
+        let mut second = Game::new(mirror(&first.moves[0]), mirror(&first.moves[1])).unwrap();
+        for mov in first.moves[2..].iter() {
+            second.play(&mirror(mov)).unwrap();
+        }
+        second.result = EndState::BlackWin;
+        second.tags = vec![("result".to_string(), "b".to_string())];
+        // Archives hold records separated by blank lines
+        let archive = format!("{}\n\n\n{}\n", first.to_record(), second.to_record());
+        let games = game::read_games(&archive).unwrap();
+        assert_eq!(games.len(), 2);
+        assert!(games[1].result == EndState::BlackWin);
+        assert!(game::read_games("1w Ra1").is_err());
+        let book = OpeningBook::from_games(&games, 10);
+        // Mirrored games share their positions
+        assert_eq!(book.len(), 3);
+        let moves = book.probe(&first.start);
+        assert_eq!(moves.len(), 1);
+        assert_eq!(moves[0].count, 2);
+        assert!((moves[0].score - 0.5).abs() < 1e-9);
+        assert_eq!(moves[0].steps.to_string(), "Cc2n Ee2n Ee3n Ee4n");
+        let moves = book.probe(&second.start);
+        assert_eq!(moves[0].steps.to_string(), "Cf2n Ed2n Ed3n Ed4n");
+        assert!(book.probe(&first.position).is_empty());
+        let mut bytes = Vec::new();
+        book.write(&mut bytes).unwrap();
+        assert!(OpeningBook::read(&bytes[..]).unwrap() == book);
+        assert!(OpeningBook::read(&bytes[..bytes.len() - 1]).is_err());
+        assert!(book.choose(&first.start, 3, 0).is_none());
+        // A turn the book can't hold is skipped without losing the turns
+        // after it. Teleporting the elephant has no step index.
+        let mut skipping = first.clone();
+        skipping
+            .moves
+            .push(Move::new(vec![Step::Move(Piece::BElephant, 51, 35)]));
+        skipping
+            .moves
+            .push(Move::new(vec![Step::Move(Piece::WRabbit, 2, 10)]));
+        let mut partial = OpeningBook::new();
+        assert_eq!(partial.add_game(&skipping, 10), 1);
+        let after = &skipping.positions()[4];
+        assert_eq!(partial.probe(after)[0].steps.to_string(), "Rc1n");
+        assert!(partial.probe(&skipping.positions()[3]).is_empty());
+        // The engine plays from the book without searching
+        let config = search::SearchConfig {
+            playouts: Some(1),
+            book: Some(Arc::new(book)),
+            ..Default::default()
+        };
+        let reply = search::choose_turn(&second.start, &config);
+        assert_eq!(reply.to_string(), "Cf2n Ed2n Ed3n Ed4n");
+        let mut replayed = Game::new(second.moves[0].clone(), second.moves[1].clone()).unwrap();
+        replayed.play(&reply).unwrap();
+        assert_eq!(replayed.moves[2].to_string(), second.moves[2].to_string());
+        // A corrupt or colliding entry is ignored instead of played. A book of
+        // one turn is laid out as a 9 byte header, the key and turn count,
+        // the count and score, the reached hash, then the number of steps
+        // and each step.
+        let mut bytes = Vec::new();
+        OpeningBook::from_games(&games[..1], 1)
+            .write(&mut bytes)
+            .unwrap();
+        assert_eq!(bytes.len(), 36 + 2 * bytes[35] as usize);
+        let start = &first.start;
+        let illegal = (0..position::PASS_STEP_INDEX)
+            .find(|i| {
+                [start.clone(), start.mirror()]
+                    .iter()
+                    .all(|p| Step::from_index(*i, p).is_some_and(|s| !p.gen_steps().contains(&s)))
+            })
+            .unwrap() as u16;
+        let first_step = [bytes[36], bytes[37]];
+        let corrupt = [
+            // An illegal first step
+            [&bytes[..36], &illegal.to_le_bytes(), &bytes[38..]].concat(),
+            // A fifth step after the turn has ended
+            [&bytes[..35], &[5], &bytes[36..], &first_step].concat(),
+        ];
+        for bytes in corrupt.iter() {
+            let book = Arc::new(OpeningBook::read(&bytes[..]).unwrap());
+            assert!(book.probe(start).is_empty());
+            let config = search::SearchConfig {
+                playouts: Some(10),
+                book: Some(book),
+                ..Default::default()
+            };
+            let reply = search::choose_turn(start, &config);
+            let mut replayed = Game::new(first.moves[0].clone(), first.moves[1].clone()).unwrap();
+            replayed.play(&reply).unwrap();
+        }
+    }
+    #[test]
+    fn test_setup_chooser() {
+        use crate::game::Game;
+        use crate::setup::{setup_score, SetupChooser};
+        let chooser = SetupChooser::default();
+        let white = chooser.choose(Side::White, None);
+        let black = chooser.choose(Side::Black, Some(&white));
+        let game = Game::new(white.clone(), black.clone()).unwrap();
+        // Rabbits stay back and the trap squares are guarded by other pieces
+        assert_eq!(game.position.bitboards[Piece::WRabbit as usize], 0xFF);
+        assert_eq!(game.position.bitboards[Piece::BRabbit as usize], 0xFF << 56);
+        let elephant = game.position.bitboards[Piece::WElephant as usize];
+        assert!(elephant == 1 << 11 || elephant == 1 << 12); // d2 or e2
+        let standard = Game::from_move_list(GAME).unwrap();
+        let best = setup_score(Side::White, &white, None).unwrap();
+        assert!(best >= setup_score(Side::White, &standard.moves[0], None).unwrap());
+        assert!(setup_score(Side::White, &standard.moves[1], None).is_none());
+        assert!(setup_score(Side::Black, &standard.moves[1], Some(&white)).is_some());
+        let mut short = white.clone();
+        short.steps.pop();
+        assert!(setup_score(Side::White, &short, None).is_none());
+        // Black's camel avoids the wing of White's elephant
+        let wing_elephant =
+            Move::parse("Ra1 Rb1 Rc1 Rd1 Re1 Rf1 Rg1 Rh1 Ea2 Db2 Cc2 Md2 He2 Cf2 Dg2 Hh2").unwrap();
+        let reply = chooser.choose(Side::Black, Some(&wing_elephant));
+        let camel = reply.steps.iter().find_map(|s| match s {
+            Step::Place(Piece::BCamel, sq) => Some(sq % 8),
+            _ => None,
+        });
+        assert!(camel.unwrap() >= 4);
+        // The choice is repeatable, and varies with randomness and the seed
+        assert_eq!(
+            chooser.choose(Side::White, None).to_string(),
+            white.to_string()
+        );
+        let setups: std::collections::HashSet<_> = (0..8)
+            .map(|seed| {
+                let chooser = SetupChooser {
+                    randomness: 1.0,
+                    seed,
+                    ..Default::default()
+                };
+                chooser.choose(Side::White, None).to_string()
+            })
+            .collect();
+        assert!(setups.len() > 1);
+        // The engine sets up with the chooser
+        let config = search::SearchConfig::default();
+        let setup = search::choose_turn(&Position::new_empty(), &config);
+        assert_eq!(setup.to_string(), white.to_string());
+    }
+    #[test]
+    fn test_perft() {
+        use crate::game::{perft, Move};
+        let mut pieces = [Piece::Empty; 64];
+        pieces[0] = Piece::WElephant; // a1
+        pieces[63] = Piece::BRabbit; // h8
+        let pos = Position::from_pieces(Side::White, 4, pieces);
+        assert_eq!(perft(&pos, 0), 1);
+        let turns = Move::all_turns(&pos);
+        assert_eq!(perft(&pos, 1), turns.len() as u64);
+        let expected: u64 = turns.iter().map(|(_, p)| perft(p, 1)).sum();
+        assert_eq!(perft(&pos, 2), expected);
+        // A rabbit reaching goal ends the game, counting as one sequence
+        pieces[55] = Piece::WRabbit; // h7
+        pieces[63] = Piece::Empty;
+        pieces[8] = Piece::BRabbit; // a2
+        let pos = Position::from_pieces(Side::White, 4, pieces);
+        let turns = Move::all_turns(&pos);
+        let wins = turns.iter().filter(|(_, p)| p.side == Side::White).count();
+        assert!(wins > 0);
+        let continued: u64 = turns
+            .iter()
+            .filter(|(_, p)| p.side == Side::Black)
+            .map(|(_, p)| Move::all_turns(p).len() as u64)
+            .sum();
+        assert_eq!(perft(&pos, 2), wins as u64 + continued);
+    }
+    #[test]
+    fn test_game_database() {
+        use crate::database::{GameDatabase, Material, Query};
+        use crate::game::Game;
+        use crate::position::EndState;
+        let first = Game::from_move_list(&format!("# white Alice\n# black Bob\n{}", GAME)).unwrap();
+        // The second game leaves the first after White's second turn
+        let mut second = Game::from_move_list(&format!(
+            "# white bob\n# black Carol\n{}",
+            GAME.replace("3w me6n Ee5n\n3b", "")
+        ))
+        .unwrap();
+        second.play(&Move::parse("Hh2n").unwrap()).unwrap();
+        second.play(&Move::parse("cc7s").unwrap()).unwrap();
+        second.result = EndState::BlackWin;
+        let path = std::env::temp_dir().join("arimaa_game_database_test.db");
+        let _ = std::fs::remove_file(&path);
+        let mut database = GameDatabase::open(&path).unwrap();
+        assert!(database.is_empty());
+        assert_eq!(database.add_game(first.clone()).unwrap(), 0);
+        assert_eq!(database.add_game(second.clone()).unwrap(), 1);
+        drop(database);
+        let mut database = GameDatabase::open(&path).unwrap();
+        assert_eq!(database.len(), 2);
+        assert_eq!(database.game(0).unwrap().to_record(), first.to_record());
+        assert_eq!(database.game(1).unwrap().to_record(), second.to_record());
+        assert!(database.game(1).unwrap().result == EndState::BlackWin);
+        // Games reaching a board, whatever the steps left in the query
+        let shared = first.positions()[1].clone();
+        let query = |q: Query| database.search(&q);
+        assert_eq!(
+            query(Query {
+                position: Some(shared.clone()),
+                ..Default::default()
+            }),
+            vec![0, 1]
+        );
+        let mut part_way = first.positions()[3].clone();
+        part_way.steps_left = 1;
+        assert_eq!(
+            query(Query {
+                position: Some(part_way),
+                ..Default::default()
+            }),
+            vec![0]
+        );
+        // Players are matched without regard to case
+        let by = |name: &str| Query {
+            player: Some(name.to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(by("BOB")), vec![0, 1]);
+        assert_eq!(query(by("carol")), vec![1]);
+        assert!(query(by("Dave")).is_empty());
+        // Only the second game captured a black cat, and no elephant was lost
+        let captured = |pieces: &[Piece]| Query {
+            captured: pieces.to_vec(),
+            ..Default::default()
+        };
+        assert_eq!(query(captured(&[Piece::WCat])), vec![0, 1]);
+        assert_eq!(query(captured(&[Piece::BCat])), vec![1]);
+        assert!(query(captured(&[Piece::WElephant, Piece::BElephant])).is_empty());
+        let material: Material = "EMHHDDCRRRRRRRR emhhddcrrrrrrrr".parse().unwrap();
+        assert_eq!(Material::new(&second.position), material);
+        assert_eq!(material.to_string(), "EMHHDDCRRRRRRRRemhhddcrrrrrrrr");
+        assert!("EE".parse::<Material>().is_err());
+        let combined = Query {
+            material: Some(material),
+            player: Some("bob".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(combined.clone()), vec![1]);
+        assert!(query(Query {
+            player: Some("Alice".to_string()),
+            ..combined
+        })
+        .is_empty());
+        // Games added later are appended to the file
+        database.add_game(first).unwrap();
+        drop(database);
+        assert_eq!(GameDatabase::open(&path).unwrap().len(), 3);
+        std::fs::write(&path, b"ARDB\x01\x05\x00").unwrap();
+        assert!(GameDatabase::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn test_goal_solver() {
+        use crate::game::Game;
+        use crate::solver::{GoalSolver, RaceResult};
+        let mut pieces = [Piece::Empty; 64];
+        pieces[19] = Piece::WRabbit; // d3
+        pieces[63] = Piece::BRabbit; // h8
+        let pos = Position::from_pieces(Side::White, 4, pieces);
+        let mut solver = GoalSolver::new();
+        // Five steps from goal takes two turns, which Black can't prevent
+        assert!(matches!(
+            solver.solve(&pos, 1).unwrap(),
+            RaceResult::Unknown
+        ));
+        let line = match solver.solve(&pos, 3).unwrap() {
+            RaceResult::Win(line) => line,
+            _ => panic!("expected a win"),
+        };
+        assert_eq!(line.len(), 3);
+        assert!(solver.wins(&pos, 2));
+        assert!(!solver.wins(&pos, 1));
+        // The line is a legal game ending in White's win
+        let mut board = pos.clone();
+        let mut status = position::EndState::Neither;
+        for mov in line.iter() {
+            assert!(status == position::EndState::Neither);
+            let side = board.side;
+            for step in mov.steps.iter() {
+                status = board.do_step(*step);
+            }
+            // Turns of four steps end by themselves
+            if status == position::EndState::Neither && board.side == side {
+                status = board.do_step(Step::Pass);
+            }
+        }
+        assert!(status == position::EndState::WhiteWin);
+        // With Black to move, Black's rabbit is two turns from goal too and
+        // wins first
+        let black = Position::from_pieces(Side::Black, 4, pieces);
+        match solver.solve(&black, 2).unwrap() {
+            RaceResult::Win(line) => assert_eq!(line.len(), 3),
+            r => panic!("expected a win, found {:?}", r),
+        }
+        // Black can't stop a rabbit two steps from goal
+        pieces[19] = Piece::Empty;
+        pieces[43] = Piece::WRabbit; // d6
+        let black = Position::from_pieces(Side::Black, 4, pieces);
+        match solver.solve(&black, 2).unwrap() {
+            RaceResult::Loss(line) => assert_eq!(line.len(), 2),
+            r => panic!("expected a loss, found {:?}", r),
+        }
+        // A rabbit a step from goal wins at once
+        pieces[43] = Piece::Empty;
+        pieces[51] = Piece::WRabbit; // d7
+        let pos = Position::from_pieces(Side::White, 4, pieces);
+        match solver.solve(&pos, 3).unwrap() {
+            RaceResult::Win(line) => assert_eq!(line[0].to_string(), "Rd7n"),
+            _ => panic!("expected a win"),
+        }
+        // Positions with many pieces or part way through a turn are refused
+        let game = Game::from_move_list(GAME).unwrap();
+        assert!(solver.solve(&game.position, 1).is_err());
+        let mut part_way = pos.clone();
+        part_way.do_step(Step::Move(Piece::WRabbit, 51, 50));
+        assert!(solver.solve(&part_way, 1).is_err());
+    }
+    #[test]
+    fn test_tablebase() {
+        use crate::database::Material;
+        use crate::solver::GoalSolver;
+        use crate::tablebase::{Outcome, Tablebases};
+        use std::sync::Arc;
+        let mut tablebases = Tablebases::new();
+        assert!(tablebases.generate("E".parse().unwrap()).is_err());
+        assert!(tablebases.generate("ERer".parse().unwrap()).is_err());
+        let material: Material = "Rr".parse().unwrap();
+        tablebases.generate(material).unwrap();
+        let tablebases = Arc::new(tablebases);
+        let table = tablebases.table(&material).unwrap();
+        assert_eq!(table.len(), 2 * 64 * 64);
+        let mut pieces = [Piece::Empty; 64];
+        pieces[19] = Piece::WRabbit; // d3
+        pieces[63] = Piece::BRabbit; // h8
+        let white = Position::from_pieces(Side::White, 4, pieces);
+        let black = Position::from_pieces(Side::Black, 4, pieces);
+        assert_eq!(tablebases.probe(&white), Some(Outcome::Win(2)));
+        assert_eq!(tablebases.probe(&black), Some(Outcome::Win(2)));
+        // The index function round trips valid positions
+        let index = table.index(&white).unwrap();
+        assert!(table.position(index).unwrap() == white);
+        // Rabbits on their goal can't start a turn
+        pieces[19] = Piece::Empty;
+        pieces[59] = Piece::WRabbit; // d8
+        let index = table.index(&Position::from_pieces(Side::White, 4, pieces));
+        assert!(table.position(index.unwrap()).is_none());
+        // Results agree with the goal-race solver
+        let mut solver = GoalSolver::new();
+        for index in (0..table.len()).step_by(97) {
+            let pos = match table.position(index) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            match table.value(index).unwrap() {
+                Outcome::Win(n) if n <= 3 => {
+                    assert!(solver.wins(&pos, n) && !solver.wins(&pos, n - 1));
+                }
+                Outcome::Loss(n) if n <= 3 => {
+                    assert!(solver.loses(&pos, n));
+                    assert!(n == 0 || !solver.loses(&pos, n - 1));
+                }
+                _ => assert!(!solver.wins(&pos, 3) && !solver.loses(&pos, 3)),
+            }
+        }
+        // The best turn wins as fast as possible
+        let mov = tablebases.best_turn(&white).unwrap();
+        let mut next = white.clone();
+        for step in mov.steps.iter() {
+            next.do_step(*step);
+        }
+        if next.side == Side::White {
+            next.do_step(Step::Pass);
+        }
+        assert_eq!(tablebases.probe(&next), Some(Outcome::Loss(1)));
+        // The engine plays from the tables without searching
+        let config = search::SearchConfig {
+            tablebases: Some(tablebases.clone()),
+            playouts: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(
+            search::choose_turn(&white, &config).to_string(),
+            mov.to_string()
+        );
+        // Tables are saved one file per material
+        let dir = std::env::temp_dir().join("arimaa_tablebase_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        tablebases.save(&dir).unwrap();
+        assert!(dir.join("R-r.tb").exists());
+        let loaded = Tablebases::load(&dir).unwrap();
+        assert_eq!(loaded.materials(), vec![material]);
+        for index in 0..table.len() {
+            assert_eq!(
+                loaded.table(&material).unwrap().value(index),
+                table.value(index)
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn test_tablebase_three_pieces() {
+        use crate::game::Move;
+        use crate::tablebase::{Outcome, Tablebases};
+        let mut tablebases = Tablebases::new();
+        let material = "RRr".parse().unwrap();
+        tablebases.generate(material).unwrap();
+        assert_eq!(tablebases.materials().len(), 2);
+        let table = tablebases.table(&material).unwrap();
+        assert_eq!(table.len(), 2 * 64 * 64 * 64);
+        // Each result follows from the results reached by the turns played
+        for index in (0..table.len()).step_by(499) {
+            let pos = match table.position(index) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            let turns = Move::all_turns(&pos);
+            let results: Vec<_> = turns
+                .iter()
+                .filter(|(_, next)| next.side != pos.side)
+                .map(|(_, next)| tablebases.probe(next).unwrap())
+                .collect();
+            let fastest_win = results
+                .iter()
+                .filter_map(|r| match r {
+                    Outcome::Loss(n) => Some(n + 1),
+                    _ => None,
+                })
+                .min();
+            let longest_loss = results
+                .iter()
+                .map(|r| match r {
+                    Outcome::Win(n) => Some(*n),
+                    _ => None,
+                })
+                .try_fold(0, |longest, n| n.map(|n| longest.max(n)));
+            let expected = if results.len() < turns.len() {
+                Outcome::Win(1)
+            } else if let Some(n) = fastest_win {
+                Outcome::Win(n)
+            } else if let Some(n) = longest_loss {
+                Outcome::Loss(n)
+            } else {
+                Outcome::Draw
+            };
+            assert_eq!(
+                table.value(index),
+                Some(expected),
+                "{}",
+                pos.to_pos_notation()
+            );
+        }
+    }
+    #[test]
+    fn test_packing() {
+        use crate::game::{Game, Move};
+        let game = Game::from_move_list(GAME).unwrap();
+        // Positions after setup pack into 28 bytes and round trip exactly
+        let mut positions = game.positions();
+        let start = &positions[0];
+        let bytes = start.to_bytes();
+        assert_eq!(bytes.len(), 28);
+        let mut pushing = Position::from_pos_notation(POS1.to_string()).unwrap();
+        let push = pushing
+            .gen_steps()
+            .into_iter()
+            .find(|s| matches!(s, Step::Push(_, _, _)))
+            .unwrap();
+        pushing.do_step(push);
+        positions.push(pushing.clone());
+        positions.push(Position::new_empty());
+        for p in positions.iter() {
+            let read = Position::from_bytes(&p.to_bytes()).unwrap();
+            assert!(read == *p);
+            assert_eq!(read.current_hash, p.current_hash);
+            assert_eq!(read.plies, p.plies);
+            assert_eq!(read.to_small_notation(), p.to_small_notation());
+        }
+        // Corrupt or truncated positions are rejected
+        let bytes = pushing.to_bytes();
+        for corrupt in [
+            &bytes[..bytes.len() - 1],
+            &[&[2], &bytes[1..]].concat(),
+            &[&bytes[..1], &[bytes[1] | 0x80], &bytes[2..]].concat(),
+            &[&bytes[..12], &[7], &bytes[13..]].concat(),
+            &[&bytes[..], &[0]].concat(),
+            &[&bytes[..1], &[bytes[1] & !0xE], &bytes[2..]].concat(),
+        ]
+        .iter()
+        {
+            assert!(Position::from_bytes(corrupt).is_err());
+        }
+        // So are impossible piece counts, such as 32 elephants
+        let start_bytes = positions[0].to_bytes();
+        let elephants = [&start_bytes[..12], &[0x66; 16]].concat();
+        assert!(Position::from_bytes(&start_bytes).is_ok());
+        assert!(Position::from_bytes(&elephants).is_err());
+        assert!(positions.iter().all(|p| p.to_bytes().len() <= 31));
+        // Moves round trip, including setups, pushes and pulls, and captures
+        // are worked out again when the game is replayed
+        let unpack = |mov: &Move, pos: &Position| {
+            let read = Move::from_bytes(&mov.to_bytes(pos).unwrap(), pos).unwrap();
+            assert!(read.steps.iter().all(|s| !matches!(s, Step::Remove(_, _))));
+            read
+        };
+        let empty = Position::new_empty();
+        let mut replayed = Game::new(
+            unpack(&game.moves[0], &empty),
+            unpack(&game.moves[1], &empty),
+        )
+        .unwrap();
+        for mov in game.moves[2..].iter() {
+            let read = unpack(mov, &replayed.position);
+            replayed.play(&read).unwrap();
+        }
+        assert_eq!(replayed.to_move_list(), game.to_move_list());
+        // Steps must be legal where the move is unpacked
+        let mov = Move::parse("Ee2n Ee3n").unwrap();
+        let bytes = mov.to_bytes(&game.start).unwrap();
+        assert_eq!(bytes.len(), 6);
+        assert!(Move::from_bytes(&bytes, &game.start).is_ok());
+        assert!(Move::from_bytes(&bytes, &game.position).is_err());
+        assert!(Move::from_bytes(&bytes[..5], &game.start).is_err());
+        assert!(Move::from_bytes(&[&[9], &bytes[1..]].concat(), &game.start).is_err());
+    }
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use crate::game::Game;
+        let game = Game::from_move_list(&format!("# white Alice\n{}", GAME)).unwrap();
+        let json = serde_json::to_string(&game).unwrap();
+        assert!(json.contains("\"Cc2n Cc3x Ee2n Ee3n Ee4n\""));
+        let read: Game = serde_json::from_str(&json).unwrap();
+        assert_eq!(read.to_record(), game.to_record());
+        assert!(read.position == game.position);
+        // Illegal turns and mismatched winners are rejected
+        assert!(serde_json::from_str::<Game>(&json.replace("Ee2n", "Ee2s")).is_err());
+        assert!(serde_json::from_str::<Game>(&json.replace("null", "\"w\"")).is_ok());
+        // Positions keep their step state, including a pending push
+        let pos = Position::from_pos_notation(POS1.to_string()).unwrap();
+        let mut pushing = pos.clone();
+        let push = pos
+            .gen_steps()
+            .into_iter()
+            .find(|s| matches!(s, Step::Push(_, _, _)))
+            .unwrap();
+        pushing.do_step(push);
+        // A piece stepping onto an unguarded trap is captured but is still
+        // the last step
+        let mut pieces = [Piece::Empty; 64];
+        pieces[19] = Piece::WDog; // d3
+        pieces[60] = Piece::BRabbit; // e8
+        let mut captured = Position::from_pieces(Side::White, 4, pieces);
+        captured.do_step(Step::Move(Piece::WDog, 19, 18));
+        assert_eq!(captured.pieces[18], Piece::Empty);
+        for p in [pos, pushing, captured].iter() {
+            let json = serde_json::to_string(p).unwrap();
+            assert!(json.contains(&p.to_small_notation()));
+            let read: Position = serde_json::from_str(&json).unwrap();
+            assert!(read == *p);
+            assert_eq!(read.current_hash, p.current_hash);
+            assert_eq!(read.plies, p.plies);
+            assert_eq!(read.gen_steps(), p.gen_steps());
+        }
+        let step: Step = serde_json::from_str("\"Ee2n\"").unwrap();
+        assert_eq!(step, Step::Move(Piece::WElephant, 12, 20));
+        assert_eq!(serde_json::to_string(&Step::Pass).unwrap(), "\"pass\"");
+        assert!(serde_json::from_str::<Step>("\"Ee8n\"").is_err());
+        assert_eq!(serde_json::to_string(&Side::Black).unwrap(), "\"b\"");
+        assert_eq!(serde_json::from_str::<Side>("\"g\"").unwrap(), Side::White);
+        assert_eq!(
+            serde_json::from_str::<Piece>("\"m\"").unwrap(),
+            Piece::BCamel
+        );
+        assert!(serde_json::from_str::<Piece>("\"x\"").is_err());
+    }
+    #[test]
+    fn test_tuning() {
+        use crate::eval::{LinearEval, NUM_FEATURES};
+        use crate::game::Game;
+        use crate::position::EndState;
+        use crate::tune::{self, Tuner};
+        let mut game = Game::from_move_list(GAME).unwrap();
+        assert!(tune::labelled_positions(&[game.clone()], 0).is_empty());
+        game.result = EndState::WhiteWin;
+        let data = tune::labelled_positions(&[game], 1);
+        assert_eq!(data.len(), 3);
+        let results: Vec<_> = data.iter().map(|d| d.result).collect();
+        assert_eq!(results, vec![0.0, 1.0, 0.0]);
+        let start = LinearEval::new([0.0; NUM_FEATURES]);
+        let initial = tune::loss(&start, &data);
+        assert!((initial - 0.25).abs() < 1e-9);
+        let path = std::env::temp_dir().join("arimaa_tuned_eval_test.txt");
+        let tuner = Tuner {
+            iterations: 50,
+            ..Default::default()
+        };
+        let tuned = tuner.tune_to_file(&start, &data, &path).unwrap();
+        assert!(tune::loss(&tuned, &data) < initial);
+        assert!(LinearEval::load(&path).unwrap() == tuned);
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn test_rollout() {
+        use crate::rollout::{self, RolloutConfig, RolloutPolicy};
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+        let mut pieces = [Piece::Empty; 64];
+        pieces[48] = Piece::WRabbit; // a7
+        pieces[28] = Piece::WElephant; // e4
+        pieces[55] = Piece::BRabbit; // h7
+        pieces[35] = Piece::BElephant; // d5
+        pieces[10] = Piece::BCat; // c2
+        let pos = Position::from_pieces(Side::White, 4, pieces);
+        assert_eq!(rollout::material_balance(&pos), 800 + 100 - 800 - 100 - 150);
+        // The heavy policy always finds the goal
+        let heavy = RolloutConfig {
+            policy: RolloutPolicy::heavy(),
+            max_turns: 10,
+        };
+        for seed in 0..10 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            assert_eq!(rollout::rollout(&pos, &heavy, &mut rng), 1);
+        }
+        // Light rollouts are reproducible from the seed
+        let light = RolloutConfig::default();
+        let results: Vec<_> = (0..2)
+            .map(|_| {
+                let mut rng = SmallRng::seed_from_u64(3);
+                (0..20)
+                    .map(|_| rollout::rollout(&pos, &light, &mut rng))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        assert_eq!(results[0], results[1]);
+        assert!(results[0].iter().all(|r| (-1..=1).contains(r)));
+        // Cut off immediately, the rollout is scored by material
+        let cutoff = RolloutConfig {
+            max_turns: 0,
+            ..light
+        };
+        assert_eq!(
+            rollout::rollout(&pos, &cutoff, &mut SmallRng::seed_from_u64(0)),
+            -1
+        );
+        let config = search::SearchConfig {
+            threads: 1,
+            playouts: Some(500),
+            rollout: Some(heavy),
+            ..Default::default()
+        };
+        let first = search::search(search::ArimaaGame::new(pos.clone()), &config);
+        let second = search::search(search::ArimaaGame::new(pos), &config);
+        assert_eq!(first.visits, second.visits);
+        assert!(first.win_rate > 0.5);
+    }
+    #[test]
     fn new_start() {
         let op = "Ra1 Db1 Rc1 Rd1 De1 Rf1 Cg1 Rh1 Ra2 Hb2 Cc2 Ed2 Me2 Rf2 Hg2 Rh2
         ha7 mb7 cc7 dd7 ee7 cf7 hg7 rh7 ra8 rb8 rc8 rd8 de8 rf8 rg8 rh8";
@@ -38,6 +1372,52 @@ mod tests {
         assert!(512 < total && total < 1569); // Somewhat loose bounds for sanity check
     }
     #[test]
+    fn test_step_index() {
+        use std::collections::HashSet;
+        assert_eq!(position::total_moves() as usize, position::NUM_STEP_INDICES);
+        // Every index is a step on a full board
+        let full = Position::from_pieces(Side::White, 4, [Piece::WCat; 64]);
+        for index in 0..position::NUM_STEP_INDICES {
+            assert!(Step::from_index(index, &full).is_some(), "{}", index);
+        }
+        assert_eq!(Step::from_index(position::NUM_STEP_INDICES, &full), None);
+        assert_eq!(Step::Pass.to_index(&full), Some(position::PASS_STEP_INDEX));
+        assert_eq!(Step::Place(Piece::WCat, 3).to_index(&full), None);
+        assert_eq!(Step::Move(Piece::WCat, 7, 8).to_index(&full), None);
+        let empty = Position::new_empty();
+        assert_eq!(Step::from_index(1, &empty), None);
+        // Legal steps have distinct indices which round trip, including the
+        // steps completing pushes and pulls
+        let mut positions = Vec::new();
+        for (notation, side) in [(POS1, Side::White), (POS2, Side::Black)].iter() {
+            let board = Position::from_pos_notation(notation.to_string()).unwrap();
+            let pos = Position::from_pieces(*side, 4, board.pieces);
+            for first in pos.gen_steps() {
+                let mut next = pos.clone();
+                next.do_step(first);
+                positions.push(next);
+            }
+            positions.push(pos);
+        }
+        let mut completions = 0;
+        for pos in positions.iter() {
+            let steps = pos.gen_steps();
+            let indices: HashSet<_> = steps
+                .iter()
+                .map(|s| {
+                    let index = s.to_index(pos).unwrap();
+                    assert_eq!(Step::from_index(index, pos), Some(*s));
+                    if Some(index) != s.to_index(&full) {
+                        completions += 1;
+                    }
+                    index
+                })
+                .collect();
+            assert_eq!(indices.len(), steps.len());
+        }
+        assert!(completions > 0);
+    }
+    #[test]
     fn test_small_notation() {
         let note = "[ rr r r m h  e c r  r r h dr c dE H    M R RRHR D C  C D R RR R ]";
         let p = Position::from_small_notation(note.to_string(), Side::Black);
@@ -52,7 +1432,7 @@ mod tests {
             if local {
                 format!("{}/{}", prefix, fname)
             } else {
-                format!("{}", fname)
+                fname.to_string()
             }
         };
         let res = Command::new(format!("{}/mc", prefix))
@@ -81,13 +1461,14 @@ mod tests {
         (moves, position_strings)
     }
     #[test]
+    #[ignore = "requires the ArimaaMoveCount perl script"]
     fn test_perl_call() {
         //use std::collections::HashSet;
         let (moves, position_strings) = parse_perl(call_perl("pos2", true));
         //let hashset: HashSet<_> = position_strings.into_iter().collect();
         println!("{}", position::alg_to_index(&['a', '8']).unwrap());
         let init_pos = Position::from_pos_notation(POS1.to_string()).unwrap();
-        for (m, pos_string) in moves.into_iter().zip(position_strings.into_iter()) {
+        for (m, pos_string) in moves.into_iter().zip(position_strings) {
             let mut pos = init_pos.clone();
             for step in m.steps.iter() {
                 pos.do_step(*step);
@@ -122,14 +1503,15 @@ mod tests {
             .into_iter()
             .map(|s| Position::from_pos_notation(s.to_string()).unwrap())
             .collect();
-        pos[1].side = Side::Black;
-        let count = [88040 as usize, 68891];
+        pos[1] = Position::from_pieces(Side::Black, 4, pos[1].pieces);
+        let count = [88040_usize, 68891];
         for (p, c) in pos.into_iter().zip(count.iter()) {
             let found_positions = crate::game::Move::all_positions(&p);
             assert_eq!(found_positions.len(), *c);
         }
     }
     #[test]
+    #[ignore = "requires the ArimaaMoveCount perl script"]
     fn test_step_gen() {
         use std::collections::HashMap;
         let pos = Position::from_pos_notation(POS2.to_string()).unwrap();
@@ -138,7 +1520,7 @@ mod tests {
         // 88040 moves from white's pos
         // pos.side = Side::Black;
         //18542
-        let (correct_steps, correct_positions) = parse_perl(call_perl(
+        let (_correct_steps, correct_positions) = parse_perl(call_perl(
             "/home/justin/Code/rust/arimaa-rs/src/test_games/pos2.txt",
             false,
         ));
@@ -219,6 +1601,107 @@ mod tests {
         //assert!(lsb & pos.bitboards[0] == 0)
     }
     #[test]
+    fn test_move_number() {
+        let pos = Position::from_pos_notation(POS3.to_string()).unwrap();
+        assert_eq!(pos.move_number(), 26);
+        assert_eq!(pos.plies, position::plies_from_move_number(26, Side::Black));
+        let mut pos = Position::from_pos_notation(POS1.to_string()).unwrap();
+        assert_eq!(pos.move_number(), 7);
+        // Each completed turn counts a ply, so the move number goes up after
+        // Black's turn
+        for header in ["7b", "8w"].iter() {
+            let plies = pos.plies;
+            let step = pos.gen_steps()[0];
+            pos.do_step(step);
+            pos.do_step(Step::Pass);
+            assert_eq!(pos.plies, plies + 1);
+            let notation = pos.to_pos_notation();
+            assert_eq!(notation.lines().next(), Some(*header));
+            let parsed = Position::from_pos_notation(notation).unwrap();
+            assert_eq!(parsed.plies, pos.plies);
+        }
+    }
+    #[test]
+    fn test_step_state_hash() {
+        use crate::zobrist::compute_hash;
+        let pos = Position::from_pos_notation(POS1.to_string()).unwrap();
+        let west = Step::from_notation("Rb1w");
+        let east = Step::from_notation("Rg1e");
+        let mut first = pos.clone();
+        first.do_step(west);
+        first.do_step(east);
+        let mut second = pos.clone();
+        second.do_step(east);
+        second.do_step(west);
+        assert_eq!(first.current_hash, second.current_hash);
+        // The same board with a different number of steps left
+        let fresh = Position::from_pieces(first.side, 4, first.pieces);
+        assert_ne!(fresh.current_hash, first.current_hash);
+        // Stepping the elephant away leaves the horse on a5 open to a pull
+        let mut pull = pos.clone();
+        pull.do_step(Step::from_notation("Ea4e"));
+        let no_follow_up = compute_hash(&pull.pieces, pull.side, pull.steps_left, None);
+        assert_ne!(no_follow_up, pull.current_hash);
+        assert_eq!(
+            compute_hash(&pull.pieces, pull.side, pull.steps_left, pull.last_step),
+            pull.current_hash
+        );
+    }
+    #[test]
+    fn test_validate() {
+        let pos = Position::from_pos_notation(POS1.to_string()).unwrap();
+        assert!(pos.validate().is_ok());
+        let rebuilt = Position::new(pos.side, pos.steps_left, pos.bitboards);
+        assert!(rebuilt.validate().is_ok());
+        assert_eq!(rebuilt.to_small_notation(), pos.to_small_notation());
+        let mut stepped = pos.clone();
+        for step in ["Ea4e", "ha5s", "Eb4n"].iter() {
+            stepped.do_step(Step::from_notation(step));
+            assert!(stepped.validate().is_ok());
+        }
+        let mut setup = Position::new_empty();
+        setup.do_step(Step::Place(Piece::WElephant, 12));
+        assert!(setup.validate().is_ok());
+        let mut corrupt = pos.clone();
+        corrupt.bitboards[Piece::WCat as usize] ^= 1;
+        corrupt.side = Side::Black;
+        let err = format!("{}", corrupt.validate().unwrap_err());
+        assert!(err.contains("bitboard 2"));
+        assert!(err.contains("hash"));
+    }
+    #[test]
+    fn test_symmetry() {
+        use position::Symmetry;
+        use std::collections::HashSet;
+        let pos = Position::from_pos_notation(POS1.to_string()).unwrap();
+        let mirror = pos.mirror();
+        assert_ne!(mirror.to_small_notation(), pos.to_small_notation());
+        assert_eq!(mirror.mirror().to_small_notation(), pos.to_small_notation());
+        assert_eq!(mirror.mirror().current_hash, pos.current_hash);
+        let flipped = pos.flip_colors();
+        assert_eq!(flipped.side, Side::Black);
+        assert_eq!(flipped.flip_colors().current_hash, pos.current_hash);
+        let canonical = pos.canonical_hash();
+        for sym in Symmetry::ALL.iter() {
+            let other = pos.transform(*sym);
+            assert_eq!(other.canonical_hash(), canonical);
+            // Legal steps map onto each other under the symmetry
+            let expected: HashSet<_> = pos
+                .gen_steps()
+                .into_iter()
+                .map(|s| format!("{}", sym.step(s)))
+                .collect();
+            let found: HashSet<_> = other.gen_steps().iter().map(|s| format!("{}", s)).collect();
+            assert_eq!(expected, found);
+        }
+        let (canon, sym) = pos.canonical();
+        assert_eq!(canon.current_hash, canonical);
+        assert_eq!(
+            pos.transform(sym).to_small_notation(),
+            canon.to_small_notation()
+        );
+    }
+    #[test]
     fn test_bit_tricks() {
         use position::Bitboard;
         let t1 = 0b0110;
@@ -240,13 +1723,8 @@ mod tests {
         println!("{} -> {}", source, dest);
         assert_eq!(format!("{}", step), "Cc2s".to_string());
     }
-    // #[test]
-    // fn gen_zobrist() {
-    //     let name = concat!(file!(), ".zobrist");
-    //     let mut f = std::fs::File::create(name).unwrap();
-    //     crate::zobrist::write_zobrist(&mut f);
-    // }
     #[test]
+    #[ignore = "requires a local gameroom archive"]
     fn test_cleanup() {
         cleanup_gameroom_logs("/home/justin/Downloads/allgames201301.txt");
     }
@@ -270,7 +1748,7 @@ mod tests {
         for s in steps.iter().rev() {
             let first_c = s.chars().next();
             if let Some(c) = first_c {
-                if c.is_digit(10) {
+                if c.is_ascii_digit() {
                     // Last move indicator
                     last_move_str = s;
                     break;
@@ -0,0 +1,822 @@
+use arimaa::book::OpeningBook;
+use arimaa::database::{GameDatabase, Query};
+use arimaa::eval::{Evaluate, HandcraftedEval, LinearEval};
+use arimaa::game::{self, Game, Move};
+use arimaa::position::{piece_char_index, EndState, Piece, Position, Side, Step};
+use arimaa::search::{self, ArimaaGame, SearchConfig};
+use arimaa::setup::SetupChooser;
+use arimaa::solver::{GoalSolver, RaceResult};
+use arimaa::tablebase::{Outcome, Tablebases};
+use arimaa::tournament::{self, AeiPlayer, EnginePlayer, MatchConfig, Player};
+use failure::{bail, ensure, format_err, Error};
+use num_traits::FromPrimitive;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, Read, Write};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The setups a human plays when no game is given to `play`
+const DEFAULT_SETUP: &str = "Ra1 Rb1 Rc1 Rd1 Re1 Rf1 Rg1 Rh1 Ha2 Db2 Cc2 Md2 Ee2 Cf2 Dg2 Hh2
+ra8 rb8 rc8 rd8 re8 rf8 rg8 rh8 ha7 db7 cc7 ed7 me7 cf7 dg7 hh7";
+const PLAY_HELP: &str = "Enter a turn as its steps, such as `Ee2n Ee3n`. Captures may be left out.
+Commands:
+    takeback        Undo your last turn and the engine's reply
+    save <file>     Write the game so far in move list notation
+    board           Draw the board again
+    help            Show this message
+    quit            Leave the game";
+
+const USAGE: &str = "Usage: arimaa <command> [options] [file]
+
+Reads a position from the file, or standard input if it is missing or `-`.
+Positions may be a board diagram, a bracketed small notation string, the two
+setup lines of a game or a whole game as a move list.
+
+Commands:
+    show                      Draw the position
+    moves [--turns]           List the legal steps, or every distinct turn
+    perft <depth>             Count turn sequences up to the given depth
+    solve <turns> [--pieces N]
+                              Solve a goal race with few pieces exactly,
+                              looking for a forced win within the given
+                              number of turns of each side
+    analyze [--seconds N] [--playouts N] [--threads N] [--weights FILE]
+            [--tablebases DIR]
+                              Search the position and print the best line,
+                              or the exact result from the tablebases
+    convert --to <format>     Write the position as `board` or `small`, or a
+                              game as `moves`
+    play [--human <w|b>] [--seconds N] [--threads N] [--weights FILE]
+         [--book FILE] [--tablebases DIR]
+                              Play against the engine from a game or setups.
+                              Without a file you play a standard setup and
+                              the engine chooses its own.
+    match --first <engine> --second <engine> [--games N] [--tc M/R/P]
+          [--setups FILE] [--out FILE] [--seed N] [--threads N] [--max-turns N]
+          [--book FILE]
+                              Play a match with alternating colors and report
+                              the Elo difference. Engines are `builtin`,
+                              `builtin:<weights file>` or `aei:<command>`.
+                              Setups are read as pairs of lines.
+    book --out FILE [--turns N] <archive>...
+                              Build an opening book from the first turns of
+                              archived games, separated by blank lines
+    import --db FILE <archive>...
+                              Add archived games to a game database, creating
+                              it if needed
+    query --db FILE [--position FILE] [--material PIECES] [--player NAME]
+          [--captured PIECES] [--out FILE]
+                              List the games meeting every criterion given:
+                              reaching a position or material such as
+                              `EMHHDDCCRRRRRRRRemhhddccrrrrrrr`, played by a
+                              player, or where any of the listed pieces was
+                              captured, such as `Ee`. Matching games may be
+                              written to an archive.
+    tablebase --dir DIR <material>...
+                              Generate tablebases for tiny material such as
+                              `Rr`, with every smaller table captures lead to
+
+Options:
+    --side <w|b>              The side to move for small notation input";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<(), Error> {
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) if command != "help" && command != "--help" => (command, rest),
+        _ => {
+            println!("{}", USAGE);
+            return Ok(());
+        }
+    };
+    let mut args = Args::parse(rest)?;
+    match command.as_str() {
+        "show" => show(&mut args),
+        "moves" => moves(&mut args),
+        "perft" => perft(&mut args),
+        "solve" => solve(&mut args),
+        "tablebase" => build_tablebases(&mut args),
+        "analyze" => analyze(&mut args),
+        "convert" => convert(&mut args),
+        "play" => play(&mut args),
+        "match" => run_match(&mut args),
+        "book" => build_book(&mut args),
+        "import" => import(&mut args),
+        "query" => query(&mut args),
+        _ => bail!("Unknown command {}\n\n{}", command, USAGE),
+    }
+}
+
+/// Options given as `--name value` or bare `--name` flags, and the remaining
+/// positional arguments
+struct Args {
+    options: HashMap<String, Option<String>>,
+    positional: Vec<String>,
+}
+
+impl Args {
+    fn parse(args: &[String]) -> Result<Args, Error> {
+        const FLAGS: [&str; 1] = ["turns"];
+        let mut options = HashMap::new();
+        let mut positional = Vec::new();
+        let mut iter = args.iter();
+        while let Some(arg) = iter.next() {
+            match arg.strip_prefix("--") {
+                Some(name) if FLAGS.contains(&name) => {
+                    options.insert(name.to_string(), None);
+                }
+                Some(name) => {
+                    let value = iter
+                        .next()
+                        .ok_or(format_err!("Missing value for --{}", name))?;
+                    options.insert(name.to_string(), Some(value.clone()));
+                }
+                None => positional.push(arg.clone()),
+            }
+        }
+        Ok(Args {
+            options,
+            positional,
+        })
+    }
+    fn flag(&mut self, name: &str) -> bool {
+        self.options.remove(name).is_some()
+    }
+    fn value(&mut self, name: &str) -> Option<String> {
+        self.options.remove(name).flatten()
+    }
+    fn number<T: std::str::FromStr>(&mut self, name: &str) -> Result<Option<T>, Error> {
+        match self.value(name) {
+            Some(v) => v
+                .parse()
+                .map(Some)
+                .map_err(|_| format_err!("Invalid value for --{}: {}", name, v)),
+            None => Ok(None),
+        }
+    }
+    fn side(&mut self, name: &str) -> Result<Option<Side>, Error> {
+        match self.value(name).as_deref() {
+            None => Ok(None),
+            Some("w") | Some("g") => Ok(Some(Side::White)),
+            Some("b") | Some("s") => Ok(Some(Side::Black)),
+            Some(s) => bail!("Invalid side {}", s),
+        }
+    }
+    /// Fails if any option is left over or more than one file is given
+    fn check_used(&self) -> Result<(), Error> {
+        ensure!(
+            self.positional.len() <= 1,
+            format_err!("Unexpected argument {}", self.positional[0])
+        );
+        if let Some(name) = self.options.keys().next() {
+            bail!("Unknown option --{}", name);
+        }
+        Ok(())
+    }
+    /// The input named by the last positional argument, checking every
+    /// argument given was used
+    fn input(&mut self) -> Result<Input, Error> {
+        let side = self.side("side")?.unwrap_or(Side::White);
+        self.check_used()?;
+        let text = match self.positional.pop() {
+            Some(ref path) if path != "-" => fs::read_to_string(path)
+                .map_err(|e| format_err!("Could not read {}: {}", path, e))?,
+            _ => {
+                let mut text = String::new();
+                io::stdin().read_to_string(&mut text)?;
+                text
+            }
+        };
+        Input::parse(&text, side)
+    }
+}
+
+/// A position read from any of the supported notations
+enum Input {
+    Position(Box<Position>),
+    Game(Box<Game>),
+}
+
+impl Input {
+    fn parse(text: &str, side: Side) -> Result<Input, Error> {
+        let trimmed = text.trim();
+        if trimmed.starts_with('[') {
+            let pos = Position::from_small_notation(trimmed.to_string(), side)?;
+            return Ok(Input::Position(Box::new(pos)));
+        }
+        let lines: Vec<_> = trimmed.lines().collect();
+        let first = lines
+            .first()
+            .and_then(|l| l.split_whitespace().next())
+            .ok_or(format_err!("No position given"))?;
+        if first.starts_with(|c: char| c.is_ascii_digit()) {
+            let diagram = lines.get(1).is_some_and(|l| l.trim().starts_with('+'));
+            if diagram {
+                let pos = Position::from_pos_notation(trimmed.to_string())?;
+                return Ok(Input::Position(Box::new(pos)));
+            }
+            return Ok(Input::Game(Box::new(Game::from_move_list(trimmed)?)));
+        }
+        ensure!(
+            lines.len() == 2,
+            format_err!("Unrecognised position notation")
+        );
+        let game = Game::new(Move::parse(lines[0])?, Move::parse(lines[1])?)?;
+        Ok(Input::Game(Box::new(game)))
+    }
+    fn position(&self) -> &Position {
+        match self {
+            Input::Position(pos) => pos,
+            Input::Game(game) => &game.position,
+        }
+    }
+}
+
+fn show(args: &mut Args) -> Result<(), Error> {
+    let input = args.input()?;
+    let pos = input.position();
+    print!("{}", pos.to_pos_notation());
+    println!("Steps left: {}", pos.steps_left);
+    if let Some(step) = pos.last_step {
+        println!("Last step: {}", step);
+    }
+    if let Input::Game(game) = &input {
+        println!("Result: {}", describe_result(&game.result));
+    }
+    println!("Hash: {:016x}", pos.current_hash);
+    Ok(())
+}
+
+fn moves(args: &mut Args) -> Result<(), Error> {
+    let turns = args.flag("turns");
+    let input = args.input()?;
+    let pos = input.position();
+    if turns {
+        let turns = Move::all_turns(pos);
+        for (mov, _) in turns.iter() {
+            println!("{}", mov);
+        }
+        println!("{} distinct turns", turns.len());
+    } else {
+        let steps = pos.gen_steps();
+        for step in steps.iter() {
+            println!("{}", step);
+        }
+        println!("{} legal steps", steps.len());
+    }
+    Ok(())
+}
+
+fn perft(args: &mut Args) -> Result<(), Error> {
+    ensure!(
+        !args.positional.is_empty(),
+        format_err!("Missing perft depth")
+    );
+    let depth = args.positional.remove(0);
+    let depth: u32 = depth
+        .parse()
+        .map_err(|_| format_err!("Invalid depth {}", depth))?;
+    let input = args.input()?;
+    for d in 1..=depth {
+        let start = Instant::now();
+        let count = game::perft(input.position(), d);
+        println!(
+            "depth {} count {} time {:.2}s",
+            d,
+            count,
+            start.elapsed().as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+fn solve(args: &mut Args) -> Result<(), Error> {
+    ensure!(
+        !args.positional.is_empty(),
+        format_err!("Missing number of turns")
+    );
+    let turns = args.positional.remove(0);
+    let turns: u32 = turns
+        .parse()
+        .map_err(|_| format_err!("Invalid number of turns {}", turns))?;
+    let mut solver = GoalSolver::new();
+    if let Some(pieces) = args.number("pieces")? {
+        solver.max_pieces = pieces;
+    }
+    let input = args.input()?;
+    let pos = input.position();
+    let start = Instant::now();
+    let (winner, line) = match solver.solve(pos, turns)? {
+        RaceResult::Win(line) => (pos.side, line),
+        RaceResult::Loss(line) => (pos.side.opposite(), line),
+        RaceResult::Unknown => {
+            println!("No forced win within {} turns", turns);
+            return Ok(());
+        }
+    };
+    // Lines alternate sides, ending with the winner's last turn
+    let winner_turns = if winner == pos.side {
+        line.len().div_ceil(2)
+    } else {
+        line.len() / 2
+    };
+    println!("{:?} wins in {} turns", winner, winner_turns);
+    let mut side = pos.side;
+    let mut number = pos.move_number();
+    for mov in line.iter() {
+        let label = match side {
+            Side::White => 'w',
+            Side::Black => 'b',
+        };
+        println!("{}{} {}", number, label, mov);
+        if side == Side::Black {
+            number += 1;
+        }
+        side = side.opposite();
+    }
+    println!(
+        "nodes {} time {:.2}s",
+        solver.nodes,
+        start.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+/// The search set up by the `--seconds`, `--playouts`, `--threads`,
+/// `--weights` and `--book` options
+fn search_config(args: &mut Args) -> Result<SearchConfig, Error> {
+    let seconds: f64 = args.number("seconds")?.unwrap_or(5.0);
+    ensure!(seconds > 0.0, format_err!("--seconds must be positive"));
+    let evaluator: Arc<dyn Evaluate> = match args.value("weights") {
+        Some(path) => Arc::new(LinearEval::load(path)?),
+        None => Arc::new(HandcraftedEval),
+    };
+    Ok(SearchConfig {
+        threads: args.number("threads")?.unwrap_or(4),
+        playouts: args.number("playouts")?,
+        time_limit: Some(Duration::from_secs_f64(seconds)),
+        evaluator: Some(evaluator),
+        book: load_book(args)?,
+        tablebases: load_tablebases(args)?,
+        ..Default::default()
+    })
+}
+
+fn load_tablebases(args: &mut Args) -> Result<Option<Arc<Tablebases>>, Error> {
+    match args.value("tablebases") {
+        Some(dir) => Ok(Some(Arc::new(Tablebases::load(dir)?))),
+        None => Ok(None),
+    }
+}
+
+fn load_book(args: &mut Args) -> Result<Option<Arc<OpeningBook>>, Error> {
+    match args.value("book") {
+        Some(path) => Ok(Some(Arc::new(OpeningBook::load(path)?))),
+        None => Ok(None),
+    }
+}
+
+fn analyze(args: &mut Args) -> Result<(), Error> {
+    let config = search_config(args)?;
+    let input = args.input()?;
+    let pos = input.position();
+    if let Some(tablebases) = config.tablebases.as_ref() {
+        if let (Some(outcome), Some(mov)) = (tablebases.probe(pos), tablebases.best_turn(pos)) {
+            println!("bestmove {}", mov);
+            println!("tablebase {:?}", outcome);
+            return Ok(());
+        }
+    }
+    let result = search::search(ArimaaGame::new(input.position().clone()), &config);
+    let pv: Vec<_> = result.pv.iter().map(|s| s.to_string()).collect();
+    println!("bestmove {}", result.best_move);
+    println!("eval {:.3}", result.win_rate);
+    println!("pv {}", pv.join(" "));
+    println!(
+        "playouts {} time {:.2}s",
+        result.playouts,
+        result.elapsed.as_secs_f64()
+    );
+    Ok(())
+}
+
+fn convert(args: &mut Args) -> Result<(), Error> {
+    let format = args
+        .value("to")
+        .ok_or(format_err!("Missing output format, use --to"))?;
+    let input = args.input()?;
+    match (format.as_str(), &input) {
+        ("board", _) => print!("{}", input.position().to_pos_notation()),
+        ("small", _) => println!("{}", input.position().to_small_notation()),
+        ("moves", Input::Game(game)) => println!("{}", game.to_move_list()),
+        ("moves", Input::Position(_)) => bail!("Only games can be written as moves"),
+        _ => bail!("Unknown format {}", format),
+    }
+    Ok(())
+}
+
+fn describe_result(result: &EndState) -> &'static str {
+    match result {
+        EndState::WhiteWin => "White wins",
+        EndState::BlackWin => "Black wins",
+        EndState::Neither => "in progress",
+    }
+}
+
+fn play(args: &mut Args) -> Result<(), Error> {
+    let human = args.side("human")?.unwrap_or(Side::White);
+    let config = search_config(args)?;
+    let mut game = if args.positional.is_empty() {
+        args.check_used()?;
+        let lines: Vec<_> = DEFAULT_SETUP.lines().collect();
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let chooser = SetupChooser {
+            randomness: 0.5,
+            seed,
+            ..Default::default()
+        };
+        match human {
+            Side::White => {
+                let white = Move::parse(lines[0])?;
+                let black = chooser.choose(Side::Black, Some(&white));
+                Game::new(white, black)?
+            }
+            Side::Black => {
+                let white = chooser.choose(Side::White, None);
+                Game::new(white, Move::parse(lines[1])?)?
+            }
+        }
+    } else {
+        match args.input()? {
+            Input::Game(game) => *game,
+            Input::Position(_) => bail!("Play needs a game or setups to start from"),
+        }
+    };
+    println!("{}", PLAY_HELP);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    let mut redraw = true;
+    loop {
+        if redraw {
+            draw(&game);
+        }
+        redraw = true;
+        if game.result == EndState::Neither && game.position.side != human {
+            println!("Thinking...");
+            let reply = search::choose_turn(&game.position, &config);
+            if reply.steps.is_empty() {
+                println!("The engine resigns");
+                game.result = EndState::from(human);
+                continue;
+            }
+            game.play(&reply)?;
+            println!("Engine plays {}", game.moves.last().unwrap());
+            continue;
+        }
+        print!("> ");
+        io::stdout().flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => break,
+        };
+        let mut words = line.split_whitespace();
+        match words.next() {
+            None => redraw = false,
+            Some("quit") | Some("exit") => break,
+            Some("help") => {
+                println!("{}", PLAY_HELP);
+                redraw = false;
+            }
+            Some("board") => {}
+            Some("takeback") | Some("undo") => {
+                let mut undone = false;
+                while game.takeback().is_some() {
+                    undone = true;
+                    if game.position.side == human {
+                        break;
+                    }
+                }
+                if !undone {
+                    println!("There is nothing to take back");
+                    redraw = false;
+                }
+            }
+            Some("save") => {
+                redraw = false;
+                match words.next() {
+                    Some(path) => match fs::write(path, game.to_move_list() + "\n") {
+                        Ok(()) => println!("Saved to {}", path),
+                        Err(e) => println!("Could not save: {}", e),
+                    },
+                    None => println!("Usage: save <file>"),
+                }
+            }
+            Some(_) => {
+                let played = Move::parse(&line).and_then(|mov| game.play(&mov));
+                if let Err(e) = played {
+                    println!("Rejected: {}", e);
+                    redraw = false;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Draws the board with the squares touched by the last turn highlighted
+fn draw(game: &Game) {
+    let mut highlight = 0;
+    if game.moves.len() > 2 {
+        for step in game.moves.last().unwrap().steps.iter() {
+            match *step {
+                Step::Move(_, source, dest) | Step::Push(_, source, dest) => {
+                    highlight |= 1 << source | 1 << dest;
+                }
+                Step::Remove(_, sq) => highlight |= 1 << sq,
+                _ => {}
+            }
+        }
+    }
+    print!("{}", game.position.to_highlighted_notation(highlight));
+    if game.result != EndState::Neither {
+        println!("{}", describe_result(&game.result));
+    }
+}
+
+fn run_match(args: &mut Args) -> Result<(), Error> {
+    let threads = args.number("threads")?.unwrap_or(2);
+    let book = load_book(args)?;
+    let mut engine = |option: &str| -> Result<Box<dyn Player>, Error> {
+        let spec = args
+            .value(option)
+            .ok_or(format_err!("Missing --{} engine", option))?;
+        let config = SearchConfig {
+            threads,
+            evaluator: Some(Arc::new(HandcraftedEval)),
+            book: book.clone(),
+            ..Default::default()
+        };
+        if spec == "builtin" {
+            return Ok(Box::new(EnginePlayer::new(&spec, config)));
+        }
+        if let Some(path) = spec.strip_prefix("builtin:") {
+            let config = SearchConfig {
+                evaluator: Some(Arc::new(LinearEval::load(path)?)),
+                ..config
+            };
+            return Ok(Box::new(EnginePlayer::new(&spec, config)));
+        }
+        if let Some(command) = spec.strip_prefix("aei:") {
+            let words: Vec<_> = command.split_whitespace().map(String::from).collect();
+            let (program, rest) = words
+                .split_first()
+                .ok_or(format_err!("Missing AEI command"))?;
+            return Ok(Box::new(AeiPlayer::spawn(program, rest)?));
+        }
+        bail!("Unknown engine {}", spec)
+    };
+    let mut first = engine("first")?;
+    let mut second = engine("second")?;
+    let mut config = MatchConfig::default();
+    if let Some(games) = args.number("games")? {
+        config.games = games;
+    }
+    if let Some(seed) = args.number("seed")? {
+        config.seed = seed;
+    }
+    if let Some(max_turns) = args.number("max-turns")? {
+        config.max_turns = max_turns;
+    }
+    if let Some(tc) = args.value("tc") {
+        config.time_control = tc.parse()?;
+    }
+    if let Some(path) = args.value("setups") {
+        let text =
+            fs::read_to_string(&path).map_err(|e| format_err!("Could not read {}: {}", path, e))?;
+        let lines: Vec<_> = text
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .collect();
+        ensure!(
+            !lines.is_empty() && lines.len() % 2 == 0,
+            format_err!("Expected pairs of setup lines in {}", path)
+        );
+        for pair in lines.chunks(2) {
+            let setup = (Move::parse(pair[0])?, Move::parse(pair[1])?);
+            Game::new(setup.0.clone(), setup.1.clone())?;
+            config.setups.push(setup);
+        }
+    }
+    let out: Box<dyn Write> = match args.value("out") {
+        Some(path) => Box::new(fs::File::create(path)?),
+        None => Box::new(io::sink()),
+    };
+    ensure!(
+        args.positional.is_empty(),
+        format_err!("Unexpected argument {}", args.positional[0])
+    );
+    args.check_used()?;
+    let stats = tournament::run_match(&config, first.as_mut(), second.as_mut(), out)?;
+    println!(
+        "{} vs {}: +{} -{} ={}",
+        first.name(),
+        second.name(),
+        stats.wins,
+        stats.losses,
+        stats.draws
+    );
+    if let Some((elo, lower, upper)) = stats.elo() {
+        println!(
+            "Score {:.1}%, Elo difference {:.0} (95% CI {:.0} to {:.0})",
+            100.0 * stats.score(),
+            elo,
+            lower,
+            upper
+        );
+    }
+    Ok(())
+}
+
+fn build_book(args: &mut Args) -> Result<(), Error> {
+    let out = args
+        .value("out")
+        .ok_or(format_err!("Missing book file, use --out"))?;
+    let turns = args.number("turns")?.unwrap_or(10);
+    if let Some(name) = args.options.keys().next() {
+        bail!("Unknown option --{}", name);
+    }
+    ensure!(
+        !args.positional.is_empty(),
+        format_err!("No game archives given")
+    );
+    let mut book = OpeningBook::new();
+    let mut num_games = 0;
+    let mut skipped = 0;
+    for path in args.positional.iter() {
+        let text =
+            fs::read_to_string(path).map_err(|e| format_err!("Could not read {}: {}", path, e))?;
+        let games = game::read_games(&text).map_err(|e| format_err!("{}: {}", path, e))?;
+        for game in games.iter() {
+            skipped += book.add_game(game, turns);
+        }
+        num_games += games.len();
+    }
+    book.save(&out)?;
+    println!(
+        "{} positions from {} games written to {}",
+        book.len(),
+        num_games,
+        out
+    );
+    if skipped > 0 {
+        println!("Skipped {} turns the book can't hold", skipped);
+    }
+    Ok(())
+}
+
+fn import(args: &mut Args) -> Result<(), Error> {
+    let path = args
+        .value("db")
+        .ok_or(format_err!("Missing database file, use --db"))?;
+    if let Some(name) = args.options.keys().next() {
+        bail!("Unknown option --{}", name);
+    }
+    ensure!(
+        !args.positional.is_empty(),
+        format_err!("No game archives given")
+    );
+    let mut database = GameDatabase::open(&path)?;
+    let mut num_games = 0;
+    for archive in args.positional.iter() {
+        let text = fs::read_to_string(archive)
+            .map_err(|e| format_err!("Could not read {}: {}", archive, e))?;
+        let games = game::read_games(&text).map_err(|e| format_err!("{}: {}", archive, e))?;
+        num_games += games.len();
+        for game in games.into_iter() {
+            database.add_game(game)?;
+        }
+    }
+    println!(
+        "{} games added to {}, which now holds {}",
+        num_games,
+        path,
+        database.len()
+    );
+    Ok(())
+}
+
+fn query(args: &mut Args) -> Result<(), Error> {
+    let path = args
+        .value("db")
+        .ok_or(format_err!("Missing database file, use --db"))?;
+    let side = args.side("side")?.unwrap_or(Side::White);
+    let position = match args.value("position") {
+        Some(file) => {
+            let text = fs::read_to_string(&file)
+                .map_err(|e| format_err!("Could not read {}: {}", file, e))?;
+            Some(Input::parse(&text, side)?.position().clone())
+        }
+        None => None,
+    };
+    let captured = args
+        .value("captured")
+        .unwrap_or_default()
+        .chars()
+        .map(|c| match piece_char_index(c) {
+            0 => Err(format_err!("Invalid piece {}", c)),
+            pix => Ok(Piece::from_u8(pix).unwrap()),
+        })
+        .collect::<Result<_, _>>()?;
+    let query = Query {
+        position,
+        material: args.value("material").map(|m| m.parse()).transpose()?,
+        player: args.value("player"),
+        captured,
+    };
+    let out = args.value("out");
+    args.check_used()?;
+    ensure!(
+        args.positional.is_empty(),
+        format_err!("Unexpected argument {}", args.positional[0])
+    );
+    let database = GameDatabase::open(&path)?;
+    let ids = database.search(&query);
+    let mut records = Vec::with_capacity(ids.len());
+    for id in ids.iter() {
+        let game = database.game(*id).unwrap();
+        println!(
+            "{:>6}  {} vs {}, {} after {} turns",
+            id,
+            game.tag("white").unwrap_or("?"),
+            game.tag("black").unwrap_or("?"),
+            describe_result(&game.result),
+            game.moves.len() - 2
+        );
+        records.push(game.to_record());
+    }
+    println!("{} of {} games", ids.len(), database.len());
+    if let Some(out) = out {
+        fs::write(&out, records.join("\n\n") + "\n")?;
+    }
+    Ok(())
+}
+
+fn build_tablebases(args: &mut Args) -> Result<(), Error> {
+    let dir = args
+        .value("dir")
+        .ok_or(format_err!("Missing tablebase directory, use --dir"))?;
+    if let Some(name) = args.options.keys().next() {
+        bail!("Unknown option --{}", name);
+    }
+    ensure!(
+        !args.positional.is_empty(),
+        format_err!("No material given")
+    );
+    let mut tablebases = if Path::new(&dir).exists() {
+        Tablebases::load(&dir)?
+    } else {
+        Tablebases::new()
+    };
+    for material in args.positional.iter() {
+        let start = Instant::now();
+        let material = material.parse()?;
+        tablebases.generate(material)?;
+        let table = tablebases.table(&material).unwrap();
+        let (mut wins, mut losses, mut draws, mut longest) = (0, 0, 0, 0);
+        for index in 0..table.len() {
+            match table.value(index) {
+                Some(Outcome::Win(n)) => {
+                    wins += 1;
+                    longest = longest.max(n);
+                }
+                Some(Outcome::Loss(_)) => losses += 1,
+                Some(Outcome::Draw) => draws += 1,
+                None => {}
+            }
+        }
+        println!(
+            "{}: {} wins, {} losses, {} draws, longest win {} turns, {:.1}s",
+            material,
+            wins,
+            losses,
+            draws,
+            longest,
+            start.elapsed().as_secs_f64()
+        );
+    }
+    tablebases.save(&dir)?;
+    Ok(())
+}
@@ -6,8 +6,9 @@ use num_derive::FromPrimitive;
 use num_traits::FromPrimitive;
 use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::str::FromStr;
 
-use crate::zobrist::{color_hash, compute_hash, update_hash};
+use crate::zobrist::{color_hash, compute_hash, step_state_hash, update_hash};
 const ALL_BITS_SET: u64 = 0xFFFFFFFFFFFFFFFF;
 
 const A_FILE: u64 = 0x8080808080808080;
@@ -33,7 +34,10 @@ const RANK_8: u64 = 0xFF00000000000000;
 // const TRAP_C3_IX: u8 = 21;
 // const TRAP_F6_IX: u8 = 42;
 // const TRAP_C6_IX: u8 = 45;
-const TRAP_INDICES: [usize; 4] = [18, 21, 42, 45];
+pub const TRAP_INDICES: [usize; 4] = [18, 21, 42, 45];
+/// The number of each piece type, from rabbits to elephants, in a setup
+pub const SETUP_COUNTS: [usize; 6] = [8, 2, 2, 2, 1, 1];
+const POSITION_BYTES_VERSION: u8 = 1;
 const TRAP_NEIGHBORS: [u64; 4] = [0x40A0400, 0x20502000, 0x40A0400000000, 0x20502000000000];
 
 #[derive(Clone, PartialEq)]
@@ -125,6 +129,18 @@ impl From<Piece> for char {
     }
 }
 
+impl Piece {
+    /// The piece of the same rank belonging to the other side
+    pub fn flip_color(self) -> Piece {
+        let pix = self as u8;
+        match pix {
+            0 => Piece::Empty,
+            1..=6 => Piece::from_u8(pix + 6).unwrap(),
+            _ => Piece::from_u8(pix - 6).unwrap(),
+        }
+    }
+}
+
 impl fmt::Display for Piece {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let ch = char::from(*self);
@@ -203,9 +219,129 @@ impl Step {
     }
 }
 
+impl Step {
+    /// A dense index for steps after setup, laid out by the per-square
+    /// offsets of `total_moves` so that every index is a possible step. The
+    /// block of each square `r` holds, in order:
+    ///
+    /// - a step from `r` to each neighbor `n`, which is a move, or the first
+    ///   step of a push when the piece on `r` belongs to the opponent;
+    /// - then for each neighbor `n`, the step from `r` to `n` completing a
+    ///   push of the piece on `n` to each of its other neighbors, followed by
+    ///   the step into `r` completing a pull by the piece that left `r` for
+    ///   `n`, from each other neighbor of `r`.
+    ///
+    /// Neighbors are taken in order of square index, and the pass comes after
+    /// every block. The last step of `pos` tells a step completing a push or
+    /// pull apart from a plain move, so `pos` must be the position the step
+    /// is played from.
+    pub fn to_index(&self, pos: &Position) -> Option<usize> {
+        let (p, source, dest) = match *self {
+            Step::Pass => return Some(PASS_STEP_INDEX),
+            Step::Move(p, source, dest) => (p, source, dest),
+            Step::Push(_, source, dest) => return step_index(source, dest),
+            Step::Place(_, _) | Step::Remove(_, _) => return None,
+        };
+        let own_piece = (p as u8 <= 6) == (pos.side == Side::White);
+        match pos.last_step {
+            Some(Step::Push(_, pushed, to)) if pushed == dest => {
+                let within = neighbor_rank(source, dest)?;
+                let pushes = neighbors_of(index_to_lsb(dest)) & !index_to_lsb(source);
+                let rank = bit_rank(pushes, to)?;
+                Some(completion_offset(source, within) + rank)
+            }
+            Some(Step::Move(_, from, to)) if from == dest && !own_piece => {
+                let within = neighbor_rank(dest, to)?;
+                let pushes = (neighbors_of(index_to_lsb(to)) & !index_to_lsb(dest)).count_ones();
+                let pulls = neighbors_of(index_to_lsb(dest)) & !index_to_lsb(to);
+                let rank = bit_rank(pulls, source)?;
+                Some(completion_offset(dest, within) + pushes as usize + rank)
+            }
+            _ => step_index(source, dest),
+        }
+    }
+    /// The step with the given dense index, taking its piece from `pos`.
+    /// Returns `None` if the square the step starts from is empty.
+    pub fn from_index(index: usize, pos: &Position) -> Option<Step> {
+        if index == PASS_STEP_INDEX {
+            return Some(Step::Pass);
+        }
+        if index > PASS_STEP_INDEX {
+            return None;
+        }
+        let root = STEP_OFFSETS.partition_point(|&o| o as usize <= index) - 1;
+        let root_lsb = index_to_lsb(root as u8);
+        let neighbors = neighbors_of(root_lsb);
+        let mut within = index - STEP_OFFSETS[root] as usize;
+        let piece_on = |sq: u64| match pos.pieces[sq.bitscan_forward()] {
+            Piece::Empty => None,
+            piece => Some(piece),
+        };
+        if within < neighbors.count_ones() as usize {
+            let dest = nth_bit(neighbors, within);
+            let piece = piece_on(root_lsb)?;
+            let own_piece = (piece as u8 <= 6) == (pos.side == Side::White);
+            let (source, dest) = (root as u8, dest.bitscan_forward() as u8);
+            return Some(if own_piece {
+                Step::Move(piece, source, dest)
+            } else {
+                Step::Push(piece, source, dest)
+            });
+        }
+        within -= neighbors.count_ones() as usize;
+        for lsb in PieceIter::new(neighbors) {
+            let pushes = neighbors_of(lsb) & !root_lsb;
+            if within < pushes.count_ones() as usize {
+                let piece = piece_on(root_lsb)?;
+                return Some(Step::Move(piece, root as u8, lsb.bitscan_forward() as u8));
+            }
+            within -= pushes.count_ones() as usize;
+            let pulls = neighbors & !lsb;
+            if within < pulls.count_ones() as usize {
+                let puller = nth_bit(pulls, within);
+                let piece = piece_on(puller)?;
+                return Some(Step::Move(
+                    piece,
+                    puller.bitscan_forward() as u8,
+                    root as u8,
+                ));
+            }
+            within -= pulls.count_ones() as usize;
+        }
+        unreachable!("step index {} past the end of its square", index)
+    }
+}
+
+/// Parses a step in standard notation, checking it stays on the board
+impl FromStr for Step {
+    type Err = Error;
+
+    fn from_str(input: &str) -> Result<Step, Error> {
+        let invalid = || format_err!("Invalid step {}", input);
+        let chars: Vec<_> = input.chars().collect();
+        ensure!(chars.len() == 3 || chars.len() == 4, invalid());
+        let piece = match piece_char_index(chars[0]) {
+            0 => bail!(invalid()),
+            pix => Piece::from_u8(pix).unwrap(),
+        };
+        let sq = alg_to_index(&chars[1..3]).ok_or_else(invalid)? as u8;
+        let (col, row) = (sq % 8, sq / 8);
+        let dest = match chars.get(3) {
+            None => return Ok(Step::Place(piece, sq)),
+            Some('x') => return Ok(Step::Remove(piece, sq)),
+            Some('n') if row < 7 => sq + 8,
+            Some('e') if col < 7 => sq + 1,
+            Some('s') if row > 0 => sq - 8,
+            Some('w') if col > 0 => sq - 1,
+            _ => bail!(invalid()),
+        };
+        Ok(Step::Move(piece, sq, dest))
+    }
+}
+
 impl fmt::Debug for Step {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        <fmt::Display>::fmt(&self, f)
+        <dyn fmt::Display>::fmt(&self, f)
     }
 }
 
@@ -243,13 +379,75 @@ impl fmt::Display for Step {
                 let piece = char::from(*p);
                 let (col, row) = index_to_alg(*sq);
                 write!(f, "{}{}{}x", piece, col, row)
-
             }
             Step::Pass => write!(f, "[Pass]"),
         }
     }
 }
 
+/// The board symmetries of Arimaa. Mirroring swaps the a and h files, while
+/// flipping colors rotates the board 180 degrees and swaps piece ownership and
+/// the side to move. Every symmetry is its own inverse.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Symmetry {
+    Identity,
+    Mirror,
+    FlipColors,
+    MirrorFlipColors,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 4] = [
+        Symmetry::Identity,
+        Symmetry::Mirror,
+        Symmetry::FlipColors,
+        Symmetry::MirrorFlipColors,
+    ];
+    fn mirrors(self) -> bool {
+        matches!(self, Symmetry::Mirror | Symmetry::MirrorFlipColors)
+    }
+    fn flips_colors(self) -> bool {
+        matches!(self, Symmetry::FlipColors | Symmetry::MirrorFlipColors)
+    }
+    pub fn square(self, sq: u8) -> u8 {
+        let mut sq = sq;
+        if self.mirrors() {
+            sq = mirror_square(sq);
+        }
+        if self.flips_colors() {
+            sq = rotate_square(sq);
+        }
+        sq
+    }
+    pub fn piece(self, piece: Piece) -> Piece {
+        if self.flips_colors() {
+            piece.flip_color()
+        } else {
+            piece
+        }
+    }
+    pub fn side(self, side: Side) -> Side {
+        if self.flips_colors() {
+            side.opposite()
+        } else {
+            side
+        }
+    }
+    pub fn step(self, step: Step) -> Step {
+        match step {
+            Step::Move(p, source, dest) => {
+                Step::Move(self.piece(p), self.square(source), self.square(dest))
+            }
+            Step::Push(p, source, dest) => {
+                Step::Push(self.piece(p), self.square(source), self.square(dest))
+            }
+            Step::Place(p, sq) => Step::Place(self.piece(p), self.square(sq)),
+            Step::Remove(p, sq) => Step::Remove(self.piece(p), self.square(sq)),
+            Step::Pass => Step::Pass,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Position {
     pub side: Side,
@@ -285,22 +483,21 @@ impl Position {
     pub fn new(side: Side, steps_left: u8, bitboards: [u64; 13]) -> Position {
         let mut placement: [u64; 2] = [0, 0];
         let mut pieces: [Piece; 64] = [Piece::Empty; 64];
-        for pix in 1..13 {
+        for (pix, board) in bitboards.iter().enumerate().skip(1) {
             if pix < Piece::BRabbit as usize {
-                placement[0] |= bitboards[pix];
+                placement[0] |= board;
             } else {
-                placement[1] |= bitboards[pix];
+                placement[1] |= board;
             }
-            let mut bb = bitboards[pix];
+            let mut bb = *board;
             while bb != 0 {
-                let piecebit = bb & !bb; // LSB
+                let piecebit = bb.isolate_lsb();
                 bb ^= piecebit; // Set LSB to 0
-                let pieceix = bb.bitscan_forward();
+                let pieceix = piecebit.bitscan_forward();
                 assert!(pieces[pieceix] == Piece::Empty);
                 pieces[pieceix] = Piece::from_u8(pix as u8).unwrap();
             }
         }
-        let hash = compute_hash(&pieces, side);
         Position {
             side,
             steps_left,
@@ -308,8 +505,8 @@ impl Position {
             bitboards,
             last_step: None,
             pieces,
-            initial_hash: hash,
-            current_hash: hash,
+            initial_hash: compute_hash(&pieces, side, 4, None),
+            current_hash: compute_hash(&pieces, side, steps_left, None),
             opp_last: 0,
             my_last: 0,
             plies: 16,
@@ -318,18 +515,20 @@ impl Position {
     pub fn new_empty() -> Position {
         let mut bitboards = [0; 13];
         bitboards[0] = ALL_BITS_SET;
+        let pieces = [Piece::Empty; 64];
+        let hash = compute_hash(&pieces, Side::White, 4, None);
         Position {
             side: Side::White,
             steps_left: 4,
             placement: [0; 2],
             bitboards,
             last_step: None,
-            pieces: [Piece::Empty; 64],
-            initial_hash: 0,
-            current_hash: 0,
+            pieces,
+            initial_hash: hash,
+            current_hash: hash,
             opp_last: 0,
             my_last: 0,
-            plies: 0,            
+            plies: 0,
         }
     }
     pub fn from_pieces(side: Side, steps_left: u8, pieces: [Piece; 64]) -> Position {
@@ -340,13 +539,12 @@ impl Position {
             let piece_index = *piece as usize;
             bitboards[piece_index] |= bit_index;
 
-            bit_index = bit_index << 1;
+            bit_index <<= 1;
         }
         for i in 1..=6 {
             placement[0] |= bitboards[i];
             placement[1] |= bitboards[i + 6];
         }
-        let hash = compute_hash(&pieces, side);
         Position {
             side,
             steps_left,
@@ -354,11 +552,11 @@ impl Position {
             bitboards,
             last_step: None,
             pieces,
-            initial_hash: hash,
-            current_hash: hash,
+            initial_hash: compute_hash(&pieces, side, 4, None),
+            current_hash: compute_hash(&pieces, side, steps_left, None),
             opp_last: 0,
             my_last: 0,
-            plies: 16,            
+            plies: 16,
         }
     }
     pub fn from_small_notation(notation: String, side: Side) -> Result<Position, Error> {
@@ -369,7 +567,7 @@ impl Position {
             .filter_map(|c| Piece::from_u8(piece_char_index(c)))
             .collect();
         ensure!(vec.len() == 64, format_err!("Wrong number of pieces!"));
-        for (index, piece) in NotationIter::new().zip(vec.into_iter()) {
+        for (index, piece) in NotationIter::new().zip(vec) {
             pieces[index] = piece;
         }
         Ok(Self::from_pieces(side, 4, pieces))
@@ -383,6 +581,135 @@ impl Position {
         vec.push(']');
         vec.into_iter().collect()
     }
+    /// Packs the position into at most 31 bytes, 28 for a position straight
+    /// after setup. The repetition history is not kept.
+    ///
+    /// | Bytes | Contents                                                    |
+    /// |-------|-------------------------------------------------------------|
+    /// | 0     | Format version                                              |
+    /// | 1     | Bit 0 set if Black is to move, bits 1-3 the steps left, bit |
+    /// |       | 4 set if there is a last step and bit 5 if it was a push    |
+    /// | 2-3   | Plies since the start of setup                              |
+    /// | 4-11  | Occupied squares, bit 0 being a1                            |
+    /// | 12-14 | The last step's piece, source and destination, if any       |
+    /// | Rest  | The piece on each occupied square from a1 upwards, four     |
+    /// |       | bits each, low bits first and padded with zero              |
+    ///
+    /// Integers are little endian.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![POSITION_BYTES_VERSION];
+        let mut flags = self.side as u8 | self.steps_left << 1;
+        match self.last_step {
+            Some(Step::Move(_, _, _)) => flags |= 1 << 4,
+            Some(Step::Push(_, _, _)) => flags |= 3 << 4,
+            _ => {}
+        }
+        bytes.push(flags);
+        bytes.extend_from_slice(&self.plies.to_le_bytes());
+        bytes.extend_from_slice(&(!self.bitboards[0]).to_le_bytes());
+        if let Some(Step::Move(p, source, dest)) | Some(Step::Push(p, source, dest)) =
+            self.last_step
+        {
+            bytes.extend_from_slice(&[p as u8, source, dest]);
+        }
+        let occupied: Vec<_> = self.pieces.iter().filter(|p| **p != Piece::Empty).collect();
+        for pair in occupied.chunks(2) {
+            let high = pair.get(1).map_or(0, |p| **p as u8);
+            bytes.push(*pair[0] as u8 | high << 4);
+        }
+        bytes
+    }
+    /// Unpacks a position written by `to_bytes`, checking that it is
+    /// consistent: one to four steps left, no more of each piece than a setup
+    /// has and a last step that matches the board
+    pub fn from_bytes(bytes: &[u8]) -> Result<Position, Error> {
+        ensure!(bytes.len() >= 12, format_err!("Packed position too short"));
+        ensure!(
+            bytes[0] == POSITION_BYTES_VERSION,
+            format_err!("Unsupported position version {}", bytes[0])
+        );
+        let flags = bytes[1];
+        ensure!(flags >> 6 == 0, format_err!("Invalid position flags"));
+        let side = if flags & 1 == 0 {
+            Side::White
+        } else {
+            Side::Black
+        };
+        let steps_left = (flags >> 1) & 7;
+        ensure!(
+            (1..=4).contains(&steps_left),
+            format_err!("{} steps left", steps_left)
+        );
+        let plies = u16::from_le_bytes([bytes[2], bytes[3]]);
+        let mut occupied = [0; 8];
+        occupied.copy_from_slice(&bytes[4..12]);
+        let occupied = u64::from_le_bytes(occupied);
+        let (last_step, rest) = match (flags >> 4) & 3 {
+            0 => (None, &bytes[12..]),
+            2 => bail!("Invalid position flags"),
+            kind => {
+                ensure!(bytes.len() >= 15, format_err!("Packed position too short"));
+                let (pix, source, dest) = (bytes[12], bytes[13], bytes[14]);
+                let piece = Piece::from_u8(pix)
+                    .filter(|p| *p != Piece::Empty)
+                    .ok_or(format_err!("Invalid piece {}", pix))?;
+                ensure!(
+                    source < 64
+                        && dest < 64
+                        && neighbors_of(index_to_lsb(source)) & index_to_lsb(dest) != 0,
+                    format_err!("Invalid last step")
+                );
+                ensure!(
+                    steps_left < 4,
+                    format_err!("Last step at the start of a turn")
+                );
+                let step = if kind == 1 {
+                    Step::Move(piece, source, dest)
+                } else {
+                    Step::Push(piece, source, dest)
+                };
+                (Some(step), &bytes[15..])
+            }
+        };
+        let count = occupied.count_ones() as usize;
+        ensure!(
+            rest.len() == count.div_ceil(2),
+            format_err!("Packed position has the wrong length")
+        );
+        let mut pieces = [Piece::Empty; 64];
+        let mut counts = [0; 12];
+        for (i, lsb) in PieceIter::new(occupied).enumerate() {
+            let pix = (rest[i / 2] >> (4 * (i % 2))) & 15;
+            pieces[lsb.bitscan_forward()] = match Piece::from_u8(pix) {
+                Some(Piece::Empty) | None => bail!("Invalid piece {}", pix),
+                Some(piece) => piece,
+            };
+            let kind = pix as usize - 1;
+            counts[kind] += 1;
+            ensure!(
+                counts[kind] <= SETUP_COUNTS[kind % 6],
+                format_err!("Too many {:?}s", pieces[lsb.bitscan_forward()])
+            );
+        }
+        ensure!(
+            count.is_multiple_of(2) || rest[count / 2] >> 4 == 0,
+            format_err!("Invalid padding")
+        );
+        if let Some(Step::Move(p, source, dest)) | Some(Step::Push(p, source, dest)) = last_step {
+            // The piece may have been captured on a trap by its last step
+            let captured =
+                TRAP_INDICES.contains(&(dest as usize)) && pieces[dest as usize] == Piece::Empty;
+            ensure!(
+                pieces[source as usize] == Piece::Empty && (pieces[dest as usize] == p || captured),
+                format_err!("Last step doesn't match the board")
+            );
+        }
+        let mut pos = Position::from_pieces(side, steps_left, pieces);
+        pos.plies = plies;
+        pos.last_step = last_step;
+        pos.current_hash = compute_hash(&pos.pieces, side, steps_left, last_step);
+        Ok(pos)
+    }
     pub fn from_pos_notation(notation: String) -> Result<Position, Error> {
         let lines: Vec<_> = notation.lines().collect();
         // Todo read initial moves
@@ -397,6 +724,11 @@ impl Position {
             's' | 'b' => Side::Black,
             _ => bail!("Invalid side to move"),
         };
+        let move_number: u16 = turn_info
+            .into_iter()
+            .collect::<String>()
+            .parse()
+            .unwrap_or(2);
         let mut pieces: [Piece; 64] = [Piece::Empty; 64];
         let mut index = 2 * alg_to_index(&['a', '8']).unwrap();
         for line_index in 2..10 {
@@ -419,50 +751,73 @@ impl Position {
                 index -= 32;
             }
         }
-        Ok(Self::from_pieces(side, 4, pieces))
+        let mut pos = Self::from_pieces(side, 4, pieces);
+        pos.plies = plies_from_move_number(move_number, side);
+        Ok(pos)
+    }
+    /// The move number as written in game records, e.g. 2 for the first move after setup
+    pub fn move_number(&self) -> u16 {
+        if self.plies < 16 {
+            1
+        } else {
+            (self.plies - 16) / 2 + 2
+        }
     }
     pub fn to_pos_notation(&self) -> String {
+        self.to_highlighted_notation(0)
+    }
+    /// The board notation with the squares in `highlight` drawn in reverse
+    /// video, for display on a terminal
+    pub fn to_highlighted_notation(&self, highlight: u64) -> String {
         let top_bot = " +-----------------+\n";
         let end = "   a b c d e f g h\n";
-        let mut rows = Vec::with_capacity(8);
-        let mut ptr = alg_to_index(&['a', '8']).unwrap();
-        for row_num in (1..=8).rev() {
-            let r = &self.pieces[ptr..ptr + 8];
-            let c_col = match r[2] {
-                Piece::Empty if row_num == 6 || row_num == 3 => {
-                    'x' // Trap
-                }
-                _ => r[2].into(),
-            };
-            let f_col = match r[5] {
-                Piece::Empty if row_num == 6 || row_num == 3 => {
-                    'x' // Trap
+        let side = match self.side {
+            Side::White => 'w',
+            Side::Black => 'b',
+        };
+        let mut diagram = format!("{}{}\n{}", self.move_number(), side, top_bot);
+        for row in (0..8).rev() {
+            diagram.push_str(&format!("{}|", row + 1));
+            for col in 0..8 {
+                let sq = row * 8 + col;
+                let c = match self.pieces[sq] {
+                    Piece::Empty if TRAP_INDICES.contains(&sq) => 'x', // Trap
+                    piece => piece.into(),
+                };
+                if highlight & (1 << sq) != 0 {
+                    diagram.push_str(&format!(" \x1b[7m{}\x1b[0m", c));
+                } else {
+                    diagram.push_str(&format!(" {}", c));
                 }
-                _ => r[5].into(),
-            };
-            let row_string = format!(
-                "{}| {} {} {} {} {} {} {} {} |\n",
-                row_num, r[0], r[1], c_col, r[3], r[4], f_col, r[6], r[7]
-            );
-            rows.push(row_string);
-            if ptr >= 8 {
-                ptr -= 8;
             }
+            diagram.push_str(" |\n");
+        }
+        diagram.push_str(top_bot);
+        diagram.push_str(end);
+        diagram
+    }
+    /// Pieces of either color which are frozen in place
+    pub fn frozen(&self) -> u64 {
+        self.strength_masks().1
+    }
+    /// For each piece type, the pieces of either color stronger than it, for
+    /// push and pull computation, along with the frozen pieces
+    fn strength_masks(&self) -> ([u64; 6], u64) {
+        let wneighbors = neighbors_of(self.placement[0]);
+        let bneighbors = neighbors_of(self.placement[1]);
+        let mut wstronger = self.placement[0];
+        let mut bstronger = self.placement[1];
+        let mut frozen = 0;
+        let mut stronger = [0; 6];
+        for pix in 1..7 {
+            // These masks are stronger relative to the current piece
+            wstronger ^= self.bitboards[pix];
+            bstronger ^= self.bitboards[pix + 6];
+            stronger[pix - 1] = wstronger | bstronger;
+            frozen |= self.bitboards[pix] & neighbors_of(bstronger) & (!wneighbors);
+            frozen |= self.bitboards[pix + 6] & neighbors_of(wstronger) & (!bneighbors);
         }
-        format!(
-            "7g Da1n Cc1n\n{}{}{}{}{}{}{}{}{}{}{}",
-            top_bot,
-            rows[0],
-            rows[1],
-            rows[2],
-            rows[3],
-            rows[4],
-            rows[5],
-            rows[6],
-            rows[7],
-            top_bot,
-            end
-        )
+        (stronger, frozen)
     }
     pub fn gen_steps(&self) -> Vec<Step> {
         let mut moves = Vec::new();
@@ -493,21 +848,7 @@ impl Position {
             }
             return moves;
         }
-        // Compute frozen
-        let wneighbors = neighbors_of(self.placement[0]);
-        let bneighbors = neighbors_of(self.placement[1]);
-        let mut wstronger = self.placement[0];
-        let mut bstronger = self.placement[1];
-        let mut frozen = 0;
-        let mut stronger = [0; 6]; // For push and pull computation
-        for pix in 1..7 {
-            // These masks are stronger relative to the current piece
-            wstronger ^= self.bitboards[pix];
-            bstronger ^= self.bitboards[pix + 6];
-            stronger[pix - 1] = wstronger | bstronger;
-            frozen |= self.bitboards[pix] & neighbors_of(bstronger) & (!wneighbors);
-            frozen |= self.bitboards[pix + 6] & neighbors_of(wstronger) & (!bneighbors);
-        }
+        let (stronger, frozen) = self.strength_masks();
         match self.last_step {
             // Continue push
             Some(Step::Push(p, source, _dest)) => {
@@ -546,7 +887,6 @@ impl Position {
                         }
                     }
                 }
-
             }
             _ => {}
         }
@@ -600,9 +940,24 @@ impl Position {
         moves
     }
     pub fn do_step(&mut self, step: Step) -> EndState {
-        // Todo finish
+        let res = self.apply_step(step);
+        #[cfg(feature = "consistency-checks")]
+        {
+            if let Err(e) = self.validate() {
+                panic!(
+                    "Inconsistent position after {}:\n{}\n{}",
+                    step,
+                    e,
+                    self.to_pos_notation()
+                );
+            }
+        }
+        res
+    }
+    fn apply_step(&mut self, step: Step) -> EndState {
         let res = match step {
             Step::Move(p, source, dest) | Step::Push(p, source, dest) => {
+                let prev_state = step_state_hash(self.side, self.steps_left, self.last_step);
                 self.steps_left -= 1;
                 let pix = p as usize;
                 self.pieces[source as usize] = Piece::Empty;
@@ -633,15 +988,17 @@ impl Position {
                     if friendly_neighbors == 0 {
                         let next_step =
                             Step::Remove(Piece::from_u8(pix as u8).unwrap(), *trap_sq as u8);
-                        self.do_step(next_step);
+                        self.apply_step(next_step);
                         break; // Each step can affect one trap at most
                     }
                 }
                 self.current_hash = update_hash(self.current_hash, step);
+                self.last_step = Some(step);
+                self.current_hash ^=
+                    prev_state ^ step_state_hash(self.side, self.steps_left, self.last_step);
                 if self.steps_left == 0 {
                     self.end_turn()
                 } else {
-                    self.last_step = Some(step); // This is the only push case
                     EndState::Neither
                 }
             }
@@ -658,6 +1015,7 @@ impl Position {
                     self.placement[1] ^= change;
                 }
                 self.bitboards[0] ^= change;
+                self.current_hash = update_hash(self.current_hash, step);
                 EndState::Neither
             }
             Step::Remove(p, sq) => {
@@ -679,8 +1037,51 @@ impl Position {
         };
         res
     }
+    /// Checks that the bitboards, placement masks and hash all agree with the
+    /// piece array, describing every mismatch found.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut problems = Vec::new();
+        let bitboards = bitboards_from_pieces(&self.pieces).unwrap();
+        for (pix, (found, expected)) in self.bitboards.iter().zip(bitboards.iter()).enumerate() {
+            if found != expected {
+                problems.push(format!(
+                    "bitboard {} is {:016x}, expected {:016x}",
+                    pix, found, expected
+                ));
+            }
+        }
+        let placement = [
+            bitboards[1..=6].iter().fold(0, |acc, bb| acc | bb),
+            bitboards[7..=12].iter().fold(0, |acc, bb| acc | bb),
+        ];
+        for (side, (found, expected)) in self.placement.iter().zip(placement.iter()).enumerate() {
+            if found != expected {
+                problems.push(format!(
+                    "placement {} is {:016x}, expected {:016x}",
+                    side, found, expected
+                ));
+            }
+        }
+        let hash = compute_hash(&self.pieces, self.side, self.steps_left, self.last_step);
+        if self.current_hash != hash {
+            problems.push(format!(
+                "hash is {:016x}, expected {:016x}",
+                self.current_hash, hash
+            ));
+        }
+        if self.steps_left > 4 {
+            problems.push(format!("{} steps left", self.steps_left));
+        }
+        ensure!(problems.is_empty(), format_err!("{}", problems.join("\n")));
+        Ok(())
+    }
     pub fn end_turn(&mut self) -> EndState {
-        if self.current_hash == self.initial_hash || self.current_hash == self.my_last {
+        // The hash this board would have at the start of a turn, for comparison
+        // against the history which is only recorded at turn boundaries
+        let restart_hash = self.current_hash
+            ^ step_state_hash(self.side, self.steps_left, self.last_step)
+            ^ step_state_hash(self.side, 4, None);
+        if restart_hash == self.initial_hash || restart_hash == self.my_last {
             // Null move or repetition
             return self.side.opposite().into();
         }
@@ -709,15 +1110,55 @@ impl Position {
             }
         }
         self.steps_left = 4;
+        self.plies += 1;
         self.my_last = self.opp_last;
         self.opp_last = self.initial_hash;
+        self.current_hash = restart_hash ^ color_hash(self.side) ^ color_hash(self.side.opposite());
         self.side = self.side.opposite();
-        self.current_hash ^= color_hash(self.side);
         self.initial_hash = self.current_hash;
         self.last_step = None;
         EndState::Neither
         // Todo repetitions
     }
+    /// Applies a board symmetry, recomputing the bitboards and hash from scratch.
+    /// Repetition history is not carried over, and the transformed position
+    /// becomes the reference point for detecting a null move this turn.
+    pub fn transform(&self, symmetry: Symmetry) -> Position {
+        let mut pieces = [Piece::Empty; 64];
+        for (sq, piece) in self.pieces.iter().enumerate() {
+            pieces[symmetry.square(sq as u8) as usize] = symmetry.piece(*piece);
+        }
+        let mut pos = Position::from_pieces(symmetry.side(self.side), self.steps_left, pieces);
+        pos.last_step = self.last_step.map(|s| symmetry.step(s));
+        pos.current_hash = compute_hash(&pos.pieces, pos.side, pos.steps_left, pos.last_step);
+        pos.plies = self.plies;
+        pos
+    }
+    /// Reflects the board left to right
+    pub fn mirror(&self) -> Position {
+        self.transform(Symmetry::Mirror)
+    }
+    /// Rotates the board 180 degrees and swaps the colors of all pieces
+    pub fn flip_colors(&self) -> Position {
+        self.transform(Symmetry::FlipColors)
+    }
+    /// Returns the symmetric variant with the smallest hash along with the
+    /// symmetry that produces it from this position
+    pub fn canonical(&self) -> (Position, Symmetry) {
+        Symmetry::ALL
+            .iter()
+            .map(|sym| (self.transform(*sym), *sym))
+            .min_by_key(|(pos, _)| pos.current_hash)
+            .unwrap()
+    }
+    /// A hash shared by all positions equivalent under `Symmetry`
+    pub fn canonical_hash(&self) -> u64 {
+        Symmetry::ALL
+            .iter()
+            .map(|sym| self.transform(*sym).current_hash)
+            .min()
+            .unwrap()
+    }
     pub fn from_opening_str(opening: &str) -> Option<Position> {
         let lines: Vec<&str> = opening.lines().collect();
         let mut pieces = [Piece::Empty; 64];
@@ -734,17 +1175,11 @@ impl Position {
                 } else {
                     assert!(val >= 7);
                 }
-                let index = match alg_to_index(&chs[1..]) {
-                    Some(index) => index,
-                    None => return None,
-                };
+                let index = alg_to_index(&chs[1..])?;
                 pieces[index] = Piece::from_u8(val).unwrap();
             }
         }
-        let bitboards = match bitboards_from_pieces(&pieces) {
-            Some(bb) => bb,
-            None => return None,
-        };
+        let bitboards = bitboards_from_pieces(&pieces)?;
         let mut placement = [0, 0];
         placement[0] |=
             bitboards[1] | bitboards[2] | bitboards[3] | bitboards[4] | bitboards[5] | bitboards[6];
@@ -754,7 +1189,7 @@ impl Position {
             | bitboards[10]
             | bitboards[11]
             | bitboards[12];
-        let hash = compute_hash(&pieces, Side::White);
+        let hash = compute_hash(&pieces, Side::White, 4, None);
         Some(Position {
             side: Side::White,
             steps_left: 4,
@@ -766,11 +1201,88 @@ impl Position {
             current_hash: hash,
             opp_last: 0,
             my_last: 0,
-            plies: 16,            
+            plies: 16,
         })
     }
 }
 
+/// The number of dense step indices, which `total_moves` also counts
+pub const NUM_STEP_INDICES: usize = STEP_OFFSETS[63] as usize + square_steps(63) as usize + 1;
+/// The dense index of the pass, after the steps of every square
+pub const PASS_STEP_INDEX: usize = NUM_STEP_INDICES - 1;
+
+/// The first step index of each square, see `Step::to_index`
+const STEP_OFFSETS: [u16; 64] = step_offsets();
+
+const fn step_offsets() -> [u16; 64] {
+    let mut offsets = [0; 64];
+    let mut cumulative = 0;
+    let mut sq = 0;
+    while sq < 64 {
+        offsets[sq] = cumulative;
+        cumulative += square_steps(sq);
+        sq += 1;
+    }
+    offsets
+}
+
+/// The number of step indices in the block of a square, as `total_moves`
+/// counts them: moves, then pushes and pulls through each neighbor
+const fn square_steps(sq: usize) -> u16 {
+    let root = 1 << sq;
+    let mut neighbors = neighbors_of(root);
+    let num_neighbors = neighbors.count_ones() as u16;
+    let mut total = num_neighbors;
+    while neighbors != 0 {
+        let lsb = neighbors & neighbors.wrapping_neg();
+        total += neighbors_of(lsb).count_ones() as u16 - 1; // Pushes
+        total += num_neighbors - 1; // Pulls
+        neighbors &= neighbors - 1;
+    }
+    total
+}
+
+/// The index of a move or first push step from `source` to `dest`
+fn step_index(source: u8, dest: u8) -> Option<usize> {
+    let rank = neighbor_rank(source, dest)?;
+    Some(STEP_OFFSETS[source as usize] as usize + rank)
+}
+
+/// The first index of the steps completing a push or pull through the
+/// `within`th neighbor of `root`
+fn completion_offset(root: u8, within: usize) -> usize {
+    let root_lsb = index_to_lsb(root);
+    let neighbors = neighbors_of(root_lsb);
+    let num_neighbors = neighbors.count_ones() as usize;
+    let mut offset = STEP_OFFSETS[root as usize] as usize + num_neighbors;
+    for lsb in PieceIter::new(neighbors).take(within) {
+        offset += (neighbors_of(lsb) & !root_lsb).count_ones() as usize + num_neighbors - 1;
+    }
+    offset
+}
+
+/// The position of `dest` among the neighbors of `source`
+fn neighbor_rank(source: u8, dest: u8) -> Option<usize> {
+    if source >= 64 {
+        return None;
+    }
+    bit_rank(neighbors_of(index_to_lsb(source)), dest)
+}
+
+/// The number of set bits of `bitboard` below square `sq`, which must be set
+fn bit_rank(bitboard: u64, sq: u8) -> Option<usize> {
+    let bit = 1u64.checked_shl(sq as u32)?;
+    if bitboard & bit == 0 {
+        return None;
+    }
+    Some((bitboard & (bit - 1)).count_ones() as usize)
+}
+
+/// The `n`th set bit of `bitboard`, counting from the least significant
+fn nth_bit(bitboard: u64, n: usize) -> u64 {
+    PieceIter::new(bitboard).nth(n).unwrap()
+}
+
 pub fn total_moves() -> u32 {
     let mut grand_total = 1; // Initial pass move
     let mut per_square = Vec::new();
@@ -785,12 +1297,12 @@ pub fn total_moves() -> u32 {
             let lsb = root_neighbors.isolate_lsb();
             assert_eq!(lsb.count_ones(), 1);
             let num_neighbors = neighbors_of(lsb).count_ones() - 1; // Ignore root
-            assert!(1 <= num_neighbors && num_neighbors <= 3);
+            assert!((1..=3).contains(&num_neighbors));
             total += num_neighbors; // Pushes
             total += num_r_neighbors - 1; // Pulls
             root_neighbors &= root_neighbors - 1;
         }
-        assert!(8 <= total && total <= 28);
+        assert!((8..=28).contains(&total));
         if total == 28 {
             interior_count += 1;
         }
@@ -800,14 +1312,14 @@ pub fn total_moves() -> u32 {
     assert_eq!(interior_count, 4 * 4);
     println!("Each square {:?}", per_square);
     let mut cumulative = 0;
-    for i in 0..per_square.len() {
-        let temp = per_square[i];
-        per_square[i] = cumulative;
+    for offset in per_square.iter_mut() {
+        let temp = *offset;
+        *offset = cumulative;
         cumulative += temp;
     }
     println!("Cumulative Offsets {:?}", per_square);
     println!("Grand Total {}", grand_total);
-    return grand_total;
+    grand_total
 }
 
 /// An iterator over the individual bits of a bitboard
@@ -829,7 +1341,7 @@ impl Iterator for PieceIter {
             return None;
         }
         let lsb = self.bitboard.isolate_lsb();
-        self.bitboard = self.bitboard ^ lsb;
+        self.bitboard ^= lsb;
         Some(lsb)
     }
 }
@@ -846,6 +1358,12 @@ impl NotationIter {
     }
 }
 
+impl Default for NotationIter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Iterator for NotationIter {
     type Item = usize;
 
@@ -871,11 +1389,26 @@ pub fn rabbit_steps(side: Side, lsb: u64) -> u64 {
     out
 }
 
+/// Reflects a square index across the middle of the board, swapping a and h files
+pub fn mirror_square(sq: u8) -> u8 {
+    sq ^ 7
+}
+
+/// Rotates a square index 180 degrees about the center of the board
+pub fn rotate_square(sq: u8) -> u8 {
+    63 - sq
+}
+
+/// Inverse of `Position::move_number`, counting plies from the start of setup
+pub fn plies_from_move_number(move_number: u16, side: Side) -> u16 {
+    16 + 2 * move_number.max(2).saturating_sub(2) + side as u16
+}
+
 pub fn index_to_lsb(index: u8) -> u64 {
     1 << index
 }
 
-pub fn neighbors_of(lsb: u64) -> u64 {
+pub const fn neighbors_of(lsb: u64) -> u64 {
     ((lsb & NOT_H_FILE) >> 1) | ((lsb & NOT_A_FILE) << 1) | (lsb >> 8) | (lsb << 8)
 }
 
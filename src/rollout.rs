@@ -0,0 +1,147 @@
+use crate::position::{EndState, Piece, Position, Side, Step};
+use rand::rngs::SmallRng;
+use rand::Rng;
+
+/// Material values indexed by piece type, ignoring color
+const PIECE_VALUES: [i32; 7] = [0, 100, 150, 200, 300, 500, 800];
+
+/// How steps are chosen during a rollout. The light policy plays uniformly
+/// random legal steps, while the heavy policy also takes a goal whenever one
+/// is available and avoids stepping into a capture when it has a choice.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RolloutPolicy {
+    pub goal_if_possible: bool,
+    pub avoid_sacrifice: bool,
+}
+
+impl RolloutPolicy {
+    pub fn light() -> RolloutPolicy {
+        RolloutPolicy {
+            goal_if_possible: false,
+            avoid_sacrifice: false,
+        }
+    }
+    pub fn heavy() -> RolloutPolicy {
+        RolloutPolicy {
+            goal_if_possible: true,
+            avoid_sacrifice: true,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RolloutConfig {
+    pub policy: RolloutPolicy,
+    /// Turns played before the rollout is cut off and scored by material
+    pub max_turns: u16,
+}
+
+impl Default for RolloutConfig {
+    fn default() -> Self {
+        RolloutConfig {
+            policy: RolloutPolicy::light(),
+            max_turns: 20,
+        }
+    }
+}
+
+/// The material difference from White's perspective
+pub fn material_balance(pos: &Position) -> i32 {
+    pos.pieces
+        .iter()
+        .map(|p| {
+            let pix = *p as usize;
+            if pix == 0 {
+                0
+            } else if pix <= 6 {
+                PIECE_VALUES[pix]
+            } else {
+                -PIECE_VALUES[pix - 6]
+            }
+        })
+        .sum()
+}
+
+/// Plays the position out with the configured policy, returning 1 or -1 if
+/// White or Black wins and the sign of the material balance if the rollout
+/// is cut off first
+pub fn rollout(pos: &Position, config: &RolloutConfig, rng: &mut SmallRng) -> i64 {
+    let mut pos = pos.clone();
+    // Rollouts don't play the setup phase
+    if pos.plies >= 16 {
+        let start_plies = pos.plies;
+        while pos.plies - start_plies < config.max_turns {
+            let step = choose_step(&pos, &config.policy, rng);
+            match pos.do_step(step) {
+                EndState::WhiteWin => return 1,
+                EndState::BlackWin => return -1,
+                EndState::Neither => {}
+            }
+        }
+    }
+    i64::from(material_balance(&pos).signum())
+}
+
+fn choose_step(pos: &Position, policy: &RolloutPolicy, rng: &mut SmallRng) -> Step {
+    let steps = pos.gen_steps();
+    let pass_is_safe = steps.contains(&Step::Pass) && passing_is_safe(pos);
+    let mut candidates: Vec<_> = steps
+        .iter()
+        .cloned()
+        .filter(|s| *s != Step::Pass || pass_is_safe)
+        .collect();
+    if candidates.is_empty() {
+        return steps[0]; // Every option loses
+    }
+    if policy.goal_if_possible {
+        let goal_rank = goal_rank(pos.side);
+        if pass_is_safe && pos.bitboards[rabbit(pos.side) as usize] & goal_rank != 0 {
+            return Step::Pass;
+        }
+        let goal = candidates.iter().find(|s| match s {
+            Step::Move(p, _, dest) => *p == rabbit(pos.side) && (1 << dest) & goal_rank != 0,
+            _ => false,
+        });
+        if let Some(step) = goal {
+            return *step;
+        }
+    }
+    if policy.avoid_sacrifice {
+        let safe: Vec<_> = candidates
+            .iter()
+            .cloned()
+            .filter(|s| !loses_piece(pos, *s))
+            .collect();
+        if !safe.is_empty() {
+            candidates = safe;
+        }
+    }
+    candidates[rng.gen_range(0, candidates.len())]
+}
+
+/// Passing is illegal if it leaves the board unchanged or repeats a position
+fn passing_is_safe(pos: &Position) -> bool {
+    let mut next = pos.clone();
+    next.do_step(Step::Pass) != EndState::from(pos.side.opposite())
+}
+
+fn loses_piece(pos: &Position, step: Step) -> bool {
+    let side = pos.side as usize;
+    let mut next = pos.clone();
+    next.do_step(step);
+    next.placement[side].count_ones() < pos.placement[side].count_ones()
+}
+
+fn rabbit(side: Side) -> Piece {
+    match side {
+        Side::White => Piece::WRabbit,
+        Side::Black => Piece::BRabbit,
+    }
+}
+
+fn goal_rank(side: Side) -> u64 {
+    match side {
+        Side::White => 0xFF00000000000000,
+        Side::Black => 0xFF,
+    }
+}
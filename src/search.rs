@@ -1,19 +1,516 @@
+use crate::book::OpeningBook;
+use crate::eval::{self, Evaluate};
+use crate::game::Move;
 use crate::position;
+use crate::rollout::{self, RolloutConfig};
+use crate::setup::SetupChooser;
+use crate::tablebase::Tablebases;
+use crate::transposition::{Bound, TranspositionTable, MIN_WIN_SCORE};
 use mcts::transposition_table::*;
-use mcts::tree_policy::*;
+use mcts::tree_policy::TreePolicy;
 use mcts::*;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+/// Longest principal variation reported, guarding against table cycles
+const MAX_PV_LENGTH: usize = 256;
+/// Playouts run between checks of the clock when both limits are set
+const PLAYOUT_CHUNK: u32 = 1024;
+/// Rewards are integers in the tree, so a win is worth this much and
+/// fractional values are rounded to a multiple of its inverse
+pub const REWARD_SCALE: i64 = 1000;
+
+/// Limits and tuning parameters for `search`. The search stops at whichever
+/// of `playouts` and `time_limit` is reached first.
+#[derive(Clone)]
+pub struct SearchConfig {
+    pub threads: usize,
+    pub playouts: Option<u32>,
+    pub time_limit: Option<Duration>,
+    /// The UCT exploration constant
+    pub exploration: f64,
+    /// Number of entries in the MCTS transposition table
+    pub table_size: usize,
+    /// Seeds the random choice between equally promising steps. A single
+    /// threaded search with a playout limit is fully deterministic.
+    pub seed: u64,
+    /// A shared table whose exact scores are used as leaf values
+    pub shared_table: Option<Arc<TranspositionTable>>,
+    /// Turn-level search only considers the first `c * n^e` turns of a node
+    /// visited `n` times, for constant `c` and exponent `e`
+    pub widening_constant: f64,
+    pub widening_exponent: f64,
+    /// Scores new leaves by a random playout instead of as a draw
+    pub rollout: Option<RolloutConfig>,
+    /// Scores new leaves, taking precedence over rollouts. Step priors from
+    /// the evaluator guide the step-level search.
+    pub evaluator: Option<Arc<dyn Evaluate>>,
+    /// Consulted by `choose_turn` at the start of a turn, playing a book turn
+    /// without searching
+    pub book: Option<Arc<OpeningBook>>,
+    /// Consulted by `choose_turn` at the start of a turn, playing the exact
+    /// best turn of a position in the tables without searching
+    pub tablebases: Option<Arc<Tablebases>>,
+    /// Chooses the whole setup when `choose_turn` is asked during setup
+    pub setup: SetupChooser,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        SearchConfig {
+            threads: 4,
+            playouts: Some(100_000),
+            time_limit: None,
+            exploration: 0.5,
+            table_size: 1024,
+            seed: 0,
+            shared_table: None,
+            widening_constant: 2.0,
+            widening_exponent: 0.5,
+            rollout: None,
+            evaluator: None,
+            book: None,
+            tablebases: None,
+            setup: SetupChooser::default(),
+        }
+    }
+}
+
+/// The outcome of a search over steps, or over whole turns for `search_turns`
+#[derive(Clone, Debug)]
+pub struct SearchResult<M = position::Step> {
+    /// The steps of the principal variation belonging to the side to move
+    pub best_move: Move,
+    /// Visits of each root move, most visited first
+    pub visits: Vec<(M, u64)>,
+    /// Expected score of the best move for the side to move, from 0 to 1
+    pub win_rate: f64,
+    pub pv: Vec<M>,
+    pub playouts: u64,
+    pub elapsed: Duration,
+}
 
 pub fn simple_search(game: ArimaaGame) -> Vec<position::Step> {
+    let mut pv = search(game, &SearchConfig::default()).pv;
+    pv.truncate(4);
+    pv
+}
+
+pub fn search(game: ArimaaGame, config: &SearchConfig) -> SearchResult {
+    let start = Instant::now();
     let mut mcts = MCTSManager::new(
         game,
         MyMCTS,
-        ArimaaEvaluator,
-        UCTPolicy::new(0.5),
-        ApproxTable::new(1024),
+        ArimaaEvaluator::new(config),
+        SeededUCTPolicy::new(config.exploration, config.seed),
+        ApproxTable::new(config.table_size),
+    );
+    run_playouts(&mut mcts, config, start);
+    summarize(&mcts, start.elapsed(), turn_prefix)
+}
+
+/// Chooses a whole turn by searching before each step, sharing any time
+/// limit evenly between the steps left. Passes are left out of the returned
+/// turn, which is empty only if there is nothing to play. A turn from the
+/// configured book is played instead when there is one, followed by the
+/// tablebases' best turn, and during setup the setup chooser places every
+/// piece, answering the opponent's setup if it is already on the board.
+pub fn choose_turn(pos: &position::Position, config: &SearchConfig) -> Move {
+    if pos.plies < 16 {
+        let (side, opponent) = if pos.plies < 8 {
+            (position::Side::White, 1)
+        } else {
+            (position::Side::Black, 0)
+        };
+        let placed = (0..64)
+            .filter(|sq| pos.placement[opponent] & (1 << sq) != 0)
+            .map(|sq| position::Step::Place(pos.pieces[sq], sq as u8))
+            .collect();
+        let opponent_setup = Move::new(placed);
+        return config.setup.choose(side, Some(&opponent_setup));
+    }
+    if let Some(book) = config.book.as_ref() {
+        if pos.steps_left == 4 {
+            if let Some(mov) = book.choose(pos, 1, config.seed) {
+                return mov;
+            }
+        }
+    }
+    if let Some(tablebases) = config.tablebases.as_ref() {
+        if let Some(mov) = tablebases.best_turn(pos) {
+            return mov;
+        }
+    }
+    let deadline = config.time_limit.map(|t| Instant::now() + t);
+    let mut pos = pos.clone();
+    let side = pos.side;
+    let mut steps = Vec::new();
+    while pos.side == side {
+        let step_config = SearchConfig {
+            time_limit: deadline.map(|d| {
+                let left = d.saturating_duration_since(Instant::now());
+                left / u32::from(pos.steps_left.max(1))
+            }),
+            ..config.clone()
+        };
+        let result = search(ArimaaGame::new(pos.clone()), &step_config);
+        let step = match result.visits.first() {
+            Some((step, _)) if *step != position::Step::Pass => *step,
+            _ => break,
+        };
+        steps.push(step);
+        if pos.do_step(step) != position::EndState::Neither {
+            break;
+        }
+    }
+    Move::new(steps)
+}
+
+/// The steps of `pv` played before the side to move changes or the game ends
+fn turn_prefix(root: &ArimaaGame, pv: &[position::Step]) -> Move {
+    let mut state = root.clone();
+    let mut steps = Vec::new();
+    for step in pv.iter() {
+        if state.pos.side != root.pos.side || state.end_state != position::EndState::Neither {
+            break;
+        }
+        steps.push(*step);
+        state.make_move(step);
+    }
+    Move::new(steps)
+}
+
+/// Searches with whole turns as the moves of the tree, widening each node
+/// progressively as set by the configuration
+pub fn search_turns(game: ArimaaTurnGame, config: &SearchConfig) -> SearchResult<Move> {
+    let start = Instant::now();
+    let policy = SeededUCTPolicy::new(config.exploration, config.seed)
+        .with_widening(config.widening_constant, config.widening_exponent);
+    let mut mcts = MCTSManager::new(
+        game,
+        TurnMCTS,
+        ArimaaEvaluator::new(config),
+        policy,
+        ApproxTable::new(config.table_size),
     );
-    mcts.playout_n_parallel(100000, 4);
-    mcts.principal_variation(4)
+    run_playouts(&mut mcts, config, start);
+    summarize(&mcts, start.elapsed(), |_, pv| {
+        pv.first().cloned().unwrap_or_else(|| Move::new(vec![]))
+    })
+}
+
+enum SessionState {
+    Idle(Box<MCTSManager<MyMCTS>>),
+    Pondering(AsyncSearchOwned<MyMCTS>),
+}
+
+/// A step-level search kept alive across turns, which can keep searching in
+/// the background while the opponent thinks.
+///
+/// The tree of the `mcts` crate can't be re-rooted, so `advance` builds a new
+/// tree for the position reached and carries over the old subtree below it:
+/// every position in that subtree is scored by the mean result of its earlier
+/// playouts when the new tree first reaches it.
+pub struct SearchSession {
+    config: SearchConfig,
+    root: ArimaaGame,
+    state: Option<SessionState>,
+}
+
+impl SearchSession {
+    pub fn new(game: ArimaaGame, config: SearchConfig) -> SearchSession {
+        let mcts = Self::manager(game.clone(), &config, HashMap::new());
+        SearchSession {
+            config,
+            root: game,
+            state: Some(SessionState::Idle(Box::new(mcts))),
+        }
+    }
+    pub fn root(&self) -> &ArimaaGame {
+        &self.root
+    }
+    pub fn is_pondering(&self) -> bool {
+        matches!(self.state, Some(SessionState::Pondering(_)))
+    }
+    /// Stops any pondering and searches the root within the configured
+    /// limits, adding to the playouts already in the tree
+    pub fn search(&mut self) -> SearchResult {
+        let start = Instant::now();
+        let mut mcts = self.stop_search();
+        run_playouts(&mut mcts, &self.config, start);
+        let result = summarize(&mcts, start.elapsed(), turn_prefix);
+        self.state = Some(SessionState::Idle(Box::new(mcts)));
+        result
+    }
+    /// Changes the limits used by later calls to `search`, for instance to
+    /// the time allocated for the current turn
+    pub fn set_limits(&mut self, playouts: Option<u32>, time_limit: Option<Duration>) {
+        self.config.playouts = playouts;
+        self.config.time_limit = time_limit;
+    }
+    /// Searches the root on the configured number of threads until `stop`,
+    /// `search` or `advance` is called
+    pub fn ponder(&mut self) {
+        let mcts = self.stop_search();
+        let search = mcts.into_playout_parallel_async(self.config.threads.max(1));
+        self.state = Some(SessionState::Pondering(search));
+    }
+    /// Stops pondering, keeping the playouts made so far
+    pub fn stop(&mut self) {
+        let mcts = self.stop_search();
+        self.state = Some(SessionState::Idle(Box::new(mcts)));
+    }
+    /// Moves the root forward by the given steps, which may span our move and
+    /// the opponent's reply, reusing what the tree knows about the new root
+    pub fn advance(&mut self, steps: &[position::Step]) {
+        let mcts = self.stop_search();
+        if steps.is_empty() {
+            self.state = Some(SessionState::Idle(Box::new(mcts)));
+            return;
+        }
+        let mut known = HashMap::new();
+        reuse_subtree(mcts.tree().root_node(), &self.root, steps, &mut known);
+        drop(mcts);
+        let mut game = self.root.clone();
+        for step in steps.iter() {
+            game.make_move(step);
+        }
+        self.root = game.clone();
+        self.state = Some(SessionState::Idle(Box::new(Self::manager(
+            game,
+            &self.config,
+            known,
+        ))));
+    }
+    fn stop_search(&mut self) -> MCTSManager<MyMCTS> {
+        match self.state.take() {
+            Some(SessionState::Idle(mcts)) => *mcts,
+            Some(SessionState::Pondering(search)) => search.halt(),
+            None => unreachable!("search session left without a tree"),
+        }
+    }
+    fn manager(
+        game: ArimaaGame,
+        config: &SearchConfig,
+        known: HashMap<u64, i64>,
+    ) -> MCTSManager<MyMCTS> {
+        let mut eval = ArimaaEvaluator::new(config);
+        if !known.is_empty() {
+            eval.known = Some(Arc::new(known));
+        }
+        MCTSManager::new(
+            game,
+            MyMCTS,
+            eval,
+            SeededUCTPolicy::new(config.exploration, config.seed),
+            ApproxTable::new(config.table_size),
+        )
+    }
+}
+
+/// Follows `steps` from `node` and collects what is known below the node
+/// reached, if the tree got that far
+fn reuse_subtree(
+    node: NodeHandle<MyMCTS>,
+    state: &ArimaaGame,
+    steps: &[position::Step],
+    known: &mut HashMap<u64, i64>,
+) {
+    match steps.split_first() {
+        None => collect_known(node, state, known),
+        Some((step, rest)) => {
+            let child = node.moves().find(|m| m.get_move() == step);
+            if let Some(child) = child.and_then(|m| m.child()) {
+                let mut next = state.clone();
+                next.make_move(step);
+                reuse_subtree(child, &next, rest, known);
+            }
+        }
+    }
+}
+
+/// Records the mean result, from White's perspective, of every
+/// visited position below `node`
+fn collect_known(node: NodeHandle<MyMCTS>, state: &ArimaaGame, known: &mut HashMap<u64, i64>) {
+    let mover = state.current_player();
+    for m in node.moves() {
+        let child = match m.child() {
+            Some(child) if m.visits() > 0 => child,
+            _ => continue,
+        };
+        let mut next = state.clone();
+        next.make_move(m.get_move());
+        if known.contains_key(&next.pos.current_hash) {
+            continue; // Reached by transposition or a cycle
+        }
+        let eval = (m.sum_rewards() as f64 / m.visits() as f64).round() as i64;
+        let eval = match mover {
+            position::Side::White => eval,
+            position::Side::Black => -eval,
+        };
+        known.insert(next.pos.current_hash, eval);
+        collect_known(child, &next, known);
+    }
+}
+
+fn run_playouts<Spec: MCTS>(mcts: &mut MCTSManager<Spec>, config: &SearchConfig, start: Instant)
+where
+    ThreadData<Spec>: Default,
+{
+    let threads = config.threads.max(1);
+    match (config.playouts, config.time_limit) {
+        (None, Some(limit)) if threads > 1 => mcts.playout_parallel_for(limit, threads),
+        (playouts, limit) => {
+            let playouts = playouts.unwrap_or(u32::MAX);
+            let mut done = 0;
+            while done < playouts && limit.is_none_or(|t| start.elapsed() < t) {
+                let chunk = if limit.is_some() {
+                    PLAYOUT_CHUNK.min(playouts - done)
+                } else {
+                    playouts - done
+                };
+                if threads == 1 {
+                    mcts.playout_n(u64::from(chunk));
+                } else {
+                    mcts.playout_n_parallel(chunk, threads);
+                }
+                done += chunk;
+            }
+        }
+    }
+}
+
+fn summarize<Spec, F>(
+    mcts: &MCTSManager<Spec>,
+    elapsed: Duration,
+    best_move: F,
+) -> SearchResult<mcts::Move<Spec>>
+where
+    Spec: MCTS,
+    ThreadData<Spec>: Default,
+    F: FnOnce(&Spec::State, &[mcts::Move<Spec>]) -> Move,
+{
+    let root = mcts.tree().root_node();
+    let mut visits: Vec<_> = root
+        .moves()
+        .map(|m| (m.get_move().clone(), m.visits()))
+        .collect();
+    visits.sort_by_key(|&(_, v)| std::cmp::Reverse(v));
+    let playouts = visits.iter().map(|(_, v)| v).sum();
+    let win_rate = match root.moves().max_by_key(|m| m.visits()) {
+        Some(m) if m.visits() > 0 => {
+            let mean = m.sum_rewards() as f64 / m.visits() as f64;
+            (mean / REWARD_SCALE as f64 + 1.0) / 2.0
+        }
+        _ => 0.5,
+    };
+    let pv = mcts.principal_variation(MAX_PV_LENGTH);
+    SearchResult {
+        best_move: best_move(mcts.tree().root_state(), &pv),
+        visits,
+        win_rate,
+        pv,
+        playouts,
+        elapsed,
+    }
+}
+
+/// UCT with ties broken by a random number generator derived from the
+/// configured seed, one stream per search thread. With progressive widening
+/// only a prefix of each node's moves, growing with its visits, is considered.
+/// Moves given a prior by the evaluator are explored by PUCT instead.
+pub struct SeededUCTPolicy {
+    exploration_constant: f64,
+    seed: u64,
+    streams: AtomicU64,
+    widening: Option<(f64, f64)>,
+}
+
+impl SeededUCTPolicy {
+    pub fn new(exploration_constant: f64, seed: u64) -> Self {
+        assert!(
+            exploration_constant > 0.0,
+            "exploration constant is {} (must be positive)",
+            exploration_constant
+        );
+        SeededUCTPolicy {
+            exploration_constant,
+            seed,
+            streams: AtomicU64::new(0),
+            widening: None,
+        }
+    }
+    pub fn with_widening(mut self, constant: f64, exponent: f64) -> Self {
+        self.widening = Some((constant, exponent));
+        self
+    }
+}
+
+impl<Spec: MCTS<TreePolicy = Self>> TreePolicy<Spec> for SeededUCTPolicy {
+    type MoveEvaluation = Option<f64>;
+    type ThreadLocalData = Option<SmallRng>;
+
+    fn choose_child<'a, MoveIter>(
+        &self,
+        moves: MoveIter,
+        mut handle: SearchHandle<Spec>,
+    ) -> &'a MoveInfo<Spec>
+    where
+        MoveIter: Iterator<Item = &'a MoveInfo<Spec>> + Clone,
+    {
+        let total_visits = moves.clone().map(|x| x.visits()).sum::<u64>();
+        let ln_adjusted_total = ((total_visits + 1) as f64).ln();
+        let width = match self.widening {
+            Some((constant, exponent)) => {
+                let width = constant * ((total_visits + 1) as f64).powf(exponent);
+                (width.ceil() as usize).max(1)
+            }
+            None => usize::MAX,
+        };
+        let rng = handle.thread_data().policy_data.get_or_insert_with(|| {
+            let stream = self.streams.fetch_add(1, Ordering::Relaxed);
+            SmallRng::seed_from_u64(self.seed.wrapping_add(stream))
+        });
+        let mut choice = None;
+        let mut num_optimal = 0;
+        let mut best_so_far = f64::NEG_INFINITY;
+        for mov in moves.take(width) {
+            let child_visits = mov.visits();
+            let mean_action_value = if child_visits == 0 {
+                0.0
+            } else {
+                mov.sum_rewards() as f64 / (child_visits * REWARD_SCALE as u64) as f64
+            };
+            let score = match mov.move_evaluation() {
+                // PUCT, exploring in proportion to the prior
+                Some(prior) => {
+                    let explore_term =
+                        prior * (total_visits as f64).sqrt() / (1 + child_visits) as f64;
+                    self.exploration_constant * explore_term + mean_action_value
+                }
+                None if child_visits == 0 => f64::INFINITY,
+                None => {
+                    let explore_term = 2.0 * (ln_adjusted_total / child_visits as f64).sqrt();
+                    self.exploration_constant * explore_term + mean_action_value
+                }
+            };
+            if score > best_so_far {
+                choice = Some(mov);
+                num_optimal = 1;
+                best_so_far = score;
+            } else if score == best_so_far {
+                num_optimal += 1;
+                if rng.gen_range(0, num_optimal) == 0 {
+                    choice = Some(mov);
+                }
+            }
+        }
+        choice.unwrap()
+    }
 }
 
 #[derive(Default)]
@@ -24,7 +521,7 @@ impl MCTS for MyMCTS {
     type Eval = ArimaaEvaluator;
     type NodeData = ();
     type ExtraThreadData = ();
-    type TreePolicy = UCTPolicy;
+    type TreePolicy = SeededUCTPolicy;
     type TranspositionTable = ApproxTable<Self>;
 
     fn cycle_behaviour(&self) -> CycleBehaviour<Self> {
@@ -56,7 +553,7 @@ impl GameState for ArimaaGame {
         if self.pos.plies < 8 {
             position::Side::White
         } else if self.pos.plies < 16 {
-            position::Side::White
+            position::Side::Black
         } else {
             self.pos.side
         }
@@ -82,7 +579,168 @@ impl TranspositionHash for ArimaaGame {
     }
 }
 
-struct ArimaaEvaluator;
+#[derive(Default)]
+struct TurnMCTS;
+
+impl MCTS for TurnMCTS {
+    type State = ArimaaTurnGame;
+    type Eval = ArimaaEvaluator;
+    type NodeData = ();
+    type ExtraThreadData = ();
+    type TreePolicy = SeededUCTPolicy;
+    type TranspositionTable = ApproxTable<Self>;
+
+    fn cycle_behaviour(&self) -> CycleBehaviour<Self> {
+        CycleBehaviour::UseCurrentEvalWhenCycleDetected
+    }
+}
+
+/// A game whose moves are whole turns, so that the player to move alternates
+/// at every level of the tree
+#[derive(Clone)]
+pub struct ArimaaTurnGame {
+    pub pos: position::Position,
+    pub end_state: position::EndState,
+}
+
+impl ArimaaTurnGame {
+    pub fn new(pos: position::Position) -> ArimaaTurnGame {
+        ArimaaTurnGame {
+            pos,
+            end_state: position::EndState::Neither,
+        }
+    }
+}
+
+impl GameState for ArimaaTurnGame {
+    type Move = Move;
+    type Player = position::Side;
+    type MoveList = Vec<Move>;
+
+    fn current_player(&self) -> Self::Player {
+        self.pos.side
+    }
+
+    /// Distinct turns ordered with winning turns first, then by material
+    /// captured, as progressive widening considers the earliest turns first
+    fn available_moves(&self) -> Self::MoveList {
+        if self.end_state != position::EndState::Neither {
+            return vec![];
+        }
+        let side = self.pos.side as usize;
+        let mut turns = Move::all_turns(&self.pos);
+        turns.sort_by_key(|(_, pos)| {
+            let won = pos.side == self.pos.side;
+            let balance = pos.placement[1 - side].count_ones() as i32
+                - pos.placement[side].count_ones() as i32;
+            (!won, balance)
+        });
+        turns.into_iter().map(|(m, _)| m).collect()
+    }
+
+    fn make_move(&mut self, mov: &Self::Move) {
+        for step in mov.steps.iter() {
+            self.end_state = self.pos.do_step(*step);
+        }
+    }
+}
+
+impl TranspositionHash for ArimaaTurnGame {
+    fn hash(&self) -> u64 {
+        self.pos.current_hash
+    }
+}
+
+struct ArimaaEvaluator {
+    table: Option<Arc<TranspositionTable>>,
+    /// Leaf values carried over from an earlier tree by `SearchSession`
+    known: Option<Arc<HashMap<u64, i64>>>,
+    rollout: Option<RolloutConfig>,
+    evaluator: Option<Arc<dyn Evaluate>>,
+    seed: u64,
+}
+
+impl ArimaaEvaluator {
+    fn new(config: &SearchConfig) -> Self {
+        ArimaaEvaluator {
+            table: config.shared_table.clone(),
+            known: None,
+            rollout: config.rollout,
+            evaluator: config.evaluator.clone(),
+            seed: config.seed,
+        }
+    }
+    fn evaluate(&self, pos: &position::Position, end_state: &position::EndState) -> i64 {
+        match end_state {
+            position::EndState::WhiteWin => REWARD_SCALE,
+            position::EndState::BlackWin => -REWARD_SCALE,
+            position::EndState::Neither => self
+                .table_eval(pos)
+                .or_else(|| self.known.as_ref()?.get(&pos.current_hash).cloned())
+                .or_else(|| self.evaluator_eval(pos))
+                .or_else(|| self.rollout_eval(pos))
+                .unwrap_or(0),
+        }
+    }
+    fn evaluator_eval(&self, pos: &position::Position) -> Option<i64> {
+        let value = self.evaluator.as_ref()?.value(pos).clamp(-1.0, 1.0);
+        let eval = (value * REWARD_SCALE as f64).round() as i64;
+        match pos.side {
+            position::Side::White => Some(eval),
+            position::Side::Black => Some(-eval),
+        }
+    }
+    /// Priors for the steps of a new node, normalized to sum to one
+    fn priors(&self, pos: &position::Position, steps: &[position::Step]) -> Vec<Option<f64>> {
+        let priors = self
+            .evaluator
+            .as_ref()
+            .filter(|_| !steps.is_empty())
+            .and_then(|e| e.priors(pos, steps))
+            .filter(|p| p.len() == steps.len());
+        match priors {
+            Some(priors) => {
+                let total: f64 = priors.iter().map(|p| p.max(0.0)).sum();
+                priors
+                    .iter()
+                    .map(|p| {
+                        if total > 0.0 {
+                            Some(p.max(0.0) / total)
+                        } else {
+                            Some(1.0 / steps.len() as f64)
+                        }
+                    })
+                    .collect()
+            }
+            None => vec![None; steps.len()],
+        }
+    }
+    /// Rollouts are seeded by the position as well as the search seed, so the
+    /// value of a leaf doesn't depend on which thread reached it first
+    fn rollout_eval(&self, pos: &position::Position) -> Option<i64> {
+        let config = self.rollout.as_ref()?;
+        let mut rng = SmallRng::seed_from_u64(self.seed ^ pos.current_hash);
+        Some(rollout::rollout(pos, config, &mut rng) * REWARD_SCALE)
+    }
+    /// An exact table score converted to White's perspective. Proven wins and
+    /// losses are worth a full reward, and other scores are squashed like a
+    /// handcrafted evaluation.
+    fn table_eval(&self, pos: &position::Position) -> Option<i64> {
+        let entry = self.table.as_ref()?.probe(pos)?;
+        if entry.bound != Bound::Exact {
+            return None;
+        }
+        let eval = if entry.score.abs() >= MIN_WIN_SCORE {
+            i64::from(entry.score.signum()) * REWARD_SCALE
+        } else {
+            (eval::score_value(f64::from(entry.score)) * REWARD_SCALE as f64).round() as i64
+        };
+        match pos.side {
+            position::Side::White => Some(eval),
+            position::Side::Black => Some(-eval),
+        }
+    }
+}
 
 impl Evaluator<MyMCTS> for ArimaaEvaluator {
     type StateEvaluation = i64;
@@ -92,13 +750,9 @@ impl Evaluator<MyMCTS> for ArimaaEvaluator {
         state: &ArimaaGame,
         moves: &Vec<position::Step>,
         _: Option<SearchHandle<MyMCTS>>,
-    ) -> (Vec<()>, i64) {
-        let eval = match state.end_state {
-            position::EndState::WhiteWin => 1,
-            position::EndState::BlackWin => -1,
-            position::EndState::Neither => 0,
-        };
-        (vec![(); moves.len()], eval)
+    ) -> (Vec<Option<f64>>, i64) {
+        let eval = self.evaluate(&state.pos, &state.end_state);
+        (self.priors(&state.pos, moves), eval)
     }
     fn interpret_evaluation_for_player(&self, evaln: &i64, player: &position::Side) -> i64 {
         match player {
@@ -110,3 +764,39 @@ impl Evaluator<MyMCTS> for ArimaaEvaluator {
         *evaln
     }
 }
+
+impl Evaluator<TurnMCTS> for ArimaaEvaluator {
+    type StateEvaluation = i64;
+
+    fn evaluate_new_state(
+        &self,
+        state: &ArimaaTurnGame,
+        moves: &Vec<Move>,
+        _: Option<SearchHandle<TurnMCTS>>,
+    ) -> (Vec<Option<f64>>, i64) {
+        // Turns that lose at once are left out, so a side without turns has
+        // lost whether or not it has any legal steps
+        let end_state = if moves.is_empty() && state.end_state == position::EndState::Neither {
+            state.pos.side.opposite().into()
+        } else {
+            state.end_state.clone()
+        };
+        let eval = self.evaluate(&state.pos, &end_state);
+        // Priors are given for steps, which turns don't map onto
+        (vec![None; moves.len()], eval)
+    }
+    fn interpret_evaluation_for_player(&self, evaln: &i64, player: &position::Side) -> i64 {
+        match player {
+            position::Side::White => *evaln,
+            position::Side::Black => -*evaln,
+        }
+    }
+    fn evaluate_existing_state(
+        &self,
+        _: &ArimaaTurnGame,
+        evaln: &i64,
+        _: SearchHandle<TurnMCTS>,
+    ) -> i64 {
+        *evaln
+    }
+}
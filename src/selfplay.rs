@@ -0,0 +1,347 @@
+use crate::position::{index_to_lsb, neighbors_of, EndState, Piece, Position, Side, Step};
+use crate::search::{self, ArimaaGame, SearchConfig};
+use crate::zobrist::compute_hash;
+use failure::{bail, ensure, format_err, Error};
+use num_traits::FromPrimitive;
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::sync::mpsc;
+use std::thread;
+
+const MAGIC: &[u8; 4] = b"ARSP";
+const VERSION: u8 = 2;
+const NO_STEP: [u8; 2] = [0xFF, 0xFF];
+/// Set on the piece of the last step if it was the first step of a push
+const PUSH_BIT: u8 = 0x80;
+/// Pieces in a setup, placed on the home ranks in random order
+const SETUP_PIECES: [u8; 16] = [1, 1, 1, 1, 1, 1, 1, 1, 2, 2, 3, 3, 4, 4, 5, 6];
+
+#[derive(Clone)]
+pub struct SelfPlayConfig {
+    pub games: u64,
+    /// Games played at once, each searching on a single thread
+    pub threads: usize,
+    pub seed: u64,
+    /// The search run before every step. Its thread count and seed are
+    /// replaced so that each game is reproducible.
+    pub search: SearchConfig,
+    /// Turns after which a game is stopped without a result
+    pub max_turns: u16,
+    /// Turns at the start of a game whose steps are sampled in proportion to
+    /// their visits instead of playing the most visited step
+    pub sampling_turns: u16,
+    /// Starting positions chosen from at random. Random setups are used if
+    /// this is empty.
+    pub setups: Vec<Position>,
+}
+
+impl Default for SelfPlayConfig {
+    fn default() -> Self {
+        SelfPlayConfig {
+            games: 1,
+            threads: 4,
+            seed: 0,
+            search: SearchConfig {
+                playouts: Some(800),
+                ..Default::default()
+            },
+            max_turns: 120,
+            sampling_turns: 4,
+            setups: Vec::new(),
+        }
+    }
+}
+
+/// A position from a game along with the visits of each step searched there
+#[derive(Clone, PartialEq)]
+pub struct PositionRecord {
+    pub position: Position,
+    pub visits: Vec<(Step, u64)>,
+}
+
+#[derive(Clone, PartialEq)]
+pub struct GameRecord {
+    pub positions: Vec<PositionRecord>,
+    /// `EndState::Neither` if the game hit the turn limit
+    pub result: EndState,
+}
+
+/// A position where both sides placed their pieces on their home ranks in a
+/// random order
+pub fn random_setup(rng: &mut SmallRng) -> Position {
+    let mut pieces = [Piece::Empty; 64];
+    for (side, squares) in [(0, 0..16), (6, 48..64)].iter().cloned() {
+        let mut setup = SETUP_PIECES;
+        setup.shuffle(rng);
+        for (sq, pix) in squares.zip(setup.iter()) {
+            pieces[sq] = Piece::from_u8(pix + side).unwrap();
+        }
+    }
+    Position::from_pieces(Side::White, 4, pieces)
+}
+
+/// Plays a game from `setup`, searching every step. The game is fully
+/// determined by the configuration and `seed`.
+pub fn play_game(setup: &Position, config: &SelfPlayConfig, seed: u64) -> GameRecord {
+    let mut rng = SmallRng::seed_from_u64(seed);
+    let mut search_config = SearchConfig {
+        threads: 1,
+        ..config.search.clone()
+    };
+    let mut pos = setup.clone();
+    let mut positions = Vec::new();
+    let mut result = EndState::Neither;
+    let last_ply = setup.plies.saturating_add(2 * config.max_turns);
+    while pos.plies < last_ply {
+        search_config.seed = rng.gen();
+        let searched = search::search(ArimaaGame::new(pos.clone()), &search_config);
+        let visits = searched.visits;
+        if visits.is_empty() {
+            break;
+        }
+        let step = if pos.plies < setup.plies + 2 * config.sampling_turns {
+            sample_step(&visits, &mut rng)
+        } else {
+            visits[0].0
+        };
+        positions.push(PositionRecord {
+            position: pos.clone(),
+            visits,
+        });
+        result = pos.do_step(step);
+        if result != EndState::Neither {
+            break;
+        }
+    }
+    GameRecord { positions, result }
+}
+
+fn sample_step(visits: &[(Step, u64)], rng: &mut SmallRng) -> Step {
+    let total: u64 = visits.iter().map(|(_, v)| v).sum();
+    if total == 0 {
+        return visits[0].0;
+    }
+    let mut choice = rng.gen_range(0, total);
+    for (step, v) in visits.iter() {
+        if choice < *v {
+            return *step;
+        }
+        choice -= v;
+    }
+    unreachable!()
+}
+
+/// Plays the configured number of games across the configured threads and
+/// writes them to `out`. Games are written in order, so the output only
+/// depends on the configuration and not on the number of threads.
+pub fn generate<W: Write>(config: &SelfPlayConfig, out: W) -> Result<(), Error> {
+    let mut writer = GameWriter::new(out)?;
+    let threads = (config.threads.max(1) as u64).min(config.games.max(1));
+    let (sender, receiver) = mpsc::channel();
+    let workers: Vec<_> = (0..threads)
+        .map(|t| {
+            let sender = sender.clone();
+            let config = config.clone();
+            thread::spawn(move || {
+                for game in (t..config.games).step_by(threads as usize) {
+                    let seed = config.seed.wrapping_add(game);
+                    let mut rng = SmallRng::seed_from_u64(seed);
+                    let setup = match config.setups.choose(&mut rng) {
+                        Some(setup) => setup.clone(),
+                        None => random_setup(&mut rng),
+                    };
+                    let record = play_game(&setup, &config, rng.gen());
+                    if sender.send((game, record)).is_err() {
+                        return; // The writer failed
+                    }
+                }
+            })
+        })
+        .collect();
+    drop(sender);
+    let mut pending = BTreeMap::new();
+    let mut next = 0;
+    for (game, record) in receiver.iter() {
+        pending.insert(game, record);
+        while let Some(record) = pending.remove(&next) {
+            writer.write(&record)?;
+            next += 1;
+        }
+    }
+    for worker in workers {
+        worker
+            .join()
+            .map_err(|_| format_err!("Self-play thread panicked"))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes games in the self-play format: a four byte magic number and a
+/// version byte, then each game as its number of positions (u32), its result
+/// (i8, 1 for White) and its positions. A position is its 64 piece codes, side,
+/// steps left, ply count (u16) and last step, followed by the number of
+/// searched steps (u16) and each step with its visits (u32). Searched steps
+/// are their dense index (u16). The last step is its source and destination
+/// squares (u8 each), or two 255 bytes if there is none, and the moving piece
+/// (u8) with the top bit set for a push. Integers are little endian.
+pub struct GameWriter<W: Write> {
+    out: W,
+}
+
+impl<W: Write> GameWriter<W> {
+    pub fn new(mut out: W) -> io::Result<GameWriter<W>> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        Ok(GameWriter { out })
+    }
+    /// Writes a game, failing without writing anything if a searched step has
+    /// no dense index or more visits than fit in the format
+    pub fn write(&mut self, game: &GameRecord) -> io::Result<()> {
+        let result: i8 = match game.result {
+            EndState::WhiteWin => 1,
+            EndState::BlackWin => -1,
+            EndState::Neither => 0,
+        };
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&(game.positions.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&result.to_le_bytes());
+        for record in game.positions.iter() {
+            let pos = &record.position;
+            bytes.extend(pos.pieces.iter().map(|p| *p as u8));
+            bytes.push(pos.side as u8);
+            bytes.push(pos.steps_left);
+            bytes.extend_from_slice(&pos.plies.to_le_bytes());
+            let (last, piece) = match pos.last_step {
+                Some(Step::Move(p, source, dest)) => ([source, dest], p as u8),
+                Some(Step::Push(p, source, dest)) => ([source, dest], p as u8 | PUSH_BIT),
+                _ => (NO_STEP, 0),
+            };
+            bytes.extend_from_slice(&last);
+            bytes.push(piece);
+            let num_visits = u16::try_from(record.visits.len())
+                .map_err(|_| invalid_input("Too many searched steps"))?;
+            bytes.extend_from_slice(&num_visits.to_le_bytes());
+            for (step, v) in record.visits.iter() {
+                let index = step
+                    .to_index(pos)
+                    .ok_or_else(|| invalid_input(&format!("Step {} has no index", step)))?;
+                let v = u32::try_from(*v).map_err(|_| invalid_input("Too many visits"))?;
+                bytes.extend_from_slice(&(index as u16).to_le_bytes());
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        self.out.write_all(&bytes)
+    }
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+fn invalid_input(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Reads back the games written by `GameWriter`
+pub struct GameReader<R: Read> {
+    input: R,
+}
+
+impl<R: Read> GameReader<R> {
+    pub fn new(mut input: R) -> Result<GameReader<R>, Error> {
+        let mut header = [0; 5];
+        input.read_exact(&mut header)?;
+        ensure!(&header[..4] == MAGIC, format_err!("Not a self-play file"));
+        ensure!(
+            header[4] == VERSION,
+            format_err!("Unsupported self-play version {}", header[4])
+        );
+        Ok(GameReader { input })
+    }
+    fn read_game(&mut self, num_positions: u32) -> Result<GameRecord, Error> {
+        let result = match self.read_bytes::<1>()?[0] as i8 {
+            1 => EndState::WhiteWin,
+            -1 => EndState::BlackWin,
+            0 => EndState::Neither,
+            r => bail!("Invalid game result {}", r),
+        };
+        let mut positions = Vec::with_capacity(num_positions as usize);
+        for _ in 0..num_positions {
+            let board = self.read_bytes::<70>()?;
+            let mut pieces = [Piece::Empty; 64];
+            for (piece, code) in pieces.iter_mut().zip(board.iter()) {
+                *piece = Piece::from_u8(*code).ok_or(format_err!("Invalid piece {}", code))?;
+            }
+            let side = match board[64] {
+                0 => Side::White,
+                1 => Side::Black,
+                s => bail!("Invalid side {}", s),
+            };
+            let steps_left = board[65];
+            ensure!(steps_left <= 4, format_err!("Invalid steps left"));
+            let mut pos = Position::from_pieces(side, steps_left, pieces);
+            pos.plies = u16::from_le_bytes([board[66], board[67]]);
+            let last = [board[68], board[69]];
+            let piece = self.read_bytes::<1>()?[0];
+            if last != NO_STEP {
+                let (source, dest) = (last[0], last[1]);
+                ensure!(
+                    source < 64 && neighbors_of(index_to_lsb(source)) & (1 << dest) != 0,
+                    format_err!("Invalid last step {} {}", source, dest)
+                );
+                let pix = piece & !PUSH_BIT;
+                let p = Piece::from_u8(pix)
+                    .filter(|p| *p != Piece::Empty)
+                    .ok_or(format_err!("Invalid piece {}", pix))?;
+                let step = if piece & PUSH_BIT != 0 {
+                    Step::Push(p, source, dest)
+                } else {
+                    Step::Move(p, source, dest)
+                };
+                pos.last_step = Some(step);
+                pos.current_hash = compute_hash(&pos.pieces, side, steps_left, pos.last_step);
+            }
+            let num_visits = u16::from_le_bytes(self.read_bytes::<2>()?);
+            let mut visits = Vec::with_capacity(num_visits as usize);
+            for _ in 0..num_visits {
+                let entry = self.read_bytes::<6>()?;
+                let index = u16::from_le_bytes([entry[0], entry[1]]);
+                let v = u32::from_le_bytes([entry[2], entry[3], entry[4], entry[5]]);
+                let step = Step::from_index(index as usize, &pos)
+                    .ok_or(format_err!("Invalid step {}", index))?;
+                visits.push((step, u64::from(v)));
+            }
+            positions.push(PositionRecord {
+                position: pos,
+                visits,
+            });
+        }
+        Ok(GameRecord { positions, result })
+    }
+    fn read_bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.input.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl<R: Read> Iterator for GameReader<R> {
+    type Item = Result<GameRecord, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut len = [0; 4];
+        match self.input.read(&mut len[..1]) {
+            Ok(0) => return None,
+            Ok(_) => {}
+            Err(e) => return Some(Err(e.into())),
+        }
+        if let Err(e) = self.input.read_exact(&mut len[1..]) {
+            return Some(Err(e.into()));
+        }
+        Some(self.read_game(u32::from_le_bytes(len)))
+    }
+}
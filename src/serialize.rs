@@ -0,0 +1,225 @@
+//! Serde support, enabled by the `serde` feature. Types are written in the
+//! notations used by game records rather than as bitboards:
+//!
+//! | Type       | Form                                                        |
+//! |------------|-------------------------------------------------------------|
+//! | `Side`     | `"w"` or `"b"`, reading `"g"` and `"s"` as well             |
+//! | `Piece`    | Its letter, such as `"E"` or `"r"`, or `" "` when empty     |
+//! | `Step`     | Standard step notation such as `"Ee2n"`, or `"pass"`        |
+//! | `Move`     | Its steps separated by spaces                               |
+//! | `Position` | The board in small notation along with the side to move,    |
+//! |            | steps left, ply count and the last step of the turn         |
+//! | `Game`     | Its tags, every turn including the setups, and the winner   |
+//!
+//! Pushes are written like any other move. Positions mark a pending push
+//! separately, and games work out pushes and pulls as their turns are
+//! replayed. The repetition history of a position is not written.
+use crate::game::{Game, Move};
+use crate::position::{piece_char_index, EndState, Piece, Position, Side, Step, TRAP_INDICES};
+use crate::zobrist::compute_hash;
+use num_traits::FromPrimitive;
+use serde::de::{self, Deserializer};
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+impl Serialize for Side {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match self {
+            Side::White => "w",
+            Side::Black => "b",
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for Side {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Side, D::Error> {
+        let side = String::deserialize(deserializer)?;
+        match side.as_str() {
+            "w" | "g" => Ok(Side::White),
+            "b" | "s" => Ok(Side::Black),
+            _ => Err(de::Error::custom(format!("Invalid side {}", side))),
+        }
+    }
+}
+
+impl Serialize for Piece {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_char(char::from(*self))
+    }
+}
+
+impl<'de> Deserialize<'de> for Piece {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Piece, D::Error> {
+        let ch = char::deserialize(deserializer)?;
+        match piece_char_index(ch) {
+            0 if ch != ' ' => Err(de::Error::custom(format!("Invalid piece {}", ch))),
+            pix => Ok(Piece::from_u8(pix).unwrap()),
+        }
+    }
+}
+
+fn step_notation(step: &Step) -> String {
+    match step {
+        Step::Pass => "pass".to_string(),
+        step => step.to_string(),
+    }
+}
+
+fn parse_step(notation: &str) -> Result<Step, failure::Error> {
+    match notation {
+        "pass" => Ok(Step::Pass),
+        notation => notation.parse(),
+    }
+}
+
+impl Serialize for Step {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&step_notation(self))
+    }
+}
+
+impl<'de> Deserialize<'de> for Step {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Step, D::Error> {
+        let notation = String::deserialize(deserializer)?;
+        parse_step(&notation).map_err(de::Error::custom)
+    }
+}
+
+impl Serialize for Move {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let steps: Vec<_> = self.steps.iter().map(step_notation).collect();
+        serializer.serialize_str(&steps.join(" "))
+    }
+}
+
+impl<'de> Deserialize<'de> for Move {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Move, D::Error> {
+        let line = String::deserialize(deserializer)?;
+        let steps = line
+            .split_whitespace()
+            .map(parse_step)
+            .collect::<Result<_, _>>()
+            .map_err(de::Error::custom)?;
+        Ok(Move::new(steps))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct PositionRepr {
+    side: Side,
+    steps_left: u8,
+    plies: u16,
+    board: String,
+    #[serde(default)]
+    last_step: Option<Step>,
+    /// Whether the last step pushed a piece which must now be followed into
+    /// its square
+    #[serde(default)]
+    push: bool,
+}
+
+impl Serialize for Position {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        PositionRepr {
+            side: self.side,
+            steps_left: self.steps_left,
+            plies: self.plies,
+            board: self.to_small_notation(),
+            last_step: self.last_step,
+            push: matches!(self.last_step, Some(Step::Push(_, _, _))),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Position {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Position, D::Error> {
+        let repr = PositionRepr::deserialize(deserializer)?;
+        if !(1..=4).contains(&repr.steps_left) {
+            return Err(de::Error::custom(format!(
+                "Invalid steps left {}",
+                repr.steps_left
+            )));
+        }
+        let board =
+            Position::from_small_notation(repr.board, repr.side).map_err(de::Error::custom)?;
+        let last_step = match (repr.last_step, repr.push) {
+            (None, false) => None,
+            (Some(Step::Move(p, source, dest)), push) if repr.steps_left < 4 => {
+                // The piece may have been captured on a trap by its last step
+                let captured = TRAP_INDICES.contains(&(dest as usize))
+                    && board.pieces[dest as usize] == Piece::Empty;
+                if board.pieces[dest as usize] != p && !captured {
+                    return Err(de::Error::custom(format!(
+                        "Last step {} doesn't match the board",
+                        Step::Move(p, source, dest)
+                    )));
+                }
+                Some(if push {
+                    Step::Push(p, source, dest)
+                } else {
+                    Step::Move(p, source, dest)
+                })
+            }
+            (step, _) => {
+                let step = step.map_or("push".to_string(), |s| step_notation(&s));
+                return Err(de::Error::custom(format!("Invalid last step {}", step)));
+            }
+        };
+        let mut pos = Position::from_pieces(repr.side, repr.steps_left, board.pieces);
+        pos.plies = repr.plies;
+        pos.last_step = last_step;
+        pos.current_hash = compute_hash(&pos.pieces, pos.side, pos.steps_left, last_step);
+        Ok(pos)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct GameRepr {
+    #[serde(default)]
+    tags: Vec<(String, String)>,
+    /// Every turn, starting with the setups of White and Black
+    moves: Vec<Move>,
+    #[serde(default)]
+    winner: Option<Side>,
+}
+
+impl Serialize for Game {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        GameRepr {
+            tags: self.tags.clone(),
+            moves: self.moves.clone(),
+            winner: match self.result {
+                EndState::WhiteWin => Some(Side::White),
+                EndState::BlackWin => Some(Side::Black),
+                EndState::Neither => None,
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Replays the turns, so that a game only deserializes if every turn is legal
+impl<'de> Deserialize<'de> for Game {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Game, D::Error> {
+        let repr = GameRepr::deserialize(deserializer)?;
+        let mut moves = repr.moves.into_iter();
+        let (white_setup, black_setup) = match (moves.next(), moves.next()) {
+            (Some(white), Some(black)) => (white, black),
+            _ => return Err(de::Error::custom("Missing setups")),
+        };
+        let mut game = Game::new(white_setup, black_setup).map_err(de::Error::custom)?;
+        for mov in moves {
+            game.play(&mov).map_err(de::Error::custom)?;
+        }
+        game.tags = repr.tags;
+        match (&game.result, repr.winner) {
+            (EndState::Neither, Some(side)) => game.result = EndState::from(side),
+            (result, Some(side)) if *result != EndState::from(side) => {
+                return Err(de::Error::custom("The winner doesn't match the board"));
+            }
+            _ => {}
+        }
+        Ok(game)
+    }
+}
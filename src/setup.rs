@@ -0,0 +1,209 @@
+use crate::game::Move;
+use crate::position::{Piece, Side, Step};
+use num_traits::FromPrimitive;
+use rand::rngs::SmallRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+
+/// Piece kinds placed in a setup, from rabbit (0) to elephant (5)
+const SETUP_KINDS: [u8; 16] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 5];
+const CAMEL: u8 = 4;
+const ELEPHANT: u8 = 5;
+
+/// Chooses the engine's setup by hill climbing over swaps of two pieces from
+/// several random arrangements, keeping the best scoring one. Noise added to
+/// every piece-square value varies the choice between games.
+#[derive(Clone, Copy, Debug)]
+pub struct SetupChooser {
+    /// The scale of the noise, where 0 always gives the same setup for the
+    /// same opponent setup
+    pub randomness: f64,
+    /// Random starting arrangements improved by hill climbing
+    pub restarts: usize,
+    pub seed: u64,
+}
+
+impl Default for SetupChooser {
+    fn default() -> Self {
+        SetupChooser {
+            randomness: 0.0,
+            restarts: 8,
+            seed: 0,
+        }
+    }
+}
+
+/// An arrangement of setup pieces, as the kind on each home square seen from
+/// the side setting up: back rank files a to h, then the rank in front
+type Arrangement = [u8; 16];
+
+/// The files of the opponent's elephant and camel, if they are known
+#[derive(Clone, Copy)]
+struct Opponent {
+    elephant: Option<u8>,
+    camel: Option<u8>,
+}
+
+impl SetupChooser {
+    /// A setup for `side`, given as `Place` steps. Black should pass White's
+    /// setup as `opponent` so the strong pieces can answer it.
+    pub fn choose(&self, side: Side, opponent: Option<&Move>) -> Move {
+        let opponent = read_opponent(opponent);
+        let mut rng = SmallRng::seed_from_u64(self.seed);
+        let mut noise = [[0.0; 6]; 16];
+        if self.randomness > 0.0 {
+            for values in noise.iter_mut() {
+                for value in values.iter_mut() {
+                    *value = self.randomness * rng.gen_range(-1.0, 1.0);
+                }
+            }
+        }
+        let score = |arrangement: &Arrangement| {
+            let noise: f64 = arrangement
+                .iter()
+                .enumerate()
+                .map(|(i, kind)| noise[i][*kind as usize])
+                .sum();
+            arrangement_score(arrangement, opponent) + noise
+        };
+        let mut best = SETUP_KINDS;
+        let mut best_score = f64::NEG_INFINITY;
+        for _ in 0..self.restarts.max(1) {
+            let mut arrangement = SETUP_KINDS;
+            arrangement.shuffle(&mut rng);
+            let mut current = score(&arrangement);
+            let mut improved = true;
+            while improved {
+                improved = false;
+                for a in 0..16 {
+                    for b in a + 1..16 {
+                        if arrangement[a] == arrangement[b] {
+                            continue;
+                        }
+                        arrangement.swap(a, b);
+                        let swapped = score(&arrangement);
+                        if swapped > current + 1e-9 {
+                            current = swapped;
+                            improved = true;
+                        } else {
+                            arrangement.swap(a, b);
+                        }
+                    }
+                }
+            }
+            if current > best_score {
+                best = arrangement;
+                best_score = current;
+            }
+        }
+        to_move(&best, side)
+    }
+}
+
+/// The heuristic value of a setup for `side`, higher being better. Rabbits
+/// belong on the back rank away from the trap squares, strong pieces in front
+/// with the elephant central, and the camel away from the opponent's
+/// elephant while horses face the opponent's camel. Returns `None` unless
+/// `setup` places exactly the setup pieces of `side` on its home ranks.
+pub fn setup_score(side: Side, setup: &Move, opponent: Option<&Move>) -> Option<f64> {
+    let mut arrangement = [u8::MAX; 16];
+    for step in setup.steps.iter() {
+        let (piece, sq) = match *step {
+            Step::Place(piece, sq) => (piece as u8, sq),
+            _ => return None,
+        };
+        let (kind, rank, file) = match side {
+            Side::White if (1..=6).contains(&piece) => (piece - 1, sq / 8, sq % 8),
+            Side::Black if (7..=12).contains(&piece) => (piece - 7, 7 - sq / 8, sq % 8),
+            _ => return None,
+        };
+        let index = (rank * 8 + file) as usize;
+        if rank > 1 || arrangement[index] != u8::MAX {
+            return None;
+        }
+        arrangement[index] = kind;
+    }
+    let mut sorted = arrangement;
+    sorted.sort_unstable();
+    if sorted != SETUP_KINDS {
+        return None;
+    }
+    Some(arrangement_score(&arrangement, read_opponent(opponent)))
+}
+
+fn arrangement_score(arrangement: &Arrangement, opponent: Opponent) -> f64 {
+    let mut score = 0.0;
+    let mut files = [0u8; 6];
+    for (i, kind) in arrangement.iter().enumerate() {
+        let (front, file) = (i >= 8, (i % 8) as u8);
+        let centrality = f64::from(file.min(7 - file));
+        let guards_trap = front && (file == 2 || file == 5);
+        score += match (*kind, front) {
+            (0, false) => 1.0,
+            (0, true) if guards_trap => -1.5,
+            (0, true) if file == 0 || file == 7 => 0.0,
+            (0, true) => -0.5,
+            (1, true) if guards_trap => 1.0,
+            (1, _) => 0.4,
+            (2, true) => 0.8 + if guards_trap { 0.3 } else { 0.0 },
+            (3, true) => 1.0 + if file == 1 || file == 6 { 0.3 } else { 0.0 },
+            (CAMEL, true) => 1.2 + 0.1 * centrality,
+            (ELEPHANT, true) => 1.5 + 0.3 * centrality,
+            (_, _) => 0.0,
+        };
+        files[*kind as usize] = file;
+    }
+    let wing = |file: u8| file < 4;
+    if let Some(elephant) = opponent.elephant {
+        if wing(files[CAMEL as usize]) != wing(elephant) {
+            score += 0.8;
+        }
+    }
+    if let Some(camel) = opponent.camel {
+        let horses = arrangement
+            .iter()
+            .enumerate()
+            .filter(|(i, kind)| **kind == 3 && *i >= 8 && wing((i % 8) as u8) == wing(camel))
+            .count();
+        score += 0.3 * horses as f64;
+    }
+    // The camel is safer apart from the elephant, which cannot protect it from
+    // an enemy elephant while tied to the other wing
+    if (i16::from(files[CAMEL as usize]) - i16::from(files[ELEPHANT as usize])).abs() > 1 {
+        score += 0.2;
+    }
+    score
+}
+
+fn read_opponent(setup: Option<&Move>) -> Opponent {
+    let mut opponent = Opponent {
+        elephant: None,
+        camel: None,
+    };
+    for step in setup.into_iter().flat_map(|m| m.steps.iter()) {
+        if let Step::Place(piece, sq) = *step {
+            match piece {
+                Piece::WElephant | Piece::BElephant => opponent.elephant = Some(sq % 8),
+                Piece::WCamel | Piece::BCamel => opponent.camel = Some(sq % 8),
+                _ => {}
+            }
+        }
+    }
+    opponent
+}
+
+fn to_move(arrangement: &Arrangement, side: Side) -> Move {
+    let steps = arrangement
+        .iter()
+        .enumerate()
+        .map(|(i, kind)| {
+            let (rank, file) = ((i / 8) as u8, (i % 8) as u8);
+            let (pix, sq) = match side {
+                Side::White => (kind + 1, rank * 8 + file),
+                Side::Black => (kind + 7, (7 - rank) * 8 + file),
+            };
+            Step::Place(Piece::from_u8(pix).unwrap(), sq)
+        })
+        .collect();
+    Move::new(steps)
+}
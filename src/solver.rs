@@ -0,0 +1,198 @@
+use crate::game::Move;
+use crate::position::{Position, Step};
+use failure::{ensure, format_err, Error};
+use std::collections::HashMap;
+
+/// The outcome of a goal race for the side to move, with the line played
+/// when both sides choose the fastest win and the longest defence
+#[derive(Clone, Debug)]
+pub enum RaceResult {
+    Win(Vec<Move>),
+    Loss(Vec<Move>),
+    /// Neither side can force a win within the turns searched
+    Unknown,
+}
+
+/// What is known about a turn-start position, in turns of the side to move
+#[derive(Clone, Copy, Default)]
+struct Proof {
+    /// A win is forced within this many turns
+    win: Option<u32>,
+    /// No win can be forced in fewer turns than this
+    no_win: u32,
+    /// Every defence loses within this many turns of the opponent
+    loss: Option<u32>,
+    /// Some defence survives fewer turns of the opponent than this
+    no_loss: u32,
+}
+
+/// An exact solver for endgames with few pieces, proving whether the side to
+/// move can force a win within a number of its own turns. Every distinct
+/// turn is tried, so the cost grows quickly with the pieces on the board and
+/// the turns searched. Proofs are cached by position between calls.
+///
+/// Repetitions are not treated as losses, though a turn must still change
+/// the position.
+pub struct GoalSolver {
+    /// Positions with more pieces are refused
+    pub max_pieces: u32,
+    /// The number of positions whose turns were generated
+    pub nodes: u64,
+    proofs: HashMap<u64, Proof>,
+}
+
+impl Default for GoalSolver {
+    fn default() -> Self {
+        GoalSolver {
+            max_pieces: 8,
+            nodes: 0,
+            proofs: HashMap::new(),
+        }
+    }
+}
+
+/// The turns from a position, with the history of each following position
+/// cleared so repetitions are ignored, and without the closing pass
+fn turns(pos: &Position) -> Vec<(Move, Position)> {
+    Move::all_turns(pos)
+        .into_iter()
+        .map(|(mut mov, mut next)| {
+            mov.steps.retain(|s| *s != Step::Pass);
+            next.my_last = 0;
+            next.opp_last = 0;
+            (mov, next)
+        })
+        .collect()
+}
+
+/// Whether a turn from `pos` reaching `next` won the game
+fn won(pos: &Position, next: &Position) -> bool {
+    next.side == pos.side
+}
+
+impl GoalSolver {
+    pub fn new() -> GoalSolver {
+        GoalSolver::default()
+    }
+    /// Solves the race for the side to move at the start of a turn, searching
+    /// up to `max_turns` turns of each side. The shortest win or loss is found.
+    pub fn solve(&mut self, pos: &Position, max_turns: u32) -> Result<RaceResult, Error> {
+        ensure!(
+            pos.steps_left == 4 && pos.last_step.is_none() && pos.plies >= 16,
+            format_err!("The solver needs a position at the start of a turn")
+        );
+        let pieces = (pos.placement[0] | pos.placement[1]).count_ones();
+        ensure!(
+            pieces <= self.max_pieces,
+            format_err!(
+                "{} pieces is more than the solver's limit of {}",
+                pieces,
+                self.max_pieces
+            )
+        );
+        let mut pos = pos.clone();
+        pos.my_last = 0;
+        pos.opp_last = 0;
+        for n in 0..=max_turns {
+            if self.loses(&pos, n) {
+                return Ok(RaceResult::Loss(self.loss_line(&pos, n)));
+            }
+            if n > 0 && self.wins(&pos, n) {
+                return Ok(RaceResult::Win(self.win_line(&pos, n)));
+            }
+        }
+        Ok(RaceResult::Unknown)
+    }
+    /// Whether the side to move can force a win within `n` of its turns
+    pub fn wins(&mut self, pos: &Position, n: u32) -> bool {
+        if n == 0 {
+            return false;
+        }
+        let proof = self
+            .proofs
+            .get(&pos.current_hash)
+            .cloned()
+            .unwrap_or_default();
+        if proof.win.is_some_and(|w| w <= n) {
+            return true;
+        }
+        if n < proof.no_win {
+            return false;
+        }
+        self.nodes += 1;
+        let turns = turns(pos);
+        let result = turns.iter().any(|(_, next)| won(pos, next))
+            || (n > 1 && turns.iter().any(|(_, next)| self.loses(next, n - 1)));
+        let proof = self.proofs.entry(pos.current_hash).or_default();
+        if result {
+            proof.win = Some(proof.win.map_or(n, |w| w.min(n)));
+        } else {
+            proof.no_win = proof.no_win.max(n + 1);
+        }
+        result
+    }
+    /// Whether the side to move loses within `n` turns of the opponent
+    /// whatever it plays, including having no turn which doesn't lose at once
+    pub fn loses(&mut self, pos: &Position, n: u32) -> bool {
+        let proof = self
+            .proofs
+            .get(&pos.current_hash)
+            .cloned()
+            .unwrap_or_default();
+        if proof.loss.is_some_and(|l| l <= n) {
+            return true;
+        }
+        if n < proof.no_loss {
+            return false;
+        }
+        self.nodes += 1;
+        let turns = turns(pos);
+        let result = !turns.iter().any(|(_, next)| won(pos, next))
+            && turns.iter().all(|(_, next)| self.wins(next, n));
+        let proof = self.proofs.entry(pos.current_hash).or_default();
+        if result {
+            proof.loss = Some(proof.loss.map_or(n, |l| l.min(n)));
+        } else {
+            proof.no_loss = proof.no_loss.max(n + 1);
+        }
+        result
+    }
+    /// The fastest win from a position won within `n` turns
+    fn win_line(&mut self, pos: &Position, n: u32) -> Vec<Move> {
+        let turns = turns(pos);
+        let winning = turns
+            .iter()
+            .filter(|(_, next)| won(pos, next))
+            .min_by_key(|(mov, _)| mov.steps.len());
+        if let Some((mov, _)) = winning {
+            return vec![mov.clone()];
+        }
+        for (mov, next) in turns.into_iter() {
+            if let Some(m) = (0..n).find(|m| self.loses(&next, *m)) {
+                let mut line = vec![mov];
+                line.extend(self.loss_line(&next, m));
+                return line;
+            }
+        }
+        unreachable!("no winning turn in a won position")
+    }
+    /// The longest defence from a position lost within `n` turns of the
+    /// opponent, followed by the opponent's fastest win
+    fn loss_line(&mut self, pos: &Position, n: u32) -> Vec<Move> {
+        let mut longest: Option<(u32, Move, Position)> = None;
+        for (mov, next) in turns(pos).into_iter() {
+            let depth = (1..=n).find(|d| self.wins(&next, *d)).unwrap();
+            if longest.as_ref().is_none_or(|(d, _, _)| depth > *d) {
+                longest = Some((depth, mov, next));
+            }
+        }
+        match longest {
+            Some((depth, mov, next)) => {
+                let mut line = vec![mov];
+                line.extend(self.win_line(&next, depth));
+                line
+            }
+            None => Vec::new(),
+        }
+    }
+}
@@ -1,787 +0,0 @@
-/// Autogenerated by zobrist.rs
-const SIDE_TO_MOVE: [u64; 2] = [17346312829043179214, 602977864700505253];
-
-const STEPS_LEFT: [u64; 4] = [18165384757819119889, 11035163740567115878, 4266740036020175768, 11317086342552193210];
-
-const ZOBRIST_PIECES: [[u64; NUM_SQUARES]; NUM_PIECES] = [[
-    1974789628899284657,
-    11329338746475547013,
-    11054236016936932795,
-    12735339793519689405,
-    14943919083637630961,
-    15686459378850635235,
-    18359898745285968490,
-    8422797956801519424,
-    16822467890597768971,
-    11918257349966569773,
-    11811286828939999044,
-    13623518296385709681,
-    1698443242101782190,
-    2020176129785663932,
-    5269204776576162590,
-    11475042194247481016,
-    18078239050050169627,
-    3480414213895905906,
-    1438049086283413116,
-    8198645475961880483,
-    14079380875221315396,
-    13145206202952868708,
-    17578226116350318744,
-    7207343611867082644,
-    2027335148467627638,
-    10636331467903890599,
-    80844781820378004,
-    11425463442987947577,
-    9780804259899459380,
-    10829774689201643143,
-    11997561923577564783,
-    5567238211282342098,
-    8725925976322591999,
-    14524065577671711308,
-    16924138359389097773,
-    2274122971485657721,
-    12002161787323681569,
-    13106834987469822327,
-    5336393644784930159,
-    16755462539567368859,
-    9320684245858598884,
-    2871993726224638877,
-    6904625632730302625,
-    2151511547139457601,
-    3255499757302201900,
-    12991362381879877045,
-    14269511814311912747,
-    13611791667337167947,
-    16545589718228691571,
-    18223291951684801848,
-    1714098593982906618,
-    13637423827207507536,
-    10231887923717593999,
-    643915457980729532,
-    11944565703358525206,
-    10891709102396330262,
-    11312658548983638853,
-    8322522414230359806,
-    2325083878293589807,
-    15256435305831777928,
-    7900876106036968722,
-    6139810468952637635,
-    3772812568108564366,
-    9630832888702671174,
-   ], [
-    12274774098414584585,
-    5873552040627003413,
-    1552196522381025755,
-    17357063634107115670,
-    10439407342684443908,
-    16017406594806257841,
-    6149145724330786640,
-    5361103422207647334,
-    4903316663230523375,
-    3158568594325306651,
-    3736748232597157929,
-    10748832428708383653,
-    828585827595300907,
-    2731652748787105440,
-    17900064379885596582,
-    11932574568657504271,
-    3838550144682842886,
-    749608906302914415,
-    4038906976922745612,
-    1588703984890173982,
-    17325538514317528280,
-    1432556219147213916,
-    10548510914497011542,
-    13177241665632487363,
-    15708418079725009315,
-    6445380559047357655,
-    1621932626023109332,
-    12636240169005993421,
-    2746504787446945071,
-    12267470133947269513,
-    375087583246594188,
-    15488634403532546932,
-    5237557391469024694,
-    3615380062072560820,
-    6911042651102434479,
-    10339753562462021326,
-    10560035480345507850,
-    15196154112056727710,
-    18155553291350424226,
-    6243771541205246734,
-    18360442317774286683,
-    8930080283800355826,
-    10959270363560091559,
-    16721042610235085048,
-    18301173074508973377,
-    3823468323780208410,
-    9106003509860417417,
-    4548490789765164178,
-    6194102568533150063,
-    2844328794475241391,
-    13119946006738251078,
-    5844697337008813433,
-    83529711157529958,
-    10371942691440204899,
-    11582669483095622097,
-    11147686396889290641,
-    6708843248085267867,
-    5112314275387416231,
-    18050817728384465708,
-    9459656022080149339,
-    16396067643292685056,
-    18268280893019743695,
-    15476356422409343980,
-    2922211938248385313,
-   ], [
-    16527357328459966946,
-    10474071453384622309,
-    15384596540803994634,
-    4812500448202142796,
-    11614281920515290574,
-    15141984551846859744,
-    12986616333105102220,
-    14255214934322300305,
-    5276205862711212663,
-    6923165868780661005,
-    2517721180079858081,
-    18390743147917550159,
-    7903926677464650345,
-    8213674593159827225,
-    9860892365172639732,
-    10587246328654389866,
-    8454137216248683190,
-    13342906774579115553,
-    6205946525351809571,
-    2084064674694146387,
-    11885882743010841004,
-    4415232104544328072,
-    11880565579910424847,
-    12949618752138706584,
-    16223833395325786430,
-    6859154468000648076,
-    11383886863960174946,
-    17616173779911361248,
-    16540711534712508975,
-    17619367146074261346,
-    13144480163914380397,
-    17014606477569904231,
-    1789304892772398154,
-    16504017970355743319,
-    6322744045449293443,
-    11500371152962781235,
-    15631110233221420679,
-    1826822773145754997,
-    10452745710815474173,
-    17006914761567976128,
-    8547257166973522565,
-    10767893446584122987,
-    15290401959127891586,
-    6459827384033926225,
-    14987544848170193933,
-    5987801701825646453,
-    922347530918330635,
-    17984711640983163959,
-    6137989656484485336,
-    6420362494244248224,
-    10507737554348571465,
-    2454980011179195037,
-    5991142871582055003,
-    11054661512690426245,
-    1216045583956827988,
-    8751344227230377289,
-    13217118266839217215,
-    1269787091304059152,
-    9248439087992930792,
-    2073988101819921285,
-    2601253881893803523,
-    9961614817401173234,
-    5109488877065926770,
-    5860710855862183007,
-   ], [
-    11585914568899548464,
-    10019147523480495575,
-    10205950806488962939,
-    12968196835634069602,
-    14945592747879439058,
-    17210160980632694254,
-    11201417505479318325,
-    13419895128724411771,
-    8618172468029259041,
-    2877545366193531571,
-    3541091731584927845,
-    17303396250276990008,
-    5615974631096049403,
-    12822884894757586522,
-    631473093004945263,
-    1957889441907313162,
-    10912397779039451285,
-    16799732394310817988,
-    7090007933180588043,
-    10115815864482349765,
-    15617116491850740259,
-    14448123501180426430,
-    16810870940480177742,
-    251029800078335867,
-    8860152255882031056,
-    15081064870076828884,
-    6009969649644043220,
-    636913511833073613,
-    5851654866383805354,
-    1782590984182270268,
-    16095432593805048819,
-    6934763096280278349,
-    14824670936013122395,
-    2051686386626466874,
-    5422253414464349567,
-    18427906979842374439,
-    15306633132667406155,
-    17941932101470938716,
-    643373051806321457,
-    7573917752920385727,
-    7264372229530321423,
-    15661836893009210439,
-    7280782974918789955,
-    1588802191177374837,
-    4931637763215341930,
-    11489997277285980794,
-    2003607253562590339,
-    10612743096904728086,
-    7025365272607042033,
-    17086082744800964104,
-    12341250220705173415,
-    10155342199589535399,
-    9274884950480403063,
-    466487080283003012,
-    6905853477241022268,
-    14881005265518388699,
-    4849409468971199540,
-    17070988354006403116,
-    8148090865682082914,
-    5764098315546435683,
-    1846815247593871798,
-    16322825005526882339,
-    13474885527035209932,
-    6248735488289604477,
-   ], [
-    9723230613857297599,
-    3152147763294072829,
-    3290619491774319695,
-    17239539711470396770,
-    8507076103968107005,
-    14021903131363131737,
-    694510856545386967,
-    9902407446814837106,
-    13358141961954875071,
-    2440849301947266059,
-    15775004377261431952,
-    16125505470171045435,
-    17418393596402282001,
-    12206063673428809178,
-    8515502061302515631,
-    15800040713317949776,
-    460833226576856166,
-    13209352634083801499,
-    1442528644148407787,
-    4931068910860557513,
-    5781425492266662914,
-    8802147739900204270,
-    14932091975937186531,
-    11337023910436899126,
-    17547345694717104645,
-    17316758286738923262,
-    14089876969498804268,
-    2799143815775979846,
-    4250328802916467728,
-    15782819076026069157,
-    6214089957998379160,
-    8453979184699618343,
-    2442216339405963644,
-    12468349243509191124,
-    4868260742612556545,
-    4962490424774170646,
-    18184848751429569406,
-    16219609614703244959,
-    17153445291871660289,
-    16989840651093428560,
-    7498076876495708820,
-    3543510437579642695,
-    4951220831671471368,
-    3257381518194541016,
-    16049041014424752916,
-    12099271778525176029,
-    14967264375260802501,
-    15074064070740428845,
-    15947221104838929885,
-    1266667099093321611,
-    3798946437853285467,
-    2784782791796611316,
-    10460939668500662894,
-    234707828050461671,
-    4083140004625154477,
-    14678073063412922002,
-    17543602764912466571,
-    8395741450150831173,
-    15440748720316109256,
-    7159536068805339964,
-    2292652682926261224,
-    9009135998779929942,
-    15086523451637837937,
-    10451917238949623730,
-   ], [
-    3250517688334090985,
-    3982374654619041005,
-    7618584046282060590,
-    5913505002091613899,
-    16839504155285915534,
-    7051067564959476583,
-    11687088013405070553,
-    16874436261381056268,
-    1447891254794930881,
-    10676521984352755439,
-    3277615765138915661,
-    4914871200700278128,
-    17184412321470449645,
-    17099893219305351002,
-    2966693556976010400,
-    18016914000179263310,
-    4590678252635123075,
-    12542800733329080939,
-    57097272687540551,
-    615640341209783257,
-    5163365295316645204,
-    15362730138364465619,
-    12233034778051823670,
-    18215932739870664887,
-    8445197200662122406,
-    18313569719002554627,
-    13955766945300158468,
-    5896741094754840802,
-    4260501168143967519,
-    4542775616159256029,
-    4583585405348702709,
-    17978304290799659930,
-    10241568152472797638,
-    12723928959064959268,
-    15448282846745103203,
-    5292174452900696078,
-    316196082200780319,
-    530836873571418560,
-    45839711137086112,
-    18106810644249449756,
-    2658618227993534037,
-    12272175502732970295,
-    13006746017717497606,
-    4221874231039344693,
-    15778486387701755840,
-    15479699004112373934,
-    5306017531831119209,
-    621905319115278551,
-    5134022120634740324,
-    12241306134871891792,
-    12344108810620489782,
-    12009968914608327153,
-    5602590571656449403,
-    10584496769754277182,
-    18259925333098238369,
-    7073582263326923241,
-    609961218235785135,
-    5012499086845372167,
-    8454197262169547037,
-    10158008202882344953,
-    9159289983803938862,
-    791325539511946897,
-    1199535162786149426,
-    7733675714218085232,
-   ], [
-    1762206908886069474,
-    10840923473032479761,
-    12876228962798194794,
-    12713595384825324475,
-    7256442928762775142,
-    5732973861735714449,
-    5486945384212300296,
-    5638665042804964217,
-    3306293476866876012,
-    13958109728323669675,
-    5784954506710533139,
-    15243538917610941754,
-    4260743828062760160,
-    2361037787126643340,
-    11088407367034748821,
-    9677963524978766031,
-    3128176887959314715,
-    16068611497861704919,
-    10035627258714297731,
-    9733769174620248185,
-    6687901732280895246,
-    10605103636894622421,
-    16827131577197970711,
-    17022511795689891785,
-    15674417025855391996,
-    15764590600793594565,
-    11870573495813536824,
-    5408114670855886087,
-    17414864488297409647,
-    5457556094380684806,
-    849839477112067794,
-    10440251234499240338,
-    9703797630473956309,
-    12423361896607057546,
-    17212763267299992745,
-    8371281269217716645,
-    16993018183487687709,
-    1080010913997079612,
-    327307867536881098,
-    9835876666701461030,
-    1310268371653293060,
-    5230267207768644515,
-    15340648097180248846,
-    5237412144335051922,
-    15540718862699228893,
-    7556182071924630557,
-    12304560111060854172,
-    14081370055292871476,
-    16670394233507089739,
-    11312279495864074713,
-    4421247331171360362,
-    2901091990578511644,
-    13346022420289040037,
-    7483773405330400137,
-    3230782138273751448,
-    2914922744325372403,
-    14385567466916037026,
-    6113821111147595216,
-    4312327063444457776,
-    6540888187759641210,
-    13739370716430957596,
-    10742512271024637995,
-    8540811502025818661,
-    4799751540667115467,
-   ], [
-    2927924307775427497,
-    4267560862013395473,
-    13074536546579504001,
-    16395288236984559531,
-    5225316322916434953,
-    553580627925606246,
-    5970953962518938013,
-    18218489473638281977,
-    15536537789445467922,
-    5767069345065923506,
-    3792331624870221185,
-    16034460720787892918,
-    16014983954183564587,
-    10030522730096682986,
-    7397066443193101687,
-    8606917504251109474,
-    18419730949305610978,
-    10779336455170668653,
-    14904424733082233642,
-    5788572138340681165,
-    15615208756258070448,
-    4345209347476146400,
-    1239975872777121117,
-    10187450890507056270,
-    11331949573470366820,
-    11555641967471048402,
-    9284254156880863261,
-    1809781960208280614,
-    15512887071828242597,
-    1821784504932444388,
-    10597799124388755921,
-    10149694233534998680,
-    17218865331255620279,
-    5168276064997509627,
-    17575268283435224699,
-    11259495307492444747,
-    11089309016518950962,
-    10706031761989443662,
-    6174624729949853921,
-    2049691994155460871,
-    6707678008676249019,
-    18074600292715976171,
-    11947869186544761545,
-    8294476140356722068,
-    15697007877936501083,
-    6547928452778736048,
-    2241054604589094792,
-    16855096652905910315,
-    11098213190423135785,
-    7565299905485060387,
-    4605531548263302045,
-    14385741997111657715,
-    11245784250845161043,
-    4688772518901801036,
-    2119940560454578463,
-    12100973339573856045,
-    8396322545160040231,
-    16744634188014410393,
-    14921739572857713274,
-    6401136272994544127,
-    4042389256707710885,
-    10897152825449669175,
-    2513762193267096432,
-    6464796116212252414,
-   ], [
-    15499897810796063561,
-    16717054837224619832,
-    16180334807265829830,
-    5938221111894682915,
-    9828974888234575702,
-    9875700000713906642,
-    11778992136550359555,
-    4188742923421550707,
-    2345993933615620004,
-    10185778355017668489,
-    14395265196529146943,
-    9057503496495777398,
-    373023444872086208,
-    12029229949672904523,
-    15662695296325093604,
-    1919143015601663784,
-    9322218835628142243,
-    3673320974223058317,
-    17821523644974609966,
-    8243100102370195364,
-    10563279912378982857,
-    10196234344891775074,
-    5720674825565126666,
-    2781969672695276013,
-    4133010161849824894,
-    17974361322846872191,
-    2143322236797624256,
-    4460980390895910857,
-    14447683819983698950,
-    7020792487810862755,
-    3047004048264222119,
-    12585744357042222204,
-    3303982368146610896,
-    4111063541367388717,
-    10577153795352856455,
-    9284414732328643905,
-    2944157449564147660,
-    12783760859490929782,
-    11149765599498748239,
-    13450237626131377576,
-    10812904588545812365,
-    8388862983444007068,
-    10396880334374296943,
-    15449174982276357031,
-    14033347381282197422,
-    14485924434295040572,
-    8548241833617506280,
-    10045069169644590106,
-    14195055375890182614,
-    6517962258347014604,
-    11166869361840906681,
-    16288782191197864374,
-    5365739021447289130,
-    10471057320285424448,
-    10119841472629054009,
-    13697277916770304420,
-    13066467035279430669,
-    3007670027950724321,
-    10376375882705469384,
-    5906253326512819073,
-    5434158808866346810,
-    970186197428979249,
-    16653164551874619355,
-    3232441252318458849,
-   ], [
-    4546582064303524004,
-    3685018104394309591,
-    236605548464462975,
-    15666520005163915702,
-    16254119120654664032,
-    1770760748401484226,
-    9466320383473800898,
-    4707326652582315967,
-    3650347066727807474,
-    5144732492734640517,
-    11953951495232900371,
-    6225958187821278838,
-    8706216427829954685,
-    15795354868737178767,
-    13795292604117131075,
-    1914964885807990171,
-    1337708388860405861,
-    16292745974013537945,
-    593161372925470281,
-    9486880944795684830,
-    9356456441430932913,
-    2277833653483569588,
-    6976972301020901855,
-    5574600418436638537,
-    8335037360792877248,
-    7139572795514741308,
-    13266803510498381403,
-    5060590025102180382,
-    11295226946366399851,
-    12909981143540810581,
-    5372945606371352745,
-    6095803794047419178,
-    6012671847114246610,
-    13126896721787901955,
-    13618143313221615578,
-    9818163730925232396,
-    8547182158953663774,
-    12964512126324886633,
-    5877684304764326667,
-    14081705384547452650,
-    16119396780296709424,
-    15202575044685102144,
-    8957787023767302385,
-    5081142530459562604,
-    8866157959262850556,
-    2493097418077683258,
-    6146201085583491949,
-    8457245735374313106,
-    1336335209529394638,
-    12488245779949984107,
-    7571675606926161312,
-    14310388597656779049,
-    15574145829405375810,
-    2755129507779763932,
-    11451600740566608026,
-    1210206878101218862,
-    17800820641513400243,
-    4112437204759285201,
-    9794963342884533376,
-    10387130370616603825,
-    8410677547810839180,
-    1542812183060815078,
-    994717682600514138,
-    5063683851480279712,
-   ], [
-    5851883925848091232,
-    1049765532219758151,
-    7090942311252029667,
-    13099698533755760928,
-    16350546472194213643,
-    15708891433744903634,
-    17510627362869417456,
-    9293137134694542692,
-    13130807270386378161,
-    5922731041779999737,
-    4778755334688335048,
-    1982934357368546693,
-    13264830658752581732,
-    9704472262838724443,
-    11937195906486513949,
-    8164388051165207805,
-    9985132900927000802,
-    13109300093220217280,
-    3331607183557923331,
-    6156947444025100089,
-    1904064829440536287,
-    7026603277869645874,
-    10472739117925795626,
-    9308898529315288741,
-    13497675073914532411,
-    6390399080039022018,
-    4907657967882638733,
-    2428614542733477931,
-    1798259945392228876,
-    15499516200619436425,
-    2768792034627761537,
-    4991649594839016012,
-    11927609862223168310,
-    6929908500495844505,
-    6720630060339631071,
-    11570816479693889603,
-    14234975541374566530,
-    127911585333656709,
-    5881169939620723898,
-    6170667064637586997,
-    17462982157673679042,
-    12301288633076299639,
-    12789082532026835219,
-    3209406748132611129,
-    10516684130398070978,
-    3906138716002652575,
-    7813142458959171297,
-    18293499664139722131,
-    4931987557858190802,
-    9459961187475978482,
-    4059473291405967202,
-    5538548403287862143,
-    2734828203968648069,
-    11459231909638595340,
-    10888112837517567637,
-    3760701972840375148,
-    1674581855968491163,
-    6386551364872549300,
-    8474966506469426415,
-    577482852896376410,
-    2583902066992295988,
-    3369471604161469262,
-    15357809477432495457,
-    12914169571075264024,
-   ], [
-    2217657531562251747,
-    5203659656866695594,
-    2439236839187932730,
-    4890618756838319051,
-    14956924386305983794,
-    2329729654658212822,
-    4871226600565424761,
-    16400496301237253460,
-    10822223864066989729,
-    15336028632158874645,
-    18149689906020737549,
-    5489226421146941650,
-    15791392206021562659,
-    3644195400281061234,
-    10973957792059531036,
-    13595979037422960293,
-    13649342659504532108,
-    15990351764146745110,
-    11245501259078645512,
-    4805201704228419369,
-    11886101624834180396,
-    9527521591573668443,
-    3979322719227841798,
-    6155517370837285127,
-    2193201335737171943,
-    14998195056869645429,
-    1998569916197753093,
-    15158498016225790434,
-    14970949834658840132,
-    6757504053660752643,
-    9063331756393929264,
-    9345616368833471475,
-    5703561225829005519,
-    9514131727546335494,
-    16187024329151582416,
-    6149094278051731279,
-    17515938782793854639,
-    95055340472612730,
-    12063657774932791859,
-    17809866593104123939,
-    3880718270316103469,
-    11055479804652868495,
-    3727352359945920559,
-    15226068548667219392,
-    2259420386219421500,
-    13242879063151296891,
-    17835160660606197781,
-    9795416214024981768,
-    1163208165248143068,
-    14629862224532687545,
-    15802340674874765379,
-    7095186946887447,
-    347795722132499187,
-    13705258536494484154,
-    17776141718713337178,
-    5439076793508617569,
-    14627423886225088882,
-    398524320470314112,
-    14223672540052306182,
-    4589363400095205724,
-    2886301909001059779,
-    4971535803186843584,
-    9663299169024815671,
-    7178606547095990591,
-]];
-
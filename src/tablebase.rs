@@ -0,0 +1,572 @@
+use crate::database::Material;
+use crate::game::Move;
+use crate::position::{
+    neighbors_of, Bitboard, EndState, Piece, PieceIter, Position, Side, Step, TRAP_INDICES,
+};
+use crate::zobrist::update_hash;
+use failure::{bail, ensure, format_err, Error};
+use num_traits::FromPrimitive;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"ARTB";
+const VERSION: u8 = 1;
+/// Tables grow by a factor of 64 with each piece. A three piece table takes
+/// up to a minute to generate, while four pieces would take hours.
+pub const MAX_TABLEBASE_PIECES: usize = 3;
+
+const INVALID: u8 = 0;
+const DRAW: u8 = 1;
+/// Marks a position with a turn which doesn't reach a win for the opponent
+const CANNOT_LOSE: u8 = u8::MAX;
+/// Longer results are stored as draws
+const MAX_DISTANCE: u32 = 125;
+
+/// The exact result of a position for the side to move, ignoring repetitions
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Outcome {
+    /// The side to move wins within this many of its turns
+    Win(u32),
+    /// The opponent wins within this many of its turns whatever is played,
+    /// where 0 means there is no turn which doesn't lose at once
+    Loss(u32),
+    /// Neither side can force a win
+    Draw,
+}
+
+fn encode(outcome: Outcome) -> u8 {
+    match outcome {
+        Outcome::Win(n) if n <= MAX_DISTANCE => 2 * n as u8,
+        Outcome::Loss(n) if n <= MAX_DISTANCE => 2 * n as u8 + 3,
+        _ => DRAW,
+    }
+}
+
+fn decode(value: u8) -> Option<Outcome> {
+    match value {
+        INVALID => None,
+        DRAW => Some(Outcome::Draw),
+        v if v % 2 == 0 => Some(Outcome::Win(u32::from(v / 2))),
+        v => Some(Outcome::Loss(u32::from((v - 3) / 2))),
+    }
+}
+
+/// The result of every position at the start of a turn with one material
+/// configuration.
+///
+/// A position's index is `side + 2 * (s0 + 64 * (s1 + 64 * ...))`, where
+/// `side` is 1 if Black is to move and `s0`, `s1`, ... are the squares of the
+/// pieces in order of piece index, identical pieces in order of square.
+/// Indices breaking that order or describing positions which can't start a
+/// turn, with pieces sharing a square, a rabbit on its goal or an
+/// unprotected piece on a trap, are invalid.
+pub struct Tablebase {
+    material: Material,
+    pieces: Vec<Piece>,
+    values: Vec<u8>,
+}
+
+impl Tablebase {
+    pub fn material(&self) -> Material {
+        self.material
+    }
+    /// The number of indices, including invalid ones
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+    /// The index of a position with this table's material, ignoring the steps
+    /// left
+    pub fn index(&self, pos: &Position) -> Option<usize> {
+        let mut placed: Vec<_> = (0..64u8)
+            .filter(|sq| pos.pieces[*sq as usize] != Piece::Empty)
+            .map(|sq| (pos.pieces[sq as usize] as u8, sq))
+            .collect();
+        placed.sort_unstable();
+        if placed.len() != self.pieces.len()
+            || placed
+                .iter()
+                .zip(self.pieces.iter())
+                .any(|((pix, _), piece)| *pix != *piece as u8)
+        {
+            return None;
+        }
+        let squares = placed
+            .iter()
+            .rev()
+            .fold(0, |index, (_, sq)| index * 64 + *sq as usize);
+        Some(2 * squares + pos.side as usize)
+    }
+    /// The position with the given index, if it is valid
+    pub fn position(&self, index: usize) -> Option<Position> {
+        if index >= self.values.len() {
+            return None;
+        }
+        let side = if index.is_multiple_of(2) {
+            Side::White
+        } else {
+            Side::Black
+        };
+        let mut squares = index / 2;
+        let mut pieces = [Piece::Empty; 64];
+        let mut previous: Option<(Piece, usize)> = None;
+        for piece in self.pieces.iter() {
+            let sq = squares % 64;
+            squares /= 64;
+            if pieces[sq] != Piece::Empty || previous.is_some_and(|(p, s)| p == *piece && s > sq) {
+                return None;
+            }
+            let on_goal = match piece {
+                Piece::WRabbit => sq >= 56,
+                Piece::BRabbit => sq < 8,
+                _ => false,
+            };
+            if on_goal {
+                return None;
+            }
+            pieces[sq] = *piece;
+            previous = Some((*piece, sq));
+        }
+        Some(Position::from_pieces(side, 4, pieces)).filter(traps_protected)
+    }
+    /// The result of the position with the given index, if it is valid
+    pub fn value(&self, index: usize) -> Option<Outcome> {
+        self.values.get(index).cloned().and_then(decode)
+    }
+}
+
+/// Keys positions by their Zobrist hash, which needs no further hashing
+#[derive(Default)]
+struct ZobristHasher(u64);
+
+impl Hasher for ZobristHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes.iter() {
+            self.0 = self.0.rotate_left(8) ^ u64::from(*byte);
+        }
+    }
+    fn write_u64(&mut self, n: u64) {
+        self.0 ^= n;
+    }
+}
+
+type ZobristSet = HashSet<u64, BuildHasherDefault<ZobristHasher>>;
+
+fn is_white(piece: Piece) -> bool {
+    (1..=6).contains(&(piece as u8))
+}
+
+/// Whether every piece on a trap has a friendly neighbor, so that none would
+/// be captured
+fn traps_protected(pos: &Position) -> bool {
+    TRAP_INDICES.iter().all(|trap| {
+        let piece = pos.pieces[*trap];
+        piece == Piece::Empty
+            || neighbors_of(1u64 << trap) & pos.placement[!is_white(piece) as usize] != 0
+    })
+}
+
+/// Whether a turn from `pos` wins at once, and otherwise the distinct
+/// positions reached by the turns which don't lose at once. Unlike
+/// `Move::all_turns`, partial turns reaching the same position are only
+/// followed once.
+fn turn_ends(pos: &Position) -> (bool, Vec<Position>) {
+    let side = pos.side;
+    let mut seen = ZobristSet::default();
+    let mut ends = HashMap::with_hasher(BuildHasherDefault::<ZobristHasher>::default());
+    let mut stack = vec![pos.clone()];
+    while let Some(current) = stack.pop() {
+        for step in current.gen_steps() {
+            let mut next = current.clone();
+            match next.do_step(step) {
+                EndState::Neither => {}
+                end if end == EndState::from(side) => return (true, vec![]),
+                _ => continue,
+            }
+            if next.side != side {
+                ends.insert(next.current_hash, next);
+            } else if seen.insert(next.current_hash) {
+                stack.push(next);
+            }
+        }
+    }
+    (false, ends.into_values().collect())
+}
+
+/// `pos` with the piece on `from` moved to `to`, leaving the state of the
+/// turn as it was
+fn moved(pos: &Position, from: u8, to: u8) -> Position {
+    let mut pos = pos.clone();
+    let piece = pos.pieces[from as usize];
+    let change = (1u64 << from) | (1u64 << to);
+    pos.current_hash = update_hash(pos.current_hash, Step::Move(piece, from, to));
+    pos.pieces[to as usize] = piece;
+    pos.pieces[from as usize] = Piece::Empty;
+    pos.bitboards[piece as usize] ^= change;
+    pos.bitboards[0] ^= change;
+    pos.placement[!is_white(piece) as usize] ^= change;
+    pos
+}
+
+/// The indices of `table` from which a turn reaches `pos`, which starts a
+/// turn with the table's material.
+///
+/// Steps are taken back one at a time, keeping the board free of captures
+/// since the material is unchanged. Only a pending push or a possible pull
+/// makes a step depend on the step before it, so the steps generated without
+/// a previous step are checked first, and those generated after the step
+/// taken back only when it vacated the square the following step enters.
+fn predecessors(table: &Tablebase, pos: &Position) -> Vec<usize> {
+    let side = pos.side.opposite();
+    let end = Position::from_pieces(side, 4, pos.pieces);
+    let mut found = Vec::new();
+    let mut found_boards = ZobristSet::default();
+    let mut seen = ZobristSet::with_capacity_and_hasher(1024, Default::default());
+    // A board with the number of steps taken back and the step played from it
+    let mut stack: Vec<(Position, usize, Option<Step>)> = vec![(end, 0, None)];
+    while let Some((after, taken, next)) = stack.pop() {
+        let next_free = next.is_some_and(|s| after.gen_steps().contains(&s));
+        let occupied = after.placement[0] | after.placement[1];
+        for dest in PieceIter::new(occupied).map(|lsb| lsb.bitscan_forward() as u8) {
+            let piece = after.pieces[dest as usize];
+            let pushed = is_white(piece) != (side == Side::White);
+            let empty = neighbors_of(1u64 << dest) & after.bitboards[0];
+            for source in PieceIter::new(empty).map(|lsb| lsb.bitscan_forward() as u8) {
+                let hash = update_hash(after.current_hash, Step::Move(piece, dest, source));
+                let mut before: Option<Position> = None;
+                let mut first_steps = None;
+                let kinds = [
+                    Step::Move(piece, source, dest),
+                    Step::Push(piece, source, dest),
+                ];
+                for step in kinds[..1 + pushed as usize].iter().cloned() {
+                    let is_push = matches!(step, Step::Push(_, _, _));
+                    let follows = match next {
+                        // The turn ends with a pass or its fourth step
+                        None => !is_push,
+                        Some(_) if next_free && !is_push => true,
+                        Some(next @ Step::Move(_, _, next_dest)) if next_dest == source => {
+                            let mut during = after.clone();
+                            during.steps_left = 2;
+                            during.last_step = Some(step);
+                            during.gen_steps().contains(&next)
+                        }
+                        Some(_) => false,
+                    };
+                    if !follows || !seen.insert(node_key(hash, taken + 1, step)) {
+                        continue;
+                    }
+                    let before = before.get_or_insert_with(|| moved(&after, dest, source));
+                    if !traps_protected(before) {
+                        break;
+                    }
+                    // Only the side to move starts a step
+                    let first = (!pushed || is_push)
+                        && first_steps
+                            .get_or_insert_with(|| before.gen_steps())
+                            .contains(&step);
+                    if first && before.pieces != pos.pieces && found_boards.insert(hash) {
+                        found.extend(table.index(before));
+                    }
+                    if taken + 1 < 4 && (first || may_complete(before, piece, dest)) {
+                        stack.push((before.clone(), taken + 1, Some(step)));
+                    }
+                }
+            }
+        }
+    }
+    found
+}
+
+fn strength(piece: Piece) -> u8 {
+    (piece as u8 - 1) % 6
+}
+
+/// Whether a step of `piece` into `dest` could complete a push or pull in
+/// `pos`, needing a weaker enemy next to `dest` which the mover pushed from
+/// there, or for an enemy piece a stronger piece of the side to move which
+/// pulled it
+fn may_complete(pos: &Position, piece: Piece, dest: u8) -> bool {
+    let pushing = is_white(piece) == (pos.side == Side::White);
+    let enemies = neighbors_of(1u64 << dest) & pos.placement[is_white(piece) as usize];
+    PieceIter::new(enemies).any(|lsb| {
+        let other = strength(pos.pieces[lsb.bitscan_forward()]);
+        other != strength(piece) && (other < strength(piece)) == pushing
+    })
+}
+
+/// A key for a board reached by taking back steps, the number taken back and
+/// the step played from the board, mixed into the board's hash
+fn node_key(hash: u64, taken: usize, step: Step) -> u64 {
+    let (push, source, dest) = match step {
+        Step::Move(_, source, dest) => (0, source, dest),
+        Step::Push(_, source, dest) => (1, source, dest),
+        _ => unreachable!(),
+    };
+    let node = (taken as u64) << 16 | push << 12 | u64::from(source) << 6 | u64::from(dest);
+    hash ^ node.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+}
+
+/// Tablebases for a set of material configurations, generated by retrograde
+/// analysis from the positions where a turn wins at once. Every table with
+/// less material that captures can lead to is generated along with it.
+///
+/// Tables are stored in a directory, one file per material named such as
+/// `ER-er.tb`, holding a four byte magic number and a version byte, the
+/// number of pieces and each piece's index (u8 each), and then a byte for
+/// each position index: 0 if invalid, 1 for a draw, `2n` for a win in `n`
+/// turns and `2n + 3` for a loss in `n` turns.
+#[derive(Default)]
+pub struct Tablebases {
+    tables: HashMap<Material, Tablebase>,
+}
+
+fn file_name(material: &Material) -> String {
+    let pieces = material.pieces();
+    let letters = |white: bool| -> String {
+        pieces
+            .iter()
+            .rev()
+            .filter(|p| (1..=6).contains(&(**p as u8)) == white)
+            .map(|p| char::from(*p))
+            .collect()
+    };
+    format!("{}-{}.tb", letters(true), letters(false))
+}
+
+impl Tablebases {
+    pub fn new() -> Tablebases {
+        Tablebases::default()
+    }
+    /// The materials with a table, in no particular order
+    pub fn materials(&self) -> Vec<Material> {
+        self.tables.keys().cloned().collect()
+    }
+    pub fn table(&self, material: &Material) -> Option<&Tablebase> {
+        self.tables.get(material)
+    }
+    /// The exact result of a position at the start of a turn, if its material
+    /// has a table. The history of the position is ignored.
+    pub fn probe(&self, pos: &Position) -> Option<Outcome> {
+        if pos.steps_left != 4 || pos.last_step.is_some() {
+            return None;
+        }
+        let table = self.tables.get(&Material::new(pos))?;
+        table.value(table.index(pos)?)
+    }
+    /// The turn to play from a position in the tables: the fastest win, the
+    /// longest defence, or any turn keeping a draw
+    pub fn best_turn(&self, pos: &Position) -> Option<Move> {
+        self.probe(pos)?;
+        let mut best: Option<(i64, Move)> = None;
+        for (mut mov, next) in Move::all_turns(pos).into_iter() {
+            // Higher scores are better for the side to move
+            let score = if next.side == pos.side {
+                i64::MAX
+            } else {
+                match self.probe(&next) {
+                    Some(Outcome::Loss(n)) => i64::MAX - 1 - i64::from(n),
+                    Some(Outcome::Draw) => 0,
+                    Some(Outcome::Win(n)) => i64::from(n) - i64::MAX / 2,
+                    None => continue,
+                }
+            };
+            if best.as_ref().is_none_or(|(s, _)| score > *s) {
+                mov.steps.retain(|s| *s != Step::Pass);
+                best = Some((score, mov));
+            }
+        }
+        best.map(|(_, mov)| mov)
+    }
+    /// Generates the table for `material` and every table with less material
+    /// reachable from it by captures. Both sides need a rabbit, as a game
+    /// without one is already decided.
+    ///
+    /// The turns of each position are generated once to count its successors
+    /// in the table. Results are then worked back from decided positions to
+    /// the positions with a turn reaching them, so that only a few bytes are
+    /// kept per index.
+    pub fn generate(&mut self, material: Material) -> Result<(), Error> {
+        if self.tables.contains_key(&material) {
+            return Ok(());
+        }
+        let pieces = material.pieces();
+        ensure!(
+            material.count(Piece::WRabbit) > 0 && material.count(Piece::BRabbit) > 0,
+            format_err!(
+                "Tablebase material {} needs a rabbit for each side",
+                material
+            )
+        );
+        ensure!(
+            pieces.len() <= MAX_TABLEBASE_PIECES,
+            format_err!("Tablebases are limited to {} pieces", MAX_TABLEBASE_PIECES)
+        );
+        for piece in pieces.iter() {
+            let less = material.without(*piece).unwrap();
+            if less.count(Piece::WRabbit) > 0 && less.count(Piece::BRabbit) > 0 {
+                self.generate(less)?;
+            }
+        }
+        let mut table = Tablebase {
+            material,
+            pieces: pieces.clone(),
+            values: vec![INVALID; 2 * 64usize.pow(pieces.len() as u32)],
+        };
+        let len = table.values.len();
+        // The successors in this table not yet known to win, and the longest
+        // known win among the successors with less material or CANNOT_LOSE
+        let mut remaining = vec![0u16; len];
+        let mut longest_known = vec![0u8; len];
+        // Positions to decide at each distance, unless already decided
+        let mut wins = vec![Vec::new(); MAX_DISTANCE as usize + 2];
+        let mut losses = vec![Vec::new(); MAX_DISTANCE as usize + 2];
+        for index in 0..len {
+            let pos = match table.position(index) {
+                Some(pos) => pos,
+                None => continue,
+            };
+            table.values[index] = DRAW;
+            let (won, ends) = turn_ends(&pos);
+            if won {
+                wins[1].push(index);
+                longest_known[index] = CANNOT_LOSE;
+                continue;
+            }
+            let mut successors = HashSet::new();
+            for next in ends.iter() {
+                if let Some(i) = table.index(next) {
+                    successors.insert(i);
+                    continue;
+                }
+                match self.probe(next).unwrap_or(Outcome::Draw) {
+                    Outcome::Win(n) if longest_known[index] != CANNOT_LOSE => {
+                        longest_known[index] = longest_known[index].max(n as u8);
+                    }
+                    Outcome::Loss(n) => {
+                        wins[n as usize + 1].push(index);
+                        longest_known[index] = CANNOT_LOSE;
+                    }
+                    _ => longest_known[index] = CANNOT_LOSE,
+                }
+            }
+            remaining[index] = successors.len() as u16;
+            if successors.is_empty() && longest_known[index] != CANNOT_LOSE {
+                losses[longest_known[index] as usize].push(index);
+            }
+        }
+        // Decided positions are worked back to the positions with a turn
+        // reaching them. Wins in n turns reach a loss in n - 1, while losses
+        // in n are found once every turn reaches a win in at most n.
+        for n in 0..=MAX_DISTANCE as usize {
+            for index in std::mem::take(&mut wins[n]).into_iter() {
+                if table.values[index] != DRAW {
+                    continue;
+                }
+                table.values[index] = encode(Outcome::Win(n as u32));
+                let pos = table.position(index).unwrap();
+                for p in predecessors(&table, &pos).into_iter() {
+                    if table.values[p] != DRAW || longest_known[p] == CANNOT_LOSE {
+                        continue;
+                    }
+                    remaining[p] -= 1;
+                    if remaining[p] == 0 {
+                        losses[n.max(longest_known[p] as usize)].push(p);
+                    }
+                }
+            }
+            for index in std::mem::take(&mut losses[n]).into_iter() {
+                if table.values[index] != DRAW {
+                    continue;
+                }
+                table.values[index] = encode(Outcome::Loss(n as u32));
+                let pos = table.position(index).unwrap();
+                for p in predecessors(&table, &pos).into_iter() {
+                    if table.values[p] == DRAW {
+                        wins[n + 1].push(p);
+                        longest_known[p] = CANNOT_LOSE;
+                    }
+                }
+            }
+        }
+        self.tables.insert(material, table);
+        Ok(())
+    }
+    /// Writes every table to `dir`, creating it if needed
+    pub fn save<P: AsRef<Path>>(&self, dir: P) -> Result<(), Error> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir)?;
+        for (material, table) in self.tables.iter() {
+            let mut bytes = MAGIC.to_vec();
+            bytes.push(VERSION);
+            bytes.push(table.pieces.len() as u8);
+            bytes.extend(table.pieces.iter().map(|p| *p as u8));
+            bytes.extend_from_slice(&table.values);
+            fs::write(dir.join(file_name(material)), bytes)?;
+        }
+        Ok(())
+    }
+    /// Reads every table in `dir`
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Tablebases, Error> {
+        let mut tablebases = Tablebases::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|e| e != "tb") {
+                continue;
+            }
+            let table = read_table(&fs::read(&path)?)
+                .map_err(|e| format_err!("{}: {}", path.display(), e))?;
+            tablebases.tables.insert(table.material, table);
+        }
+        Ok(tablebases)
+    }
+}
+
+fn read_table(bytes: &[u8]) -> Result<Tablebase, Error> {
+    ensure!(
+        bytes.len() >= 6 && &bytes[..4] == MAGIC,
+        format_err!("Not a tablebase")
+    );
+    ensure!(
+        bytes[4] == VERSION,
+        format_err!("Unsupported tablebase version {}", bytes[4])
+    );
+    let num_pieces = bytes[5] as usize;
+    ensure!(
+        num_pieces <= MAX_TABLEBASE_PIECES && bytes.len() >= 6 + num_pieces,
+        format_err!("Invalid tablebase header")
+    );
+    let mut pieces = Vec::with_capacity(num_pieces);
+    let mut board = [Piece::Empty; 64];
+    for (sq, pix) in bytes[6..6 + num_pieces].iter().enumerate() {
+        match Piece::from_u8(*pix) {
+            Some(Piece::Empty) | None => bail!("Invalid piece {}", pix),
+            Some(piece) => {
+                pieces.push(piece);
+                board[sq] = piece;
+            }
+        }
+    }
+    ensure!(
+        pieces.windows(2).all(|w| (w[0] as u8) <= (w[1] as u8)),
+        format_err!("Tablebase pieces out of order")
+    );
+    let values = bytes[6 + num_pieces..].to_vec();
+    ensure!(
+        values.len() == 2 * 64usize.pow(num_pieces as u32),
+        format_err!("Tablebase has the wrong length")
+    );
+    let material = Material::new(&Position::from_pieces(Side::White, 4, board));
+    Ok(Tablebase {
+        material,
+        pieces,
+        values,
+    })
+}
@@ -0,0 +1,418 @@
+use crate::clock::{Clock, GameLimit, TimeAllocator, TimeControl};
+use crate::game::{Game, Move};
+use crate::position::{EndState, Piece, Position, Side, Step};
+use crate::search::{self, SearchConfig};
+use crate::selfplay::random_setup;
+use failure::{bail, ensure, format_err, Error};
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Extra time an AEI engine is given to answer before it is forfeited
+const AEI_GRACE: Duration = Duration::from_secs(5);
+/// How long an AEI engine may take to start up or confirm it is ready
+const AEI_STARTUP: Duration = Duration::from_secs(30);
+
+/// One side of a match
+pub trait Player {
+    fn name(&self) -> String;
+    /// Called before each game
+    fn start_game(&mut self, _tc: &TimeControl) -> Result<(), Error> {
+        Ok(())
+    }
+    /// Chooses a turn for the side to move in `game`, given both clocks
+    /// indexed by side. Returning no steps resigns the game.
+    fn play_turn(
+        &mut self,
+        game: &Game,
+        tc: &TimeControl,
+        clocks: &[Clock; 2],
+    ) -> Result<Move, Error>;
+}
+
+/// The engine of this crate, searching in the same process
+pub struct EnginePlayer {
+    pub name: String,
+    pub config: SearchConfig,
+    pub allocator: TimeAllocator,
+}
+
+impl EnginePlayer {
+    pub fn new(name: &str, config: SearchConfig) -> EnginePlayer {
+        EnginePlayer {
+            name: name.to_string(),
+            config,
+            allocator: TimeAllocator::default(),
+        }
+    }
+}
+
+impl Player for EnginePlayer {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn play_turn(
+        &mut self,
+        game: &Game,
+        tc: &TimeControl,
+        clocks: &[Clock; 2],
+    ) -> Result<Move, Error> {
+        let pos = &game.position;
+        let config = self
+            .allocator
+            .configure(&self.config, tc, &clocks[pos.side as usize], pos);
+        Ok(search::choose_turn(pos, &config))
+    }
+}
+
+/// An engine run as a subprocess speaking the Arimaa Engine Interface. The
+/// game is sent with `makemove` before each search, and the clocks with the
+/// `wreserve`, `breserve`, `greserve` and `sreserve` options.
+pub struct AeiPlayer {
+    name: String,
+    child: Child,
+    input: ChildStdin,
+    output: Receiver<String>,
+    /// Turns of the current game already sent to the engine
+    sent: usize,
+}
+
+impl AeiPlayer {
+    pub fn spawn(program: &str, args: &[String]) -> Result<AeiPlayer, Error> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format_err!("Could not start {}: {}", program, e))?;
+        let input = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (sender, output) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                match line {
+                    Ok(line) if sender.send(line.trim().to_string()).is_ok() => {}
+                    _ => return,
+                }
+            }
+        });
+        let mut player = AeiPlayer {
+            name: program.to_string(),
+            child,
+            input,
+            output,
+            sent: 0,
+        };
+        player.send("aei")?;
+        player.wait_for("protocol-version", AEI_STARTUP)?;
+        loop {
+            let line = player.next_line(AEI_STARTUP)?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                player.name = name.to_string();
+            } else if line == "aeiok" {
+                break;
+            }
+        }
+        Ok(player)
+    }
+    fn send(&mut self, line: &str) -> Result<(), Error> {
+        writeln!(self.input, "{}", line)?;
+        self.input.flush()?;
+        Ok(())
+    }
+    fn next_line(&mut self, timeout: Duration) -> Result<String, Error> {
+        match self.output.recv_timeout(timeout) {
+            Ok(line) => Ok(line),
+            Err(RecvTimeoutError::Timeout) => bail!("{} did not respond in time", self.name),
+            Err(RecvTimeoutError::Disconnected) => bail!("{} exited", self.name),
+        }
+    }
+    /// Skips lines until one starting with `prefix`, which is returned
+    fn wait_for(&mut self, prefix: &str, timeout: Duration) -> Result<String, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline
+                .checked_duration_since(Instant::now())
+                .unwrap_or_default();
+            let line = self.next_line(left)?;
+            if line.starts_with(prefix) {
+                return Ok(line);
+            }
+        }
+    }
+    fn set_option(&mut self, name: &str, value: u64) -> Result<(), Error> {
+        self.send(&format!("setoption name {} value {}", name, value))
+    }
+}
+
+impl Player for AeiPlayer {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+    fn start_game(&mut self, tc: &TimeControl) -> Result<(), Error> {
+        self.send("newgame")?;
+        self.sent = 0;
+        self.set_option("tcmove", tc.per_move.as_secs())?;
+        self.set_option("tcreserve", tc.reserve.as_secs())?;
+        self.set_option("tcpercent", u64::from(tc.percent_carry))?;
+        self.set_option("tcmax", tc.reserve_limit.map_or(0, |d| d.as_secs()))?;
+        match tc.game_limit {
+            Some(GameLimit::Time(limit)) => self.set_option("tctotal", limit.as_secs())?,
+            Some(GameLimit::Turns(turns)) => self.set_option("tcturns", u64::from(turns))?,
+            None => {}
+        }
+        self.set_option("tcturntime", tc.max_turn.map_or(0, |d| d.as_secs()))?;
+        self.send("isready")?;
+        self.wait_for("readyok", AEI_STARTUP)?;
+        Ok(())
+    }
+    fn play_turn(
+        &mut self,
+        game: &Game,
+        tc: &TimeControl,
+        clocks: &[Clock; 2],
+    ) -> Result<Move, Error> {
+        for mov in game.moves[self.sent..].iter() {
+            self.send(&format!("makemove {}", mov))?;
+        }
+        self.sent = game.moves.len();
+        for (side, names) in [(0, ["wreserve", "greserve"]), (1, ["breserve", "sreserve"])].iter() {
+            for name in names.iter() {
+                self.set_option(name, clocks[*side].reserve.as_secs())?;
+            }
+        }
+        self.send("go")?;
+        let available = clocks[game.position.side as usize].available(tc);
+        let line = self.wait_for("bestmove", available + AEI_GRACE)?;
+        Move::parse(line["bestmove".len()..].trim())
+    }
+}
+
+impl Drop for AeiPlayer {
+    fn drop(&mut self) {
+        if self.send("quit").is_err() || self.wait_for("bye", AEI_GRACE).is_err() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+#[derive(Clone)]
+pub struct MatchConfig {
+    /// Games played in total. Each setup is played twice with the colors
+    /// swapped, so an even number gives both players the same setups.
+    pub games: u32,
+    pub time_control: TimeControl,
+    /// Setups of White and Black to start games from, used in turn. Random
+    /// setups drawn with `seed` are used if this is empty.
+    pub setups: Vec<(Move, Move)>,
+    pub seed: u64,
+    /// Turns after which an unfinished game counts as a draw
+    pub max_turns: u32,
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        MatchConfig {
+            games: 2,
+            time_control: "0:10/1/100".parse().unwrap(),
+            setups: Vec::new(),
+            seed: 0,
+            max_turns: 200,
+        }
+    }
+}
+
+/// A finished game and why it ended
+pub struct MatchGame {
+    pub game: Game,
+    pub reason: String,
+}
+
+/// Results from the first player's point of view
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MatchStats {
+    pub wins: u32,
+    pub losses: u32,
+    pub draws: u32,
+}
+
+impl MatchStats {
+    pub fn games(&self) -> u32 {
+        self.wins + self.losses + self.draws
+    }
+    /// The fraction of points scored, counting draws as half a point
+    pub fn score(&self) -> f64 {
+        (f64::from(self.wins) + f64::from(self.draws) / 2.0) / f64::from(self.games())
+    }
+    /// The Elo difference implied by the score along with the bounds of its
+    /// 95% confidence interval, or `None` before any games are played. The
+    /// interval is clamped to scores between 0 and 1, so a bound, like the
+    /// difference for a perfect score, may be infinite.
+    pub fn elo(&self) -> Option<(f64, f64, f64)> {
+        if self.games() == 0 {
+            return None;
+        }
+        let n = f64::from(self.games());
+        let p = self.score();
+        let variance = (f64::from(self.wins) * (1.0 - p).powi(2)
+            + f64::from(self.losses) * p.powi(2)
+            + f64::from(self.draws) * (0.5 - p).powi(2))
+            / n;
+        let margin = 1.96 * (variance / n).sqrt();
+        let elo = |p: f64| {
+            if p <= 0.0 {
+                f64::NEG_INFINITY
+            } else if p >= 1.0 {
+                f64::INFINITY
+            } else {
+                400.0 * (p / (1.0 - p)).log10()
+            }
+        };
+        Some((elo(p), elo(p - margin), elo(p + margin)))
+    }
+}
+
+/// The `Place` steps of each side's pieces on their home ranks
+pub fn setup_moves(pos: &Position) -> (Move, Move) {
+    let place = |squares: std::ops::Range<usize>| {
+        let steps = squares
+            .filter(|sq| pos.pieces[*sq] != Piece::Empty)
+            .map(|sq| Step::Place(pos.pieces[sq], sq as u8))
+            .collect();
+        Move::new(steps)
+    };
+    (place(0..16), place(48..64))
+}
+
+/// Plays a game between `white` and `black` from the given setups, charging
+/// each turn to the mover's clock. A player who overruns their time, fails
+/// or plays an illegal turn loses.
+pub fn play_game(
+    setup: &(Move, Move),
+    white: &mut dyn Player,
+    black: &mut dyn Player,
+    config: &MatchConfig,
+) -> Result<MatchGame, Error> {
+    let tc = &config.time_control;
+    let mut game = Game::new(setup.0.clone(), setup.1.clone())?;
+    white.start_game(tc)?;
+    black.start_game(tc)?;
+    let mut clocks = [tc.start_clock(), tc.start_clock()];
+    let mut turns = 0;
+    let reason = loop {
+        if game.result != EndState::Neither {
+            break "finished".to_string();
+        }
+        let limit = match tc.game_limit {
+            Some(GameLimit::Turns(limit)) => limit.min(config.max_turns),
+            _ => config.max_turns,
+        };
+        if turns >= limit {
+            break "turn limit".to_string();
+        }
+        if let Some(GameLimit::Time(limit)) = tc.game_limit {
+            if clocks[0].game_elapsed >= limit {
+                break "game time limit".to_string();
+            }
+        }
+        let side = game.position.side;
+        let player: &mut dyn Player = match side {
+            Side::White => &mut *white,
+            Side::Black => &mut *black,
+        };
+        let start = Instant::now();
+        let chosen = player.play_turn(&game, tc, &clocks);
+        let used = start.elapsed();
+        let loss = EndState::from(side.opposite());
+        let in_time = clocks[side as usize].update(tc, used);
+        clocks[side.opposite() as usize].game_elapsed += used;
+        let mov = match chosen {
+            Ok(mov) => mov,
+            Err(e) => {
+                game.result = loss;
+                break format!("{} failed: {}", player.name(), e);
+            }
+        };
+        if !in_time {
+            game.result = loss;
+            break format!("{} lost on time", player.name());
+        }
+        if mov.steps.is_empty() {
+            game.result = loss;
+            break format!("{} resigned", player.name());
+        }
+        if let Err(e) = game.play(&mov) {
+            game.result = loss;
+            break format!("{} played an illegal turn: {}", player.name(), e);
+        }
+        turns += 1;
+    };
+    Ok(MatchGame { game, reason })
+}
+
+/// Plays a match, alternating colors between games, and writes every game to
+/// `out` as a record tagged with the players and the result
+pub fn run_match<W: Write>(
+    config: &MatchConfig,
+    first: &mut dyn Player,
+    second: &mut dyn Player,
+    mut out: W,
+) -> Result<MatchStats, Error> {
+    ensure!(
+        config.games > 0,
+        format_err!("A match needs at least one game")
+    );
+    let mut rng = SmallRng::seed_from_u64(config.seed);
+    let mut random_setups = Vec::new();
+    let mut stats = MatchStats::default();
+    for number in 0..config.games {
+        let pair = (number / 2) as usize;
+        let setup = if config.setups.is_empty() {
+            if random_setups.len() <= pair {
+                random_setups.push(setup_moves(&random_setup(&mut rng)));
+            }
+            random_setups[pair].clone()
+        } else {
+            config.setups[pair % config.setups.len()].clone()
+        };
+        let first_white = number % 2 == 0;
+        let played = if first_white {
+            play_game(&setup, first, second, config)?
+        } else {
+            play_game(&setup, second, first, config)?
+        };
+        let (white, black) = if first_white {
+            (first.name(), second.name())
+        } else {
+            (second.name(), first.name())
+        };
+        let result = match played.game.result {
+            EndState::WhiteWin => "w",
+            EndState::BlackWin => "b",
+            EndState::Neither => "-",
+        };
+        match (&played.game.result, first_white) {
+            (EndState::Neither, _) => stats.draws += 1,
+            (EndState::WhiteWin, true) | (EndState::BlackWin, false) => stats.wins += 1,
+            _ => stats.losses += 1,
+        }
+        let mut game = played.game;
+        game.tags = vec![
+            ("game".to_string(), (number + 1).to_string()),
+            ("white".to_string(), white),
+            ("black".to_string(), black),
+            (
+                "result".to_string(),
+                format!("{} {}", result, played.reason),
+            ),
+        ];
+        writeln!(out, "{}\n", game.to_record())?;
+        out.flush()?;
+    }
+    Ok(stats)
+}
@@ -0,0 +1,219 @@
+use crate::position::{Piece, Position, Step};
+use num_traits::FromPrimitive;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+const BUCKET_SIZE: usize = 4;
+const GENERATION_MASK: u8 = 0x3F;
+
+/// How the stored score relates to the true value of the position
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Bound {
+    Exact = 0,
+    Lower = 1,
+    Upper = 2,
+}
+
+/// A proven win for the side to move scores `WIN_SCORE` less the number of
+/// steps to the end of the game, and a proven loss the negation
+pub const WIN_SCORE: i32 = 1_000_000;
+/// Scores at least this large in magnitude are proven wins or losses, while
+/// smaller scores are evaluations in material units
+pub const MIN_WIN_SCORE: i32 = WIN_SCORE - 10_000;
+
+/// A decoded table entry. Scores are from the perspective of the side to move.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TableEntry {
+    pub depth: u8,
+    pub bound: Bound,
+    pub score: i32,
+    pub best_step: Option<Step>,
+}
+
+/// A slot stores the key xored with the data so that a torn write from two
+/// threads racing on the same slot fails verification instead of being read
+/// back as a corrupt entry.
+#[derive(Default)]
+struct Slot {
+    check: AtomicU64,
+    data: AtomicU64,
+}
+
+#[derive(Default)]
+struct Bucket {
+    slots: [Slot; BUCKET_SIZE],
+}
+
+/// A lock-free hash table keyed by `Position::current_hash`, shared between
+/// search threads. Each bucket holds four entries; a store replaces the entry
+/// for the same position if present, and otherwise the entry with the least
+/// depth, counting entries from older searches as shallower.
+pub struct TranspositionTable {
+    buckets: Vec<Bucket>,
+    generation: AtomicU8,
+}
+
+impl TranspositionTable {
+    /// Creates a table using at most `megabytes` of memory
+    pub fn new(megabytes: usize) -> TranspositionTable {
+        let bytes = megabytes.max(1) * 1024 * 1024;
+        Self::with_buckets(bytes / std::mem::size_of::<Bucket>())
+    }
+    /// Creates a table with the given number of buckets, rounded down to a
+    /// power of two
+    pub fn with_buckets(num_buckets: usize) -> TranspositionTable {
+        let num_buckets = num_buckets.max(1);
+        let num_buckets = 1 << (63 - (num_buckets as u64).leading_zeros());
+        let mut buckets = Vec::with_capacity(num_buckets);
+        buckets.resize_with(num_buckets, Bucket::default);
+        TranspositionTable {
+            buckets,
+            generation: AtomicU8::new(0),
+        }
+    }
+    /// The number of entries the table can hold
+    pub fn capacity(&self) -> usize {
+        self.buckets.len() * BUCKET_SIZE
+    }
+    /// Ages all current entries so they are replaced in preference to entries
+    /// from the new search
+    pub fn new_search(&self) {
+        let next = (self.generation.load(Ordering::Relaxed) + 1) & GENERATION_MASK;
+        self.generation.store(next, Ordering::Relaxed);
+    }
+    pub fn clear(&mut self) {
+        for bucket in self.buckets.iter_mut() {
+            *bucket = Bucket::default();
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+    pub fn probe(&self, pos: &Position) -> Option<TableEntry> {
+        let hash = pos.current_hash;
+        for slot in self.bucket(hash).slots.iter() {
+            let data = slot.data.load(Ordering::Relaxed);
+            if data != 0 && (slot.check.load(Ordering::Relaxed) ^ data) == hash {
+                return Some(unpack(data, &pos.pieces));
+            }
+        }
+        None
+    }
+    pub fn store(
+        &self,
+        pos: &Position,
+        depth: u8,
+        bound: Bound,
+        score: i32,
+        best_step: Option<Step>,
+    ) {
+        let hash = pos.current_hash;
+        let generation = self.generation.load(Ordering::Relaxed);
+        let bucket = self.bucket(hash);
+        let mut victim = &bucket.slots[0];
+        let mut victim_value = i32::MAX;
+        let mut best_step = best_step;
+        for slot in bucket.slots.iter() {
+            let data = slot.data.load(Ordering::Relaxed);
+            if data == 0 {
+                if victim_value > i32::MIN {
+                    victim = slot;
+                    victim_value = i32::MIN;
+                }
+                continue;
+            }
+            if (slot.check.load(Ordering::Relaxed) ^ data) == hash {
+                // Keep the previous best step rather than forgetting it
+                if best_step.is_none() {
+                    best_step = unpack(data, &pos.pieces).best_step;
+                }
+                victim = slot;
+                break;
+            }
+            let age = generation.wrapping_sub(unpacked_generation(data)) & GENERATION_MASK;
+            let value = unpacked_depth(data) as i32 - 4 * age as i32;
+            if value < victim_value {
+                victim = slot;
+                victim_value = value;
+            }
+        }
+        let data = pack(depth, bound, score, generation, best_step);
+        victim.check.store(hash ^ data, Ordering::Relaxed);
+        victim.data.store(data, Ordering::Relaxed);
+    }
+    /// The fraction of sampled slots in use by the current search, per mille
+    pub fn hashfull(&self) -> usize {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let sample = self.buckets.len().min(250);
+        let used: usize = self.buckets[..sample]
+            .iter()
+            .flat_map(|b| b.slots.iter())
+            .map(|s| s.data.load(Ordering::Relaxed))
+            .filter(|data| *data != 0 && unpacked_generation(*data) == generation)
+            .count();
+        used * 1000 / (sample * BUCKET_SIZE)
+    }
+    fn bucket(&self, hash: u64) -> &Bucket {
+        &self.buckets[hash as usize & (self.buckets.len() - 1)]
+    }
+}
+
+// Data layout: score in bits 0-31, depth 32-39, bound 40-41, generation 42-47
+// and the best step in 48-63. Unused bound value 3 marks the slot as occupied
+// so that no valid entry packs to zero.
+fn pack(depth: u8, bound: Bound, score: i32, generation: u8, best_step: Option<Step>) -> u64 {
+    let data = (score as u32 as u64)
+        | (depth as u64) << 32
+        | (bound as u64) << 40
+        | ((generation & GENERATION_MASK) as u64) << 42
+        | (pack_step(best_step) as u64) << 48;
+    if data == 0 {
+        3 << 40
+    } else {
+        data
+    }
+}
+
+fn unpack(data: u64, pieces: &[Piece; 64]) -> TableEntry {
+    let bound = match (data >> 40) & 3 {
+        1 => Bound::Lower,
+        2 => Bound::Upper,
+        _ => Bound::Exact,
+    };
+    TableEntry {
+        depth: unpacked_depth(data),
+        bound,
+        score: data as u32 as i32,
+        best_step: unpack_step((data >> 48) as u16, pieces),
+    }
+}
+
+fn unpacked_depth(data: u64) -> u8 {
+    (data >> 32) as u8
+}
+
+fn unpacked_generation(data: u64) -> u8 {
+    (data >> 42) as u8 & GENERATION_MASK
+}
+
+/// Steps are stored as a 3 bit kind and two squares, recovering the moving
+/// piece from the board. Placements store the piece in place of the second square.
+fn pack_step(step: Option<Step>) -> u16 {
+    let (kind, first, second) = match step {
+        None | Some(Step::Remove(_, _)) => return 0,
+        Some(Step::Move(_, source, dest)) => (1, source, dest),
+        Some(Step::Push(_, source, dest)) => (2, source, dest),
+        Some(Step::Place(p, sq)) => (3, sq, p as u8),
+        Some(Step::Pass) => (4, 0, 0),
+    };
+    kind << 12 | (first as u16) << 6 | second as u16
+}
+
+fn unpack_step(bits: u16, pieces: &[Piece; 64]) -> Option<Step> {
+    let first = ((bits >> 6) & 0x3F) as u8;
+    let second = (bits & 0x3F) as u8;
+    match bits >> 12 {
+        1 => Some(Step::Move(pieces[first as usize], first, second)),
+        2 => Some(Step::Push(pieces[first as usize], first, second)),
+        3 => Some(Step::Place(Piece::from_u8(second)?, first)),
+        4 => Some(Step::Pass),
+        _ => None,
+    }
+}
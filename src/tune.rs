@@ -0,0 +1,143 @@
+use crate::eval::{features, LinearEval, NUM_FEATURES};
+use crate::game::Game;
+use crate::position::{EndState, Position, Side};
+use crate::selfplay::GameRecord;
+use failure::Error;
+use std::path::Path;
+
+/// The features of a position along with the result of its game, 1 if the
+/// side to move went on to win and 0 if it lost
+#[derive(Clone, Debug, PartialEq)]
+pub struct LabelledPosition {
+    pub features: [f64; NUM_FEATURES],
+    pub result: f64,
+}
+
+impl LabelledPosition {
+    pub fn new(pos: &Position, result: &EndState) -> Option<LabelledPosition> {
+        let winner = match result {
+            EndState::WhiteWin => Side::White,
+            EndState::BlackWin => Side::Black,
+            EndState::Neither => return None,
+        };
+        Some(LabelledPosition {
+            features: features(pos),
+            result: if pos.side == winner { 1.0 } else { 0.0 },
+        })
+    }
+}
+
+/// The turn-start positions of decided games, skipping the first
+/// `skip_turns` turns of each game where the setups dominate
+pub fn labelled_positions(games: &[Game], skip_turns: usize) -> Vec<LabelledPosition> {
+    games
+        .iter()
+        .flat_map(|game| {
+            game.positions()
+                .into_iter()
+                .skip(skip_turns)
+                .filter_map(move |pos| LabelledPosition::new(&pos, &game.result))
+        })
+        .collect()
+}
+
+/// Every searched position of decided self-play games
+pub fn selfplay_positions(games: &[GameRecord]) -> Vec<LabelledPosition> {
+    games
+        .iter()
+        .flat_map(|game| {
+            game.positions
+                .iter()
+                .filter_map(move |record| LabelledPosition::new(&record.position, &game.result))
+        })
+        .collect()
+}
+
+/// The predicted chance the side to move wins, from the evaluation in -1..1
+fn win_probability(eval: &LinearEval, features: &[f64; NUM_FEATURES]) -> f64 {
+    (1.0 + dot(&eval.weights, features).tanh()) / 2.0
+}
+
+fn dot(weights: &[f64; NUM_FEATURES], features: &[f64; NUM_FEATURES]) -> f64 {
+    weights
+        .iter()
+        .zip(features.iter())
+        .map(|(w, f)| w * f)
+        .sum()
+}
+
+/// The mean squared error between predicted win probabilities and results
+pub fn loss(eval: &LinearEval, data: &[LabelledPosition]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let total: f64 = data
+        .iter()
+        .map(|d| (win_probability(eval, &d.features) - d.result).powi(2))
+        .sum();
+    total / data.len() as f64
+}
+
+/// Fits the weights of a `LinearEval` to game results by full-batch gradient
+/// descent on `loss`. A step which fails to reduce the loss is discarded and
+/// the learning rate halved.
+#[derive(Clone, Copy, Debug)]
+pub struct Tuner {
+    pub learning_rate: f64,
+    pub iterations: usize,
+}
+
+impl Default for Tuner {
+    fn default() -> Self {
+        Tuner {
+            learning_rate: 0.01,
+            iterations: 500,
+        }
+    }
+}
+
+impl Tuner {
+    pub fn tune(&self, start: &LinearEval, data: &[LabelledPosition]) -> LinearEval {
+        let mut eval = start.clone();
+        let mut best = loss(&eval, data);
+        let mut rate = self.learning_rate;
+        for _ in 0..self.iterations {
+            if data.is_empty() || rate < 1e-12 {
+                break;
+            }
+            let mut gradient = [0.0; NUM_FEATURES];
+            for d in data.iter() {
+                let squashed = dot(&eval.weights, &d.features).tanh();
+                let error = (1.0 + squashed) / 2.0 - d.result;
+                let scale = error * (1.0 - squashed * squashed);
+                for (g, f) in gradient.iter_mut().zip(d.features.iter()) {
+                    *g += scale * f;
+                }
+            }
+            let mut candidate = eval.clone();
+            for (w, g) in candidate.weights.iter_mut().zip(gradient.iter()) {
+                *w -= rate * g / data.len() as f64;
+            }
+            let candidate_loss = loss(&candidate, data);
+            if candidate_loss < best {
+                eval = candidate;
+                best = candidate_loss;
+            } else {
+                rate /= 2.0;
+            }
+        }
+        eval
+    }
+    /// Tunes the weights and saves them in the format read by
+    /// `LinearEval::load`
+    pub fn tune_to_file<P: AsRef<Path>>(
+        &self,
+        start: &LinearEval,
+        data: &[LabelledPosition],
+        path: P,
+    ) -> Result<LinearEval, Error> {
+        let eval = self.tune(start, data);
+        eval.save(path)?;
+        Ok(eval)
+    }
+}
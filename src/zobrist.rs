@@ -1,19 +1,21 @@
-
-use rand::rngs::SmallRng;
-use rand::RngCore;
-use rand::SeedableRng;
-use std::fs::File;
-use std::io::Write;
-
 use crate::position::{Piece, Side, Step};
 
 const NUM_PIECES: usize = 12; // Empty is not counted
 const NUM_SQUARES: usize = 64;
 
-include!("table_zobrist.rs");
+// Generated by build.rs. Set ARIMAA_ZOBRIST_SEED at build time to use an
+// alternate key set, e.g. when testing the effect of hash collisions.
+include!(concat!(env!("OUT_DIR"), "/table_zobrist.rs"));
 
-pub fn compute_hash(board: &[Piece; 64], side: Side) -> u64 {
-    let mut hash = 0;
+/// Hashes a position from scratch, including the steps remaining in the turn
+/// and any follow-up step the previous step makes available.
+pub fn compute_hash(
+    board: &[Piece; 64],
+    side: Side,
+    steps_left: u8,
+    last_step: Option<Step>,
+) -> u64 {
+    let mut hash = step_state_hash(side, steps_left, last_step);
     for (square, p) in board.iter().enumerate() {
         let pix = *p as usize;
         if pix != 0 {
@@ -42,6 +44,31 @@ pub fn update_hash(mut hash: u64, step: Step) -> u64 {
     }
     hash
 }
+/// The part of the hash describing progress through the current turn. A push
+/// obliges the mover to fill the vacated square, and a step by one of the
+/// mover's non-rabbit pieces allows a pull into the square it left, so both
+/// are keyed by the piece involved and that square.
+pub fn step_state_hash(side: Side, steps_left: u8, last_step: Option<Step>) -> u64 {
+    let mut hash = match steps_left {
+        1..=4 => STEPS_LEFT[steps_left as usize - 1],
+        _ => 0,
+    };
+    match last_step {
+        Some(Step::Push(p, source, _)) => {
+            hash ^= PENDING_STEP[p as usize - 1][source as usize];
+        }
+        Some(Step::Move(p, source, _)) => {
+            let pix = p as usize;
+            let own_piece = (pix <= 6) == (side == Side::White);
+            let is_rabbit = pix == Piece::WRabbit as usize || pix == Piece::BRabbit as usize;
+            if own_piece && !is_rabbit {
+                hash ^= PENDING_STEP[pix - 1][source as usize];
+            }
+        }
+        _ => {}
+    }
+    hash
+}
 pub fn color_hash(color: Side) -> u64 {
     match color {
         Side::White => SIDE_TO_MOVE[0],
@@ -54,42 +81,3 @@ fn get_zobrist(sq: usize, piece: usize) -> u64 {
     // dbg!(piece);
     ZOBRIST_PIECES[piece - 1][sq]
 }
-
-pub fn write_zobrist(f: &mut File) {
-    let mut rng = SmallRng::seed_from_u64(0xDEADBEEF);
-
-    write!(f, "/// Autogenerated by zobrist.rs\n").unwrap();
-
-    write!(
-        f,
-        "const SIDE_TO_MOVE: [u64; 2] = [{}, {}];\n\n",
-        rng.next_u64(),
-        rng.next_u64()
-    )
-    .unwrap();
-
-    write!(
-        f,
-        "const STEPS_LEFT: [u64; 4] = [{}, {}, {}, {}];\n\n",
-        rng.next_u64(),
-        rng.next_u64(),
-        rng.next_u64(),
-        rng.next_u64()
-    )
-    .unwrap();
-
-    write!(
-        f,
-        "const ZOBRIST_PIECES: [[u64; NUM_SQUARES]; NUM_PIECES] = [[\n"
-    )
-    .unwrap();
-    for j in 0..NUM_PIECES {
-        for _ in 0..NUM_SQUARES {
-            write!(f, "    {},\n", rng.next_u64()).unwrap();
-        }
-        if j != NUM_PIECES - 1 {
-            write!(f, "   ], [\n").unwrap();
-        }
-    }
-    write!(f, "]];\n\n").unwrap();
-}
\ No newline at end of file